@@ -0,0 +1,202 @@
+use super::cooktorrance::{ggx_d, ggx_g, pdf_refl, sample_halfvec};
+use Bsdf;
+use color::{self, Color, Rgb};
+use math::{self, Dot, Norm, Real, Vector3f};
+
+/// Complex index of refraction `eta + i k` of a metal, per channel at the
+/// red, green and blue wavelengths.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Metal {
+    pub eta: Rgb<Real>,
+    pub k: Rgb<Real>,
+}
+
+impl Metal {
+    pub fn new(eta: Rgb<Real>, k: Rgb<Real>) -> Metal {
+        Metal { eta, k }
+    }
+
+    /// Reflectance at `cos_i` to the normal.
+    pub fn fresnel(&self, cos_i: Real) -> Rgb<Real> {
+        Rgb::new(
+            fresnel_conductor(cos_i, self.eta.r, self.k.r),
+            fresnel_conductor(cos_i, self.eta.g, self.k.g),
+            fresnel_conductor(cos_i, self.eta.b, self.k.b),
+        )
+    }
+}
+
+pub const GOLD: Metal = Metal {
+    eta: Rgb::new(0.143, 0.374, 1.442),
+    k: Rgb::new(3.983, 2.385, 1.603),
+};
+pub const COPPER: Metal = Metal {
+    eta: Rgb::new(0.200, 0.924, 1.102),
+    k: Rgb::new(3.912, 2.452, 2.142),
+};
+pub const ALUMINIUM: Metal = Metal {
+    eta: Rgb::new(1.657, 0.880, 0.521),
+    k: Rgb::new(9.224, 6.270, 4.837),
+};
+pub const SILVER: Metal = Metal {
+    eta: Rgb::new(0.155, 0.117, 0.138),
+    k: Rgb::new(4.828, 3.122, 2.147),
+};
+pub const CHROME: Metal = Metal {
+    eta: Rgb::new(3.107, 3.181, 2.323),
+    k: Rgb::new(3.331, 3.329, 3.135),
+};
+pub const IRON: Metal = Metal {
+    eta: Rgb::new(2.911, 2.950, 2.585),
+    k: Rgb::new(3.089, 2.932, 2.767),
+};
+
+/// Fresnel reflectance of unpolarized light hitting a conductor of the
+/// complex index `eta + i k` from a dielectric of index 1 at `cos_i`.
+pub fn fresnel_conductor(cos_i: Real, eta: Real, k: Real) -> Real {
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+
+    let t1 = a2b2 + cos2;
+    let t2 = 2.0 * a * cos_i;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rs + rp)
+}
+
+/// Metal surface, a mirror if `alpha` is 0 and a GGX microfacet reflector
+/// otherwise.
+#[derive(Clone, Copy, Debug)]
+pub struct Conductor {
+    pub metal: Metal,
+    pub alpha: Real,
+}
+
+impl Conductor {
+    /// `roughness` is squared to the GGX `alpha` like in `CookTorranceMat`.
+    pub fn new(metal: Metal, roughness: Real) -> Conductor {
+        let r = roughness.max(0.0).min(1.0);
+        Conductor {
+            metal: metal,
+            alpha: r * r,
+        }
+    }
+
+    pub fn smooth(metal: Metal) -> Conductor {
+        Conductor::new(metal, 0.0)
+    }
+
+    fn mirror(&self, normal: &Vector3f, in_dir: &Vector3f) -> (Vector3f, Color) {
+        let cos = -normal.dot(in_dir);
+        let dir = *in_dir + *normal * (2.0 * cos);
+        (dir, self.metal.fresnel(cos.max(0.0)).into())
+    }
+}
+
+impl Bsdf for Conductor {
+    fn radiance(&self) -> Option<Color> {
+        None
+    }
+
+    /// Zero for a mirror, which reflects only into sampled directions.
+    fn eval(
+        &self,
+        surface_normal: &Vector3f,
+        in_dir: &Vector3f,
+        out_dir: &Vector3f,
+    ) -> (Color, Real) {
+        if self.alpha == 0.0 {
+            return (color::BLACK, 0.0);
+        }
+        let view = -in_dir;
+        let cos_nv = surface_normal.dot(&view);
+        let cos_no = surface_normal.dot(out_dir);
+        if cos_nv <= 0.0 || cos_no <= 0.0 {
+            return (color::BLACK, 0.0);
+        }
+
+        let half = (view + *out_dir).normalize();
+        let cos_nh = surface_normal.dot(&half);
+        let cos_oh = half.dot(out_dir);
+        let d = ggx_d(cos_nh, self.alpha);
+        let g = ggx_g(cos_no, cos_nv, cos_oh, cos_oh, self.alpha);
+        let fr = self.metal.fresnel(cos_oh) * (d * g / (4.0 * cos_nv * cos_no));
+        (fr.into(), pdf_refl(cos_nh, cos_oh, self.alpha))
+    }
+
+    /// Samples the GGX distribution of the half vector, directions
+    /// reflected below the surface carry no weight.
+    fn sample(&self, surface_normal: &Vector3f, in_dir: &Vector3f) -> (Vector3f, Color, Real) {
+        if self.alpha == 0.0 {
+            let (dir, f) = self.mirror(surface_normal, in_dir);
+            return (dir, f, surface_normal.dot(&dir).abs());
+        }
+        let view = -in_dir;
+        let half = sample_halfvec(surface_normal, self.alpha);
+        let out_dir = math::reflect_vec(&view, &half);
+        if surface_normal.dot(&out_dir) <= 0.0 || half.dot(&view) <= 0.0 {
+            return (out_dir, color::BLACK, 1.0);
+        }
+        let (fr, pdf) = self.eval(surface_normal, in_dir, &out_dir);
+        (out_dir, fr, pdf)
+    }
+
+    fn sample_proj(&self, surface_normal: &Vector3f, in_dir: &Vector3f) -> (Vector3f, Color, Real) {
+        if self.alpha == 0.0 {
+            let (dir, f) = self.mirror(surface_normal, in_dir);
+            return (dir, f, 1.0);
+        }
+        let (out_dir, fr, pdf) = self.sample(surface_normal, in_dir);
+        let cos = surface_normal.dot(&out_dir);
+        (out_dir, fr, if cos > 0.0 { pdf / cos } else { 1.0 })
+    }
+
+    fn is_specular(&self) -> bool {
+        self.alpha == 0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsdf::dielectric::fresnel_dielectric;
+    use bsdf::testing;
+
+    #[test]
+    fn fresnel_limits() {
+        // ((n - 1)² + k²) / ((n + 1)² + k²) at normal incidence
+        let f0 = fresnel_conductor(1.0, 0.2, 3.9);
+        let expected = (0.64 + 3.9 * 3.9) / (1.44 + 3.9 * 3.9);
+        assert!((f0 - expected).abs() < 1e-9);
+        assert!((fresnel_conductor(0.0, 0.2, 3.9) - 1.0).abs() < 1e-9);
+        // a conductor without absorption is a dielectric
+        let f = fresnel_conductor(0.5, 1.5, 0.0);
+        assert!((f - fresnel_dielectric(0.5, 1.5)).abs() < 1e-9);
+
+        let gold = GOLD.fresnel(1.0);
+        assert!(gold.r > 0.95 && gold.b < 0.4);
+    }
+
+    #[test]
+    fn rough_sample_matches_eval() {
+        let n = Vector3f::new(0.0, 1.0, 0.0);
+        let c = Conductor::new(COPPER, 0.5);
+        assert!(!c.is_specular());
+        for in_dir in &[Vector3f::new(0.6, -0.8, 0.0), Vector3f::new(0.0, -1.0, 0.0)] {
+            testing::assert_sampling_matches_eval(&c, &n, in_dir);
+        }
+        let a = testing::quadrature_albedo(&c, &n, &Vector3f::new(0.6, -0.8, 0.0));
+        assert!(a.r > a.b);
+
+        let mirror = Conductor::smooth(COPPER);
+        let (dir, _, pdf) = mirror.sample_proj(&n, &in_dir);
+        assert!((dir - Vector3f::new(0.6, 0.8, 0.0)).norm() < 1e-9);
+        assert_eq!(pdf, 1.0);
+    }
+}
@@ -2,12 +2,10 @@ use Color;
 use color::Rgb;
 use math;
 use math::{Dot, Norm, Real, Vector3f};
-use rand;
-use rand::Closed01;
-use rand::distributions::{IndependentSample, Range};
 use std::f64::consts::PI;
 use traits::Bsdf;
 use utils::consts;
+use utils::rng;
 
 /// Microfacet distribution function
 pub fn ggx_d(cos_nh: Real, alpha: Real) -> Real {
@@ -95,10 +93,8 @@ pub fn calc_view(light: &Vector3f, half: &Vector3f) -> Vector3f {
 }
 
 pub fn sample_halfvec(normal: &Vector3f, alpha: Real) -> Vector3f {
-    let mut rng = rand::thread_rng();
-    let r01 = Range::new(0.0, 1.0 as Real);
-    let u1 = r01.ind_sample(&mut rng);
-    let u2 = r01.ind_sample(&mut rng);
+    let u1 = rng::uniform();
+    let u2 = rng::uniform();
 
     //let theta = (alpha * u1.sqrt() / (1.0 - u1).sqrt()).atan();
     let theta = ((1.0 - u1) / ((alpha * alpha - 1.0) * u1 + 1.0))
@@ -135,7 +131,7 @@ pub fn sample(
     };
 
     let ks = 0.5;
-    let Closed01(e) = rand::random::<Closed01<Real>>();
+    let e = rng::uniform();
 
     let vec_out = if e <= ks {
         vec_out
@@ -211,4 +207,10 @@ impl Bsdf for CookTorrance {
             pdf,
         )
     }
+    fn eval_diffuse(&self, _: &Vector3f, in_dir: &Vector3f, out_dir: &Vector3f) -> Color {
+        let half = calc_halfvec_refl(in_dir, out_dir);
+        let f = math::fresnel3_schlick_f0(half.dot(&(-in_dir)), &self.f0);
+        let diff = (Rgb::<Real>::from(1.0) - f) * (1.0 / PI) * self.albedo;
+        diff.into()
+    }
 }
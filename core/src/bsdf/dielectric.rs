@@ -0,0 +1,174 @@
+use Bsdf;
+use color::{self, Color};
+use math::{Dot, Norm, Real, Vector3f};
+use utils::rng;
+
+/// Wavelength in nm of the helium d line, `Dielectric` refracts at it
+/// unless traced spectrally.
+pub const D_LINE: Real = 587.6;
+
+/// Index of refraction as a function of the wavelength.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ior {
+    Constant(Real),
+    /// `a + b / λ²` with λ in µm.
+    Cauchy { a: Real, b: Real },
+    /// Sellmeier equation with `c` in µm².
+    Sellmeier { b: [Real; 3], c: [Real; 3] },
+}
+
+/// Borosilicate crown glass.
+pub const BK7: Ior = Ior::Sellmeier {
+    b: [1.039_612_12, 0.231_792_344, 1.010_469_45],
+    c: [0.006_000_698_67, 0.020_017_914_4, 103.560_653],
+};
+/// Dense flint glass, strongly dispersive.
+pub const SF11: Ior = Ior::Sellmeier {
+    b: [1.737_596_95, 0.313_747_346, 1.898_781_01],
+    c: [0.013_188_707, 0.062_306_814_2, 155.236_29],
+};
+pub const FUSED_SILICA: Ior = Ior::Sellmeier {
+    b: [0.696_166_3, 0.407_942_6, 0.897_479_4],
+    c: [0.004_679_148, 0.013_512_06, 97.934_0],
+};
+pub const WATER: Ior = Ior::Cauchy {
+    a: 1.3199,
+    b: 0.006_878,
+};
+
+impl Ior {
+    /// Index of refraction at `lambda` nm.
+    pub fn at(&self, lambda: Real) -> Real {
+        let l = lambda * 1e-3;
+        let l2 = l * l;
+        match *self {
+            Ior::Constant(n) => n,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => {
+                let sum: Real = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        match *self {
+            Ior::Constant(_) => false,
+            _ => true,
+        }
+    }
+}
+
+/// Fresnel reflectance of unpolarized light hitting an interface at
+/// `cos_i` from the side with the lower index, `eta` is the ratio of the
+/// transmitted to the incident index.
+pub fn fresnel_dielectric(cos_i: Real, eta: Real) -> Real {
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+/// Smooth interface of a transparent solid, the normal points out of it.
+#[derive(Clone, Copy, Debug)]
+pub struct Dielectric {
+    pub ior: Ior,
+    /// Transmittance of the refracted light.
+    pub color: Color,
+}
+
+impl Dielectric {
+    pub fn new(ior: Ior) -> Dielectric {
+        Dielectric {
+            ior: ior,
+            color: color::WHITE,
+        }
+    }
+
+    pub fn with_color(mut self, color: Color) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Reflects or refracts `in_dir` by the Fresnel reflectance at the
+    /// index `n`, with the throughput weight.
+    fn scatter(&self, normal: &Vector3f, in_dir: &Vector3f, n: Real) -> (Vector3f, Color) {
+        let cos = -normal.dot(in_dir);
+        let (normal, cos_i, eta) = if cos > 0.0 {
+            (*normal, cos, n)
+        } else {
+            (-*normal, -cos, 1.0 / n)
+        };
+
+        if rng::uniform() < fresnel_dielectric(cos_i, eta) {
+            (*in_dir + normal * (2.0 * cos_i), color::WHITE)
+        } else {
+            let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+            let cos_t = (1.0 - sin2_t).sqrt();
+            let dir = (*in_dir * (1.0 / eta) + normal * (cos_i / eta - cos_t)).normalize();
+            // radiance is compressed into the smaller solid angle
+            (dir, self.color * (1.0 / (eta * eta)) as f32)
+        }
+    }
+}
+
+impl Bsdf for Dielectric {
+    fn radiance(&self) -> Option<Color> {
+        None
+    }
+
+    /// Zero, the interface scatters only into sampled directions.
+    fn eval(&self, _: &Vector3f, _: &Vector3f, _: &Vector3f) -> (Color, Real) {
+        (color::BLACK, 0.0)
+    }
+
+    fn sample(&self, surface_normal: &Vector3f, in_dir: &Vector3f) -> (Vector3f, Color, Real) {
+        let (dir, w) = self.scatter(surface_normal, in_dir, self.ior.at(D_LINE));
+        (dir, w, surface_normal.dot(&dir).abs())
+    }
+
+    fn sample_proj(&self, surface_normal: &Vector3f, in_dir: &Vector3f) -> (Vector3f, Color, Real) {
+        let (dir, w) = self.scatter(surface_normal, in_dir, self.ior.at(D_LINE));
+        (dir, w, 1.0)
+    }
+
+    fn ior(&self) -> Option<&Ior> {
+        Some(&self.ior)
+    }
+
+    fn sample_wavelength(
+        &self,
+        surface_normal: &Vector3f,
+        in_dir: &Vector3f,
+        lambda: Real,
+    ) -> (Vector3f, Color, Real) {
+        let (dir, w) = self.scatter(surface_normal, in_dir, self.ior.at(lambda));
+        (dir, w, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glass_disperses() {
+        // n_d and n_F - n_C of the catalog
+        assert!((BK7.at(D_LINE) - 1.5168).abs() < 1e-4);
+        assert!((SF11.at(D_LINE) - 1.7847).abs() < 1e-3);
+        assert!(BK7.at(486.1) - BK7.at(656.3) > 0.0075);
+        assert!(!Ior::Constant(1.5).is_dispersive());
+    }
+
+    #[test]
+    fn fresnel_limits() {
+        let f0 = fresnel_dielectric(1.0, 1.5);
+        assert!((f0 - 0.04).abs() < 1e-9);
+        assert_eq!(fresnel_dielectric(0.1, 1.0 / 1.5), 1.0);
+        assert!((fresnel_dielectric(1e-9, 1.5) - 1.0).abs() < 1e-6);
+    }
+}
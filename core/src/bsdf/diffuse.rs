@@ -1,6 +1,7 @@
 use Bsdf;
 use color::{self, Color, Rgb};
 use math::{self, Dot, Real, Vector3f};
+use spectrum::Spd;
 use std::f64::consts::PI;
 
 #[inline]
@@ -33,6 +34,7 @@ where
 pub struct Diffuse {
     pub color: Color,
     pub radiance: Option<Color>,
+    pub spd: Option<Spd>,
 }
 
 impl Diffuse {
@@ -40,8 +42,16 @@ impl Diffuse {
         Diffuse {
             color: color,
             radiance: radiance,
+            spd: None,
         }
     }
+
+    /// Emits `spd` with luminance `luminance`, e.g. a black body.
+    pub fn with_emission(mut self, spd: Spd, luminance: f32) -> Self {
+        self.radiance = Some(spd.radiance(luminance));
+        self.spd = Some(spd);
+        self
+    }
 }
 
 impl Bsdf for Diffuse {
@@ -49,6 +59,10 @@ impl Bsdf for Diffuse {
         self.radiance
     }
 
+    fn emission_spd(&self) -> Option<&Spd> {
+        self.spd.as_ref()
+    }
+
     fn sample(&self, surface_normal: &Vector3f, _: &Vector3f) -> (Vector3f, Color, Real) {
         sample::<f32>(surface_normal, &self.color)
     }
@@ -68,6 +82,14 @@ impl Bsdf for Diffuse {
         eval::<f32>(cos_no, &self.color)
     }
 
+    fn eval_diffuse(&self, _: &Vector3f, _: &Vector3f, _: &Vector3f) -> Color {
+        self.color * (1.0 / PI as f32)
+    }
+
+    fn albedo(&self, _: &Vector3f, _: &Vector3f) -> Color {
+        self.color
+    }
+
     // fn eval_proj(
     //     &self,
     //     _: &Vector3f,
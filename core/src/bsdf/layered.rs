@@ -0,0 +1,254 @@
+use super::cooktorrance::{ggx_d, ggx_g, pdf_refl, sample_halfvec};
+use super::dielectric::fresnel_dielectric;
+use {Bsdf, BsdfRef};
+use color::{self, Color};
+use math::{self, Dot, Norm, Real, Vector3f};
+use std::sync::Arc;
+use utils::consts;
+use utils::rng;
+
+/// Dielectric coat of a `Layered` BSDF, a rough interface over an
+/// absorbing layer.
+#[derive(Clone, Copy, Debug)]
+pub struct Coat {
+    pub ior: Real,
+    /// Roughness of the interface, squared to the GGX `alpha`.
+    pub roughness: Real,
+    pub thickness: Real,
+    /// Transmittance of a layer of unit thickness at normal incidence.
+    pub color: Color,
+}
+
+impl Coat {
+    /// Clear smooth coat of index `ior`, e.g. 1.5 for varnish or lacquer.
+    pub fn new(ior: Real) -> Coat {
+        Coat {
+            ior: ior.max(1.0 + 1e-4),
+            roughness: 0.0,
+            thickness: 0.0,
+            color: color::WHITE,
+        }
+    }
+
+    pub fn with_roughness(mut self, roughness: Real) -> Self {
+        self.roughness = roughness.max(0.0).min(1.0);
+        self
+    }
+
+    /// Absorbs like a layer of `thickness` with the transmittance `color`
+    /// at unit thickness.
+    pub fn with_absorption(mut self, thickness: Real, color: Color) -> Self {
+        self.thickness = thickness.max(0.0);
+        self.color = color;
+        self
+    }
+
+    fn alpha(&self) -> Real {
+        (self.roughness * self.roughness).max(consts::REAL_EPSILON.sqrt())
+    }
+
+    /// `dir`, pointing away from the surface, refracted into the coat.
+    fn refract_in(&self, normal: &Vector3f, dir: &Vector3f) -> Vector3f {
+        let cos = normal.dot(dir);
+        let sin2_t = (1.0 - cos * cos) / (self.ior * self.ior);
+        let cos_t = (1.0 - sin2_t).sqrt();
+        ((*dir - *normal * cos) * (1.0 / self.ior) + *normal * cos_t).normalize()
+    }
+
+    /// `dir` in the coat refracted out of it, `None` if it is reflected
+    /// totally.
+    fn refract_out(&self, normal: &Vector3f, dir: &Vector3f) -> Option<Vector3f> {
+        let cos_t = normal.dot(dir);
+        let sin2 = (1.0 - cos_t * cos_t) * self.ior * self.ior;
+        if sin2 >= 1.0 {
+            return None;
+        }
+        let cos = (1.0 - sin2).sqrt();
+        Some(((*dir - *normal * cos_t) * self.ior + *normal * cos).normalize())
+    }
+
+    /// Transmittance along the path down at `cos_in` and up at `cos_out`
+    /// to the normal inside the coat.
+    fn transmittance(&self, cos_in: Real, cos_out: Real) -> Color {
+        if self.thickness <= 0.0 {
+            return color::WHITE;
+        }
+        let d = (self.thickness * (1.0 / cos_in + 1.0 / cos_out)) as f32;
+        Color::new(self.color.r.powf(d), self.color.g.powf(d), self.color.b.powf(d))
+    }
+}
+
+/// `base` under a dielectric coat, e.g. car paint or varnished wood. Light
+/// reaching the base is attenuated by the Fresnel transmittance of the
+/// interface in both directions and the absorption of the coat, the base
+/// is evaluated at the directions refracted into the coat. The base
+/// should not be specular.
+pub struct Layered<'a> {
+    pub base: BsdfRef<'a>,
+    pub coat: Coat,
+}
+
+impl<'a> Layered<'a> {
+    pub fn new(base: BsdfRef<'a>, coat: Coat) -> Layered<'a> {
+        Layered { base, coat }
+    }
+
+    pub fn shared<B: Bsdf + 'a>(base: B, coat: Coat) -> Layered<'a> {
+        Layered::new(BsdfRef::Shared(Arc::new(base)), coat)
+    }
+
+    /// Probability of sampling the coat for `view` at `cos_v` to the
+    /// normal, its Fresnel reflectance against the light reaching the
+    /// base.
+    fn coat_prob(&self, cos_v: Real, cos_vt: Real) -> Real {
+        let f = fresnel_dielectric(cos_v, self.coat.ior);
+        let t = color::luminance(&self.coat.transmittance(cos_vt, cos_vt)) as Real;
+        let sum = f + (1.0 - f) * t;
+        if sum > 0.0 { f / sum } else { 1.0 }
+    }
+
+    /// Reflectance of the coat and of the base through the coat for `view`
+    /// and `light` pointing away from the surface, with the pdf of
+    /// sampling `light`.
+    fn eval_layers(
+        &self,
+        normal: &Vector3f,
+        view: &Vector3f,
+        light: &Vector3f,
+    ) -> (Color, Color, Real) {
+        let cos_v = normal.dot(view);
+        let cos_l = normal.dot(light);
+        if cos_v <= 0.0 || cos_l <= 0.0 {
+            return (color::BLACK, color::BLACK, 0.0);
+        }
+        let eta = self.coat.ior;
+        let alpha = self.coat.alpha();
+
+        let half = (*view + *light).normalize();
+        let cos_nh = normal.dot(&half);
+        let cos_lh = light.dot(&half);
+        let d = ggx_d(cos_nh, alpha);
+        let g = ggx_g(cos_l, cos_v, cos_lh, cos_lh, alpha);
+        let coat = fresnel_dielectric(cos_lh, eta) * d * g / (4.0 * cos_v * cos_l);
+        let pdf_coat = pdf_refl(cos_nh, cos_lh, alpha);
+
+        let view_t = self.coat.refract_in(normal, view);
+        let light_t = self.coat.refract_in(normal, light);
+        let cos_vt = normal.dot(&view_t);
+        let cos_lt = normal.dot(&light_t);
+        let (fr, pdf_base) = self.base.eval(normal, &(-view_t), &light_t);
+        // without the eta² compression of the radiance in the coat, which
+        // stands in for the light reflected back down by the interface
+        let t_v = 1.0 - fresnel_dielectric(cos_v, eta);
+        let t_l = 1.0 - fresnel_dielectric(cos_l, eta);
+        let base = fr * self.coat.transmittance(cos_vt, cos_lt) * (t_v * t_l) as f32;
+        // solid angle of `light_t` per solid angle of `light`
+        let jacobian = cos_l / (eta * eta * cos_lt);
+
+        let p = self.coat_prob(cos_v, cos_vt);
+        let pdf = p * pdf_coat + (1.0 - p) * pdf_base * jacobian;
+        (Color::from(coat as f32), base, pdf)
+    }
+}
+
+impl<'a> Bsdf for Layered<'a> {
+    fn radiance(&self) -> Option<Color> {
+        self.base.radiance()
+    }
+
+    fn eval(
+        &self,
+        surface_normal: &Vector3f,
+        in_dir: &Vector3f,
+        out_dir: &Vector3f,
+    ) -> (Color, Real) {
+        let (coat, base, pdf) = self.eval_layers(surface_normal, &(-in_dir), out_dir);
+        (coat + base, pdf)
+    }
+
+    /// Samples the coat or the base by `coat_prob`, directions leaving
+    /// below the surface carry no weight.
+    fn sample(&self, surface_normal: &Vector3f, in_dir: &Vector3f) -> (Vector3f, Color, Real) {
+        let view = -in_dir;
+        let cos_v = surface_normal.dot(&view);
+        if cos_v <= 0.0 {
+            return (*in_dir, color::BLACK, 1.0);
+        }
+
+        let view_t = self.coat.refract_in(surface_normal, &view);
+        let out_dir = if rng::uniform() < self.coat_prob(cos_v, surface_normal.dot(&view_t)) {
+            let half = sample_halfvec(surface_normal, self.coat.alpha());
+            math::reflect_vec(&view, &half)
+        } else {
+            let (light_t, _, _) = self.base.sample(surface_normal, &(-view_t));
+            match self.coat.refract_out(surface_normal, &light_t) {
+                Some(dir) if surface_normal.dot(&light_t) > 0.0 => dir,
+                _ => return (light_t, color::BLACK, 1.0),
+            }
+        };
+
+        let (fr, pdf) = self.eval(surface_normal, in_dir, &out_dir);
+        if pdf > 0.0 {
+            (out_dir, fr, pdf)
+        } else {
+            (out_dir, color::BLACK, 1.0)
+        }
+    }
+
+    fn eval_diffuse(&self, normal: &Vector3f, in_dir: &Vector3f, out_dir: &Vector3f) -> Color {
+        let view = -in_dir;
+        let (_, base, _) = self.eval_layers(normal, &view, out_dir);
+        if base == color::BLACK {
+            return base;
+        }
+        // the diffuse fraction of the base
+        let view_t = self.coat.refract_in(normal, &view);
+        let light_t = self.coat.refract_in(normal, out_dir);
+        let (fr, _) = self.base.eval(normal, &(-view_t), &light_t);
+        let fd = self.base.eval_diffuse(normal, &(-view_t), &light_t);
+        let ratio = |d: f32, f: f32| if f > 0.0 { (d / f).max(0.0).min(1.0) } else { 0.0 };
+        Color::new(
+            base.r * ratio(fd.r, fr.r),
+            base.g * ratio(fd.g, fr.g),
+            base.b * ratio(fd.b, fr.b),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsdf::Diffuse;
+    use bsdf::testing;
+
+    #[test]
+    fn sample_matches_eval() {
+        let n = Vector3f::new(0.0, 1.0, 0.0);
+        let coat = Coat::new(1.5)
+            .with_roughness(0.3)
+            .with_absorption(0.5, Color::new(0.9, 0.5, 0.2));
+        let b = Layered::shared(Diffuse::new(color::WHITE, None), coat);
+        for in_dir in &[Vector3f::new(0.6, -0.8, 0.0), Vector3f::new(0.0, -1.0, 0.0)] {
+            testing::assert_sampling_matches_eval(&b, &n, in_dir);
+        }
+    }
+
+    #[test]
+    fn coat_attenuates_base() {
+        let n = Vector3f::new(0.0, 1.0, 0.0);
+        let in_dir = Vector3f::new(0.0, -1.0, 0.0);
+        let out_dir = Vector3f::new(0.6, 0.8, 0.0);
+        let diffuse = Diffuse::new(color::WHITE, None);
+        let (fd, _) = diffuse.eval(&n, &in_dir, &out_dir);
+
+        let clear = Layered::shared(diffuse, Coat::new(1.5));
+        let (f, _) = clear.eval(&n, &in_dir, &out_dir);
+        // off the highlight only the base remains, less than without coat
+        assert!(f.g < fd.g && f.g > 0.3 * fd.g);
+
+        let tinted = Coat::new(1.5).with_absorption(1.0, Color::new(1.0, 0.5, 0.5));
+        let (t, _) = Layered::shared(diffuse, tinted).eval(&n, &in_dir, &out_dir);
+        assert!((t.r - f.r).abs() < 1e-6);
+        assert!(t.g < 0.3 * f.g);
+    }
+}
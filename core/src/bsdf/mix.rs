@@ -0,0 +1,226 @@
+use super::Ior;
+use {Bsdf, BsdfRef};
+use color::{self, Color};
+use math::{Dot, Real, Vector3f};
+use std::sync::Arc;
+use utils::rng;
+
+/// `a` and `b` blended by `weight`, the fraction of `b`. Samples one of
+/// them by the weight and combines the pdfs of both. Specular if either is
+/// specular, a specular part is then sampled alone.
+pub struct Mix<'a> {
+    pub a: BsdfRef<'a>,
+    pub b: BsdfRef<'a>,
+    pub weight: Real,
+}
+
+impl<'a> Mix<'a> {
+    pub fn new(a: BsdfRef<'a>, b: BsdfRef<'a>, weight: Real) -> Mix<'a> {
+        Mix {
+            a,
+            b,
+            weight: weight.max(0.0).min(1.0),
+        }
+    }
+
+    pub fn shared<A, B>(a: A, b: B, weight: Real) -> Mix<'a>
+    where
+        A: Bsdf + 'a,
+        B: Bsdf + 'a,
+    {
+        Mix::new(BsdfRef::Shared(Arc::new(a)), BsdfRef::Shared(Arc::new(b)), weight)
+    }
+
+    fn blend(&self, a: Color, b: Color) -> Color {
+        a * (1.0 - self.weight) as f32 + b * self.weight as f32
+    }
+
+    /// Samples `a` or `b`, giving the direction with the value and the
+    /// solid angle pdf, or the throughput weight of a specular sample. A
+    /// specular part is sampled at `lambda` nm if given.
+    fn sample_dir(
+        &self,
+        normal: &Vector3f,
+        in_dir: &Vector3f,
+        lambda: Option<Real>,
+    ) -> (Vector3f, Color, Real, bool) {
+        let chosen = if rng::uniform() < self.weight {
+            &self.b
+        } else {
+            &self.a
+        };
+        if chosen.is_specular() {
+            let (dir, w, _) = match lambda {
+                Some(lambda) => chosen.sample_wavelength(normal, in_dir, lambda),
+                None => chosen.sample_proj(normal, in_dir),
+            };
+            return (dir, w, 1.0, true);
+        }
+        let (dir, _, _) = chosen.sample(normal, in_dir);
+        let (fr, pdf) = if self.is_specular() {
+            // the other part has no density, the weights of the choice and
+            // of the part cancel
+            chosen.eval(normal, in_dir, &dir)
+        } else {
+            self.eval(normal, in_dir, &dir)
+        };
+        (dir, fr, pdf, false)
+    }
+
+    fn sample_proj_at(
+        &self,
+        normal: &Vector3f,
+        in_dir: &Vector3f,
+        lambda: Option<Real>,
+    ) -> (Vector3f, Color, Real) {
+        match self.sample_dir(normal, in_dir, lambda) {
+            (dir, w, _, true) => (dir, w, 1.0),
+            (dir, fr, pdf, false) => {
+                let cos = normal.dot(&dir);
+                if pdf > 0.0 && cos > 0.0 {
+                    (dir, fr, pdf / cos)
+                } else {
+                    (dir, color::BLACK, 1.0)
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Bsdf for Mix<'a> {
+    fn radiance(&self) -> Option<Color> {
+        match (self.a.radiance(), self.b.radiance()) {
+            (None, None) => None,
+            (a, b) => Some(self.blend(a.unwrap_or(color::BLACK), b.unwrap_or(color::BLACK))),
+        }
+    }
+
+    fn radiance_dir(&self, surface_normal: &Vector3f, dir: &Vector3f) -> Option<Color> {
+        let a = self.a.radiance_dir(surface_normal, dir);
+        let b = self.b.radiance_dir(surface_normal, dir);
+        match (a, b) {
+            (None, None) => None,
+            (a, b) => Some(self.blend(a.unwrap_or(color::BLACK), b.unwrap_or(color::BLACK))),
+        }
+    }
+
+    fn eval(
+        &self,
+        surface_normal: &Vector3f,
+        in_dir: &Vector3f,
+        out_dir: &Vector3f,
+    ) -> (Color, Real) {
+        let (fa, pdf_a) = self.a.eval(surface_normal, in_dir, out_dir);
+        let (fb, pdf_b) = self.b.eval(surface_normal, in_dir, out_dir);
+        let pdf = pdf_a * (1.0 - self.weight) + pdf_b * self.weight;
+        (self.blend(fa, fb), pdf)
+    }
+
+    fn sample(&self, surface_normal: &Vector3f, in_dir: &Vector3f) -> (Vector3f, Color, Real) {
+        match self.sample_dir(surface_normal, in_dir, None) {
+            (dir, w, _, true) => (dir, w, surface_normal.dot(&dir).abs()),
+            (dir, fr, pdf, false) => if pdf > 0.0 {
+                (dir, fr, pdf)
+            } else {
+                (dir, color::BLACK, 1.0)
+            },
+        }
+    }
+
+    fn sample_proj(&self, surface_normal: &Vector3f, in_dir: &Vector3f) -> (Vector3f, Color, Real) {
+        self.sample_proj_at(surface_normal, in_dir, None)
+    }
+
+    fn eval_diffuse(&self, normal: &Vector3f, in_dir: &Vector3f, out_dir: &Vector3f) -> Color {
+        self.blend(
+            self.a.eval_diffuse(normal, in_dir, out_dir),
+            self.b.eval_diffuse(normal, in_dir, out_dir),
+        )
+    }
+
+    fn albedo(&self, surface_normal: &Vector3f, in_dir: &Vector3f) -> Color {
+        self.blend(
+            self.a.albedo(surface_normal, in_dir),
+            self.b.albedo(surface_normal, in_dir),
+        )
+    }
+
+    fn ior(&self) -> Option<&Ior> {
+        self.a.ior().or_else(|| self.b.ior())
+    }
+
+    fn is_specular(&self) -> bool {
+        self.a.is_specular() || self.b.is_specular()
+    }
+
+    fn sample_wavelength(
+        &self,
+        surface_normal: &Vector3f,
+        in_dir: &Vector3f,
+        lambda: Real,
+    ) -> (Vector3f, Color, Real) {
+        self.sample_proj_at(surface_normal, in_dir, Some(lambda))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsdf::{Conductor, Dielectric, Diffuse, OrenNayar};
+    use bsdf::conductor::GOLD;
+    use bsdf::dielectric::SF11;
+
+    #[test]
+    fn pdfs_combine() {
+        let n = Vector3f::new(0.0, 1.0, 0.0);
+        let in_dir = Vector3f::new(0.6, -0.8, 0.0);
+        let a = Diffuse::new(Color::new(0.8, 0.8, 0.8), None);
+        let b = Conductor::new(GOLD, 0.4);
+        let mix = Mix::shared(a, b, 0.25);
+        assert!(!mix.is_specular());
+        for _ in 0..16 {
+            let (dir, fr, pdf) = mix.sample(&n, &in_dir);
+            let (fa, pdf_a) = a.eval(&n, &in_dir, &dir);
+            let (fb, pdf_b) = b.eval(&n, &in_dir, &dir);
+            if pdf > 0.0 && fr != color::BLACK {
+                assert!((pdf - (0.75 * pdf_a + 0.25 * pdf_b)).abs() < 1e-9);
+                assert!((fr.g - (0.75 * fa.g + 0.25 * fb.g)).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn specular_part_is_sampled_alone() {
+        let n = Vector3f::new(0.0, 1.0, 0.0);
+        let in_dir = Vector3f::new(0.6, -0.8, 0.0);
+        let a = OrenNayar::new(color::WHITE, 0.3, None);
+        let mix = Mix::shared(a, Conductor::smooth(GOLD), 0.5);
+        assert!(mix.is_specular());
+        for _ in 0..16 {
+            let (dir, w, pdf) = mix.sample_proj(&n, &in_dir);
+            if dir.z == 0.0 {
+                // the mirror reflection
+                assert_eq!(pdf, 1.0);
+            } else {
+                // the Oren-Nayar part by its own pdf
+                let (f, p) = a.eval_proj(&n, &in_dir, &dir);
+                assert_eq!((w, pdf), (f, p));
+            }
+        }
+    }
+
+    #[test]
+    fn specular_part_disperses() {
+        let n = Vector3f::new(0.0, 1.0, 0.0);
+        let in_dir = Vector3f::new(0.6, -0.8, 0.0);
+        let mix = Mix::shared(Diffuse::new(color::WHITE, None), Dielectric::new(SF11), 1.0);
+        let refracted = |lambda: Real| loop {
+            let (dir, _, _) = mix.sample_wavelength(&n, &in_dir, lambda);
+            if dir.y < 0.0 {
+                break dir;
+            }
+        };
+        // blue is refracted closer to the normal than red
+        assert!(refracted(450.0).x < refracted(650.0).x);
+    }
+}
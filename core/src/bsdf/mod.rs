@@ -1,19 +1,48 @@
 pub mod diffuse;
 pub mod phong;
 pub mod cooktorrance;
+pub mod conductor;
+pub mod dielectric;
+pub mod layered;
+pub mod mix;
+pub mod orennayar;
+pub mod principled;
+pub mod profiled;
+#[cfg(test)]
+mod testing;
 
+pub use self::conductor::{Conductor, Metal};
 pub use self::cooktorrance::*;
+pub use self::dielectric::{Dielectric, Ior};
 pub use self::diffuse::Diffuse;
+pub use self::layered::{Coat, Layered};
+pub use self::mix::Mix;
+pub use self::orennayar::OrenNayar;
 pub use self::phong::Phong;
+pub use self::principled::Principled;
+pub use self::profiled::Profiled;
 
-use color::Color;
+use color::{self, Color};
 use math::{Dot, Real, Vector3f};
+use spectrum::Spd;
 use std::ops::Deref;
 use std::sync::Arc;
 
 pub trait Bsdf: Sync + Send {
     fn radiance(&self) -> Option<Color>;
 
+    /// Radiance emitted toward `dir`, pointing away from the surface.
+    /// `radiance` unless the emission depends on the direction.
+    fn radiance_dir(&self, _surface_normal: &Vector3f, _dir: &Vector3f) -> Option<Color> {
+        self.radiance()
+    }
+
+    /// Spectral power distribution of the emission, whose color scaled by
+    /// its luminance is `radiance`. Uplifted from `radiance` without one.
+    fn emission_spd(&self) -> Option<&Spd> {
+        None
+    }
+
     fn eval(
         &self,
         surface_normal: &Vector3f,
@@ -42,6 +71,49 @@ pub trait Bsdf: Sync + Send {
 
         (ray, fr, pdf / cos_theta)
     }
+
+    /// Lambertian part of `eval`, used to split diffuse from specular
+    /// reflection.
+    fn eval_diffuse(&self, _: &Vector3f, _: &Vector3f, _: &Vector3f) -> Color {
+        color::BLACK
+    }
+
+    /// Fraction of the light coming from `in_dir` that is reflected,
+    /// estimated by sampling unless overridden.
+    fn albedo(&self, surface_normal: &Vector3f, in_dir: &Vector3f) -> Color {
+        const SAMPLES: u32 = 16;
+        let mut sum = color::BLACK;
+        for _ in 0..SAMPLES {
+            let (_, fr, pdf) = self.sample_proj(surface_normal, in_dir);
+            if pdf > 0.0 {
+                sum += fr * (1.0 / pdf) as f32;
+            }
+        }
+        sum * (1.0 / SAMPLES as f32)
+    }
+
+    /// Index of refraction of a BSDF that refracts. Such surfaces are hit
+    /// from both sides and scatter only into sampled directions.
+    fn ior(&self) -> Option<&Ior> {
+        None
+    }
+
+    /// Whether the BSDF scatters only into sampled directions, with `eval`
+    /// zero, like a mirror or a refracting interface.
+    fn is_specular(&self) -> bool {
+        self.ior().is_some()
+    }
+
+    /// `sample_proj` for light of `lambda` nm, which differs for BSDFs with
+    /// a dispersive `ior`.
+    fn sample_wavelength(
+        &self,
+        surface_normal: &Vector3f,
+        in_dir: &Vector3f,
+        _lambda: Real,
+    ) -> (Vector3f, Color, Real) {
+        self.sample_proj(surface_normal, in_dir)
+    }
 }
 
 pub enum BsdfRef<'a> {
@@ -0,0 +1,138 @@
+use Bsdf;
+use color::Color;
+use math::{self, Dot, Real, Vector3f};
+use std::f64::consts::PI;
+
+/// `A` and `B` coefficients of the qualitative Oren-Nayar model for the
+/// slope deviation `sigma` in radians.
+#[inline]
+pub fn coefficients(sigma: Real) -> (Real, Real) {
+    let sigma2 = sigma * sigma;
+    let a = 1.0 - sigma2 / (2.0 * (sigma2 + 0.33));
+    let b = 0.45 * sigma2 / (sigma2 + 0.09);
+    (a, b)
+}
+
+/// Factor of `albedo / π` reflected from `view` to `light`, both pointing
+/// away from the surface.
+pub fn eval(normal: &Vector3f, view: &Vector3f, light: &Vector3f, a: Real, b: Real) -> Real {
+    let cos_v = normal.dot(view);
+    let cos_l = normal.dot(light);
+    if cos_v <= 0.0 || cos_l <= 0.0 {
+        return 0.0;
+    }
+
+    let sin_v = (1.0 - cos_v * cos_v).max(0.0).sqrt();
+    let sin_l = (1.0 - cos_l * cos_l).max(0.0).sqrt();
+    if sin_v < 1e-4 || sin_l < 1e-4 {
+        return a;
+    }
+
+    // cosine of the azimuth between the directions
+    let tan_v = *view - *normal * cos_v;
+    let tan_l = *light - *normal * cos_l;
+    let cos_phi = (tan_v.dot(&tan_l) / (sin_v * sin_l)).max(0.0);
+
+    let (sin_alpha, tan_beta) = if cos_v > cos_l {
+        (sin_l, sin_v / cos_v)
+    } else {
+        (sin_v, sin_l / cos_l)
+    };
+    a + b * cos_phi * sin_alpha * tan_beta
+}
+
+/// Rough diffuse reflector, e.g. clay, concrete or cloth. Brighter than a
+/// Lambertian surface toward the light at grazing angles.
+#[derive(Clone, Copy, Debug)]
+pub struct OrenNayar {
+    pub color: Color,
+    /// Standard deviation of the facet slopes in radians, 0 is Lambertian.
+    pub sigma: Real,
+    pub radiance: Option<Color>,
+    a: Real,
+    b: Real,
+}
+
+impl OrenNayar {
+    pub fn new(color: Color, sigma: Real, radiance: Option<Color>) -> OrenNayar {
+        let sigma = sigma.max(0.0);
+        let (a, b) = coefficients(sigma);
+        OrenNayar {
+            color: color,
+            sigma: sigma,
+            radiance: radiance,
+            a: a,
+            b: b,
+        }
+    }
+
+    fn reflectance(&self, normal: &Vector3f, in_dir: &Vector3f, out_dir: &Vector3f) -> Color {
+        let k = eval(normal, &(-in_dir), out_dir, self.a, self.b) / PI as Real;
+        self.color * k as f32
+    }
+}
+
+impl Bsdf for OrenNayar {
+    fn radiance(&self) -> Option<Color> {
+        self.radiance
+    }
+
+    /// Cosine weighted, like `Diffuse`.
+    fn sample(&self, surface_normal: &Vector3f, in_dir: &Vector3f) -> (Vector3f, Color, Real) {
+        let out_dir = math::hs_cosine_sampling(surface_normal);
+        let (fr, pdf) = self.eval(surface_normal, in_dir, &out_dir);
+        (out_dir, fr, pdf)
+    }
+
+    fn eval(
+        &self,
+        surface_normal: &Vector3f,
+        in_dir: &Vector3f,
+        out_dir: &Vector3f,
+    ) -> (Color, Real) {
+        let pdf = surface_normal.dot(out_dir).max(0.0) / PI as Real;
+        (self.reflectance(surface_normal, in_dir, out_dir), pdf)
+    }
+
+    fn eval_diffuse(&self, normal: &Vector3f, in_dir: &Vector3f, out_dir: &Vector3f) -> Color {
+        self.reflectance(normal, in_dir, out_dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsdf::Diffuse;
+    use color;
+    use math::Norm;
+
+    #[test]
+    fn smooth_is_lambertian() {
+        let n = Vector3f::new(0.0, 1.0, 0.0);
+        let in_dir = Vector3f::new(0.6, -0.8, 0.0);
+        let out_dir = Vector3f::new(0.0, 0.6, 0.8);
+        let on = OrenNayar::new(color::WHITE, 0.0, None);
+        let d = Diffuse::new(color::WHITE, None);
+        let (f, pdf) = on.eval(&n, &in_dir, &out_dir);
+        let (fd, pdf_d) = d.eval(&n, &in_dir, &out_dir);
+        assert!((f.g - fd.g).abs() < 1e-6);
+        assert!((pdf - pdf_d).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rough_retroreflects() {
+        let n = Vector3f::new(0.0, 1.0, 0.0);
+        let view = Vector3f::new(0.9, 0.3, 0.0).normalize();
+        let on = OrenNayar::new(color::WHITE, 0.5, None);
+        // back toward the viewer at a grazing angle
+        let (back, _) = on.eval(&n, &(-view), &view);
+        let (away, _) = on.eval(&n, &(-view), &Vector3f::new(-view.x, view.y, 0.0));
+        assert!(back.g > (1.0 / PI) as f32);
+        assert!(back.g > away.g);
+
+        let (dir, fr, pdf) = on.sample(&n, &(-view));
+        let (f, p) = on.eval(&n, &(-view), &dir);
+        assert_eq!(fr, f);
+        assert_eq!(pdf, p);
+    }
+}
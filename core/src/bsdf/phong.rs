@@ -3,10 +3,8 @@ use color;
 use math;
 use math::{Cross, Dot, Norm};
 use math::{Real, Vector3f};
-use rand;
-use rand::Closed01;
-use rand::distributions::{IndependentSample, Range};
 use std::f32::consts::PI;
+use utils::rng;
 
 
 #[derive(Clone, Copy, Debug)]
@@ -35,10 +33,8 @@ impl Phong {
 
     fn random_vector(&self, normal: &Vector3f) -> Vector3f {
 
-        let mut rng = rand::thread_rng();
-        let r01 = Range::new(0.0, 1.0 as Real);
-        let u1 = r01.ind_sample(&mut rng);
-        let u2 = r01.ind_sample(&mut rng);
+        let u1 = rng::uniform();
+        let u2 = rng::uniform();
 
         let alpha = (1.0 - u1).powf(1.0 / (self.n as Real + 1.0)).acos();
         let phi = 2.0 * (PI as Real) * u2;
@@ -92,7 +88,7 @@ impl Bsdf for Phong {
     // }
 
     // fn brdf (&self, ray_dir: &Vector3f, surface_point: &Point3f, surface_normal: &Vector3f) -> (Ray3f, Color) {
-    //     let Closed01(e) = rand::random::<Closed01<f32>>();
+    //     let e = rng::uniform() as f32;
 
     //     if e < self.kd {
     //         (
@@ -130,7 +126,7 @@ impl Bsdf for Phong {
         in_dir: &Vector3f,
         out_dir: &Vector3f,
     ) -> (Color, Real) {
-        let Closed01(e) = rand::random::<Closed01<f32>>();
+        let e = rng::uniform() as f32;
         if e < self.kd {
             let pdf = out_dir.dot(surface_normal) / PI as Real;
 
@@ -160,9 +156,13 @@ impl Bsdf for Phong {
         }
     }
 
+    fn eval_diffuse(&self, _: &Vector3f, _: &Vector3f, _: &Vector3f) -> Color {
+        self.color * (self.kd / PI)
+    }
+
 
     fn sample(&self, surface_normal: &Vector3f, in_dir: &Vector3f) -> (Vector3f, Color, Real) {
-        let Closed01(e) = rand::random::<Closed01<f32>>();
+        let e = rng::uniform() as f32;
         if e < self.kd {
             let out_dir = math::hs_cosine_sampling(surface_normal);
             let pdf = out_dir.dot(surface_normal);
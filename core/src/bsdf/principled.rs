@@ -0,0 +1,445 @@
+//! Disney principled BSDF, "Physically Based Shading at Disney" (Burley
+//! 2012) with the transmission of the 2015 extension.
+
+use super::dielectric::{Dielectric, Ior};
+use Bsdf;
+use color::{self, Color, Rgb};
+use math::{self, Cross, Dot, Norm, Real, Vector3f};
+use std::f64::consts::PI;
+use utils::{clamp, rng};
+
+#[inline]
+fn lerp(a: Real, b: Real, t: Real) -> Real {
+    a + (b - a) * t
+}
+
+#[inline]
+fn lerp_rgb(a: &Rgb<Real>, b: &Rgb<Real>, t: Real) -> Rgb<Real> {
+    *a * (1.0 - t) + *b * t
+}
+
+#[inline]
+fn schlick_weight(cos: Real) -> Real {
+    clamp(1.0 - cos, 0.0, 1.0).powi(5)
+}
+
+/// Generalized Trowbridge-Reitz distribution with `gamma = 1`, the
+/// clearcoat lobe.
+fn gtr1(cos_nh: Real, alpha: Real) -> Real {
+    if alpha >= 1.0 {
+        return 1.0 / PI;
+    }
+    let alpha2 = alpha * alpha;
+    let t = 1.0 + (alpha2 - 1.0) * cos_nh * cos_nh;
+    (alpha2 - 1.0) / (PI * alpha2.ln() * t)
+}
+
+/// Anisotropic GGX for the half vector `(hx, hn, hz)` in the tangent frame.
+fn gtr2_aniso(hx: Real, hn: Real, hz: Real, ax: Real, ay: Real) -> Real {
+    let t = (hx / ax).powi(2) + (hz / ay).powi(2) + hn * hn;
+    1.0 / (PI * ax * ay * t * t)
+}
+
+/// Smith masking of GGX over `4 cos`, so that the product for both
+/// directions divides the microfacet BRDF by `4 cos_nl cos_nv`.
+fn smith_g(cos_n: Real, alpha: Real) -> Real {
+    let a = alpha * alpha;
+    let b = cos_n * cos_n;
+    1.0 / (cos_n + (a + b - a * b).sqrt())
+}
+
+fn smith_g_aniso(cos_n: Real, wx: Real, wz: Real, ax: Real, ay: Real) -> Real {
+    1.0 / (cos_n + ((wx * ax).powi(2) + (wz * ay).powi(2) + cos_n * cos_n).sqrt())
+}
+
+/// Probabilities of sampling each lobe.
+struct Lobes {
+    diffuse: Real,
+    specular: Real,
+    clearcoat: Real,
+    transmission: Real,
+}
+
+/// Principled BSDF of the parameters used by most DCC tools, all in
+/// `[0, 1]`. Transmission is smooth, refracting at the index given by
+/// `specular`. A transmitting surface is hit from both sides and traced
+/// like a `Dielectric`, without light samples.
+#[derive(Clone, Copy, Debug)]
+pub struct Principled {
+    base_color: Rgb<Real>,
+    metallic: Real,
+    roughness: Real,
+    specular: Real,
+    specular_tint: Real,
+    anisotropic: Real,
+    sheen: Real,
+    sheen_tint: Real,
+    clearcoat: Real,
+    clearcoat_gloss: Real,
+    subsurface: Real,
+    transmission: Real,
+    transmitter: Option<Dielectric>,
+    tangent: Option<Vector3f>,
+}
+
+impl Principled {
+    pub fn new<C>(base_color: C, metallic: Real, roughness: Real) -> Self
+    where
+        Rgb<Real>: From<C>,
+    {
+        Principled {
+            base_color: base_color.into(),
+            metallic: clamp(metallic, 0.0, 1.0),
+            roughness: clamp(roughness, 0.0, 1.0),
+            specular: 0.5,
+            specular_tint: 0.0,
+            anisotropic: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            subsurface: 0.0,
+            transmission: 0.0,
+            transmitter: None,
+            tangent: None,
+        }
+    }
+
+    pub fn with_specular(mut self, specular: Real, tint: Real) -> Self {
+        self.specular = clamp(specular, 0.0, 1.0);
+        self.specular_tint = clamp(tint, 0.0, 1.0);
+        let transmission = self.transmission;
+        self.with_transmission(transmission)
+    }
+
+    /// Stretches the highlight along the tangent, see `with_tangent`.
+    pub fn with_anisotropic(mut self, anisotropic: Real) -> Self {
+        self.anisotropic = clamp(anisotropic, 0.0, 1.0);
+        self
+    }
+
+    /// Tangent of the surface, usually of the vertex TBN, orienting the
+    /// anisotropic highlight. Without one the orientation is arbitrary.
+    pub fn with_tangent(mut self, tangent: Vector3f) -> Self {
+        self.tangent = Some(tangent);
+        self
+    }
+
+    pub fn with_sheen(mut self, sheen: Real, tint: Real) -> Self {
+        self.sheen = clamp(sheen, 0.0, 1.0);
+        self.sheen_tint = clamp(tint, 0.0, 1.0);
+        self
+    }
+
+    pub fn with_clearcoat(mut self, clearcoat: Real, gloss: Real) -> Self {
+        self.clearcoat = clamp(clearcoat, 0.0, 1.0);
+        self.clearcoat_gloss = clamp(gloss, 0.0, 1.0);
+        self
+    }
+
+    /// Blends the diffuse lobe toward the flatter Hanrahan-Krueger
+    /// approximation of subsurface scattering.
+    pub fn with_subsurface(mut self, subsurface: Real) -> Self {
+        self.subsurface = clamp(subsurface, 0.0, 1.0);
+        self
+    }
+
+    pub fn with_transmission(mut self, transmission: Real) -> Self {
+        self.transmission = clamp(transmission, 0.0, 1.0);
+        self.transmitter = if self.transmission_weight() > 0.0 {
+            // index of refraction with a normal reflectance of
+            // `0.08 specular`
+            let eta = 2.0 / (1.0 - (0.08 * self.specular).sqrt()) - 1.0;
+            let ior = Ior::Constant(eta.max(1.0 + 1e-4));
+            Some(Dielectric::new(ior).with_color(self.base_color.into()))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Weight of the transmission lobe, which replaces the diffuse and the
+    /// specular lobes.
+    fn transmission_weight(&self) -> Real {
+        (1.0 - self.metallic) * self.transmission
+    }
+
+    fn diffuse_weight(&self) -> Real {
+        (1.0 - self.metallic) * (1.0 - self.transmission)
+    }
+
+    /// Hue and saturation of the base color.
+    fn tint(&self) -> Rgb<Real> {
+        let lum = color::luminance(&self.base_color.into());
+        if lum > 0.0 {
+            self.base_color * (1.0 / lum as Real)
+        } else {
+            Rgb::<Real>::from(1.0)
+        }
+    }
+
+    fn specular_f0(&self) -> Rgb<Real> {
+        let tinted = lerp_rgb(&Rgb::<Real>::from(1.0), &self.tint(), self.specular_tint);
+        lerp_rgb(&(tinted * (0.08 * self.specular)), &self.base_color, self.metallic)
+    }
+
+    fn alpha_aniso(&self) -> (Real, Real) {
+        let aspect = (1.0 - 0.9 * self.anisotropic).sqrt();
+        let r2 = self.roughness * self.roughness;
+        ((r2 / aspect).max(0.001), (r2 * aspect).max(0.001))
+    }
+
+    /// Tangent and bitangent around `normal`, the tangent made orthogonal
+    /// to the normal, which may be from a normal map.
+    fn frame(&self, normal: &Vector3f) -> (Vector3f, Vector3f) {
+        if let Some(ref t) = self.tangent {
+            let x = *t - *normal * t.dot(normal);
+            if x.norm_squared() > 1e-12 {
+                let x = x.normalize();
+                return (x, x.cross(normal));
+            }
+        }
+        math::basis_y(normal)
+    }
+
+    fn clearcoat_alpha(&self) -> Real {
+        lerp(0.1, 0.001, self.clearcoat_gloss)
+    }
+
+    fn lobes(&self, cos_nv: Real) -> Lobes {
+        let fv = schlick_weight(cos_nv);
+        let base = color::luminance(&self.base_color.into()) as Real;
+        let f0 = color::luminance(&self.specular_f0().into()) as Real;
+        let trans = self.transmission_weight();
+
+        let mut lobes = Lobes {
+            diffuse: self.diffuse_weight() * (base + self.sheen),
+            specular: (1.0 - trans) * lerp(f0, 1.0, fv),
+            clearcoat: 0.25 * self.clearcoat * lerp(0.04, 1.0, fv),
+            transmission: trans,
+        };
+        let sum = lobes.diffuse + lobes.specular + lobes.clearcoat + lobes.transmission;
+        if sum > 0.0 {
+            lobes.diffuse /= sum;
+            lobes.specular /= sum;
+            lobes.clearcoat /= sum;
+            lobes.transmission /= sum;
+        } else {
+            lobes.diffuse = 1.0;
+        }
+        lobes
+    }
+
+    /// Diffuse, sheen included, and the sum of all lobes but the
+    /// transmission for the directions `view` and `light` pointing away
+    /// from the surface, with the pdf of sampling `light`.
+    fn eval_lobes(
+        &self,
+        normal: &Vector3f,
+        view: &Vector3f,
+        light: &Vector3f,
+    ) -> (Rgb<Real>, Rgb<Real>, Real) {
+        let black = Rgb::<Real>::from(0.0);
+        let cos_nv = normal.dot(view);
+        let cos_nl = normal.dot(light);
+        if cos_nv <= 0.0 || cos_nl <= 0.0 {
+            return (black, black, 0.0);
+        }
+
+        let half = (*view + *light).normalize();
+        let cos_nh = normal.dot(&half);
+        let cos_lh = light.dot(&half);
+        let fl = schlick_weight(cos_nl);
+        let fv = schlick_weight(cos_nv);
+        let fh = schlick_weight(cos_lh);
+        let lobes = self.lobes(cos_nv);
+
+        // retroreflection of rough surfaces and the subsurface flattening
+        let fd90 = 0.5 + 2.0 * cos_lh * cos_lh * self.roughness;
+        let fd = lerp(1.0, fd90, fl) * lerp(1.0, fd90, fv);
+        let fss90 = cos_lh * cos_lh * self.roughness;
+        let fss = lerp(1.0, fss90, fl) * lerp(1.0, fss90, fv);
+        let ss = 1.25 * (fss * (1.0 / (cos_nl + cos_nv) - 0.5) + 0.5);
+        let sheen_color = lerp_rgb(&Rgb::<Real>::from(1.0), &self.tint(), self.sheen_tint);
+        let diffuse = (self.base_color * (lerp(fd, ss, self.subsurface) / PI)
+            + sheen_color * (fh * self.sheen)) * self.diffuse_weight();
+
+        let (x, z) = self.frame(normal);
+        let (ax, ay) = self.alpha_aniso();
+        let d = gtr2_aniso(half.dot(&x), cos_nh, half.dot(&z), ax, ay);
+        let g = smith_g_aniso(cos_nl, light.dot(&x), light.dot(&z), ax, ay)
+            * smith_g_aniso(cos_nv, view.dot(&x), view.dot(&z), ax, ay);
+        let f = lerp_rgb(&self.specular_f0(), &Rgb::<Real>::from(1.0), fh);
+        let specular = f * (d * g * (1.0 - self.transmission_weight()));
+
+        let alpha_c = self.clearcoat_alpha();
+        let dc = gtr1(cos_nh, alpha_c);
+        let gc = smith_g(cos_nl, 0.25) * smith_g(cos_nv, 0.25);
+        let clearcoat = 0.25 * self.clearcoat * lerp(0.04, 1.0, fh) * dc * gc;
+
+        let to_light = 1.0 / (4.0 * cos_lh);
+        let pdf = lobes.diffuse * cos_nl / PI + lobes.specular * d * cos_nh * to_light
+            + lobes.clearcoat * dc * cos_nh * to_light;
+
+        (diffuse, diffuse + specular + Rgb::from(clearcoat), pdf)
+    }
+
+    /// Samples a lobe, giving the direction and the throughput weight if
+    /// the sample is from the transmission.
+    fn sample_dir(&self, normal: &Vector3f, in_dir: &Vector3f) -> (Vector3f, Option<Color>) {
+        let view = -in_dir;
+        let cos_nv = normal.dot(&view);
+        if cos_nv <= 0.0 {
+            // only the interface of a transmitting surface is hit from
+            // behind
+            return match self.transmitter {
+                Some(ref d) => {
+                    let (dir, w, _) = d.sample_proj(normal, in_dir);
+                    (dir, Some(w))
+                }
+                None => (*in_dir, Some(color::BLACK)),
+            };
+        }
+
+        let lobes = self.lobes(cos_nv);
+        let e = rng::uniform();
+        if let Some(d) = self.transmitter {
+            if e < lobes.transmission {
+                let (dir, w, _) = d.sample_proj(normal, in_dir);
+                let k = self.transmission_weight() / lobes.transmission;
+                return (dir, Some(w * k as f32));
+            }
+        }
+        let e = e - lobes.transmission;
+        if e < lobes.diffuse {
+            return (math::hs_cosine_sampling(normal), None);
+        }
+        let half = if e < lobes.diffuse + lobes.specular {
+            let (ax, ay) = self.alpha_aniso();
+            let u1 = rng::uniform();
+            let u2 = rng::uniform();
+            let r = (u1 / (1.0 - u1)).sqrt();
+            let phi = 2.0 * PI * u2;
+            // a slope of the anisotropic GGX distribution
+            let (x, z) = self.frame(normal);
+            (x * (r * ax * phi.cos()) + *normal + z * (r * ay * phi.sin())).normalize()
+        } else {
+            let alpha2 = self.clearcoat_alpha().powi(2);
+            let u1 = rng::uniform();
+            let u2 = rng::uniform();
+            let cos = ((1.0 - alpha2.powf(1.0 - u1)) / (1.0 - alpha2)).max(0.0).sqrt();
+            let sin = (1.0 - cos * cos).max(0.0).sqrt();
+            let phi = 2.0 * PI * u2;
+            let h = Vector3f::new(sin * phi.cos(), cos, sin * phi.sin());
+            math::transform_basis_y(normal, &h)
+        };
+        (math::reflect_vec(&view, &half), None)
+    }
+}
+
+impl Bsdf for Principled {
+    fn radiance(&self) -> Option<Color> {
+        None
+    }
+
+    /// All lobes but the smooth transmission.
+    fn eval(
+        &self,
+        surface_normal: &Vector3f,
+        in_dir: &Vector3f,
+        out_dir: &Vector3f,
+    ) -> (Color, Real) {
+        let (_, fr, pdf) = self.eval_lobes(surface_normal, &(-in_dir), out_dir);
+        (fr.into(), pdf)
+    }
+
+    fn sample(&self, surface_normal: &Vector3f, in_dir: &Vector3f) -> (Vector3f, Color, Real) {
+        match self.sample_dir(surface_normal, in_dir) {
+            (dir, Some(w)) => (dir, w, surface_normal.dot(&dir).abs()),
+            (dir, None) => {
+                let (fr, pdf) = self.eval(surface_normal, in_dir, &dir);
+                if pdf > 0.0 {
+                    (dir, fr, pdf)
+                } else {
+                    (dir, color::BLACK, 1.0)
+                }
+            }
+        }
+    }
+
+    fn sample_proj(&self, surface_normal: &Vector3f, in_dir: &Vector3f) -> (Vector3f, Color, Real) {
+        match self.sample_dir(surface_normal, in_dir) {
+            (dir, Some(w)) => (dir, w, 1.0),
+            (dir, None) => {
+                let (fr, pdf) = self.eval_proj(surface_normal, in_dir, &dir);
+                if pdf > 0.0 {
+                    (dir, fr, pdf)
+                } else {
+                    (dir, color::BLACK, 1.0)
+                }
+            }
+        }
+    }
+
+    fn eval_diffuse(&self, normal: &Vector3f, in_dir: &Vector3f, out_dir: &Vector3f) -> Color {
+        self.eval_lobes(normal, &(-in_dir), out_dir).0.into()
+    }
+
+    fn ior(&self) -> Option<&Ior> {
+        self.transmitter.as_ref().map(|d| &d.ior)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsdf::testing;
+
+    #[test]
+    fn sample_matches_eval() {
+        let n = Vector3f::new(0.0, 1.0, 0.0);
+        // a glossier clearcoat is too narrow for the quadrature
+        let b = Principled::new(Color::new(0.8, 0.4, 0.2), 0.3, 0.5)
+            .with_anisotropic(0.5)
+            .with_sheen(0.5, 0.5)
+            .with_clearcoat(1.0, 0.0)
+            .with_subsurface(0.5);
+        assert!(b.ior().is_none());
+        for in_dir in &[Vector3f::new(0.6, -0.8, 0.0), Vector3f::new(0.0, -0.6, 0.8)] {
+            testing::assert_sampling_matches_eval(&b, &n, in_dir);
+            testing::assert_sampling_matches_eval(&b.with_tangent(n + *in_dir), &n, in_dir);
+        }
+    }
+
+    #[test]
+    fn highlight_follows_tangent() {
+        let n = Vector3f::new(0.0, 1.0, 0.0);
+        let in_dir = Vector3f::new(0.0, -1.0, 0.0);
+        let b = Principled::new(Color::new(0.8, 0.8, 0.8), 1.0, 0.5).with_anisotropic(1.0);
+        let along_x = Vector3f::new(0.5, 0.75f64.sqrt(), 0.0);
+        let along_z = Vector3f::new(0.0, 0.75f64.sqrt(), 0.5);
+
+        // the highlight is wider along the tangent
+        let x = b.with_tangent(Vector3f::new(1.0, 0.2, 0.0));
+        assert!(x.eval(&n, &in_dir, &along_x).0.r > x.eval(&n, &in_dir, &along_z).0.r);
+        let z = b.with_tangent(Vector3f::new(0.0, 0.0, 1.0));
+        assert!(z.eval(&n, &in_dir, &along_z).0.r > z.eval(&n, &in_dir, &along_x).0.r);
+    }
+
+    #[test]
+    fn lobe_probabilities() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        let lobes = Principled::new(white, 1.0, 0.5).lobes(0.5);
+        assert_eq!(lobes.diffuse, 0.0);
+        assert_eq!(lobes.transmission, 0.0);
+        assert!((lobes.specular - 1.0).abs() < 1e-9);
+
+        let glass = Principled::new(white, 0.0, 0.0).with_transmission(1.0);
+        let ior = glass.ior().map(|ior| ior.at(550.0)).unwrap();
+        assert!((ior - 1.5).abs() < 1e-9);
+        let lobes = glass.lobes(1.0);
+        assert_eq!(lobes.diffuse, 0.0);
+        assert_eq!(lobes.specular, 0.0);
+        assert_eq!(lobes.transmission, 1.0);
+    }
+}
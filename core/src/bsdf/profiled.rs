@@ -0,0 +1,108 @@
+use super::Ior;
+use {Bsdf, BsdfRef};
+use color::Color;
+use math::{Real, Vector3f};
+use photometry::IesProfile;
+use spectrum::Spd;
+use std::sync::Arc;
+
+/// `bsdf` with its emission shaped by an IES profile.
+pub struct Profiled<'a> {
+    pub bsdf: BsdfRef<'a>,
+    pub profile: Arc<IesProfile>,
+    /// 0° direction of the horizontal angles of the profile.
+    pub axis: Vector3f,
+}
+
+impl<'a> Profiled<'a> {
+    /// The 0° plane of the profile contains the x axis.
+    pub fn new(bsdf: BsdfRef<'a>, profile: Arc<IesProfile>) -> Profiled<'a> {
+        Profiled {
+            bsdf,
+            profile,
+            axis: Vector3f::new(1.0, 0.0, 0.0),
+        }
+    }
+
+    pub fn shared<B: Bsdf + 'a>(bsdf: B, profile: Arc<IesProfile>) -> Profiled<'a> {
+        Profiled::new(BsdfRef::Shared(Arc::new(bsdf)), profile)
+    }
+
+    /// Orients the fixture, its 0° plane contains `axis`.
+    pub fn with_axis(mut self, axis: Vector3f) -> Self {
+        self.axis = axis;
+        self
+    }
+}
+
+impl<'a> Bsdf for Profiled<'a> {
+    fn radiance(&self) -> Option<Color> {
+        self.bsdf.radiance()
+    }
+
+    fn radiance_dir(&self, surface_normal: &Vector3f, dir: &Vector3f) -> Option<Color> {
+        self.bsdf
+            .radiance_dir(surface_normal, dir)
+            .map(|e| e * self.profile.factor(surface_normal, &self.axis, dir) as f32)
+    }
+
+    fn emission_spd(&self) -> Option<&Spd> {
+        self.bsdf.emission_spd()
+    }
+
+    fn eval(
+        &self,
+        surface_normal: &Vector3f,
+        in_dir: &Vector3f,
+        out_dir: &Vector3f,
+    ) -> (Color, Real) {
+        self.bsdf.eval(surface_normal, in_dir, out_dir)
+    }
+
+    fn sample(&self, surface_normal: &Vector3f, in_dir: &Vector3f) -> (Vector3f, Color, Real) {
+        self.bsdf.sample(surface_normal, in_dir)
+    }
+
+    fn eval_proj(
+        &self,
+        surface_normal: &Vector3f,
+        in_dir: &Vector3f,
+        out_dir: &Vector3f,
+    ) -> (Color, Real) {
+        self.bsdf.eval_proj(surface_normal, in_dir, out_dir)
+    }
+
+    fn sample_proj(&self, surface_normal: &Vector3f, in_dir: &Vector3f) -> (Vector3f, Color, Real) {
+        self.bsdf.sample_proj(surface_normal, in_dir)
+    }
+
+    fn eval_diffuse(
+        &self,
+        surface_normal: &Vector3f,
+        in_dir: &Vector3f,
+        out_dir: &Vector3f,
+    ) -> Color {
+        self.bsdf.eval_diffuse(surface_normal, in_dir, out_dir)
+    }
+
+    fn albedo(&self, surface_normal: &Vector3f, in_dir: &Vector3f) -> Color {
+        self.bsdf.albedo(surface_normal, in_dir)
+    }
+
+    fn ior(&self) -> Option<&Ior> {
+        self.bsdf.ior()
+    }
+
+    fn is_specular(&self) -> bool {
+        self.bsdf.is_specular()
+    }
+
+    fn sample_wavelength(
+        &self,
+        surface_normal: &Vector3f,
+        in_dir: &Vector3f,
+        lambda: Real,
+    ) -> (Vector3f, Color, Real) {
+        self.bsdf.sample_wavelength(surface_normal, in_dir, lambda)
+    }
+}
@@ -0,0 +1,65 @@
+//! Checks shared by the BSDF tests.
+
+use Bsdf;
+use color::{self, Color};
+use math::{self, Real, Vector3f};
+use std::f64::consts::PI;
+
+/// `∫ f cos dω` over the hemisphere of `normal` for light from `in_dir`,
+/// by the midpoint rule in `(cos θ, φ)`.
+pub fn quadrature_albedo<B: Bsdf + ?Sized>(b: &B, normal: &Vector3f, in_dir: &Vector3f) -> Color {
+    const STEPS: usize = 512;
+    let (x, z) = math::basis_y(normal);
+    let mut sum = color::BLACK;
+    for i in 0..STEPS {
+        let cos = (i as Real + 0.5) / STEPS as Real;
+        let sin = (1.0 - cos * cos).sqrt();
+        let mut row = color::BLACK;
+        for j in 0..2 * STEPS {
+            let phi = (j as Real + 0.5) * PI / STEPS as Real;
+            let dir = x * (sin * phi.cos()) + *normal * cos + z * (sin * phi.sin());
+            let (f, _) = b.eval(normal, in_dir, &dir);
+            row += f * cos as f32;
+        }
+        sum += row;
+    }
+    // a cell covers `1 / STEPS` in cos θ and `π / STEPS` in φ
+    sum * (PI / (STEPS * STEPS) as Real) as f32
+}
+
+/// The same integral estimated from `samples` directions of
+/// `Bsdf::sample_proj`, which agrees only if the pdfs returned are the
+/// densities the directions are sampled with.
+pub fn sampled_albedo<B: Bsdf + ?Sized>(
+    b: &B,
+    normal: &Vector3f,
+    in_dir: &Vector3f,
+    samples: u32,
+) -> Color {
+    let mut sum = color::BLACK;
+    for _ in 0..samples {
+        let (_, fr, pdf) = b.sample_proj(normal, in_dir);
+        if pdf > 0.0 {
+            sum += fr * (1.0 / pdf) as f32;
+        }
+    }
+    sum * (1.0 / samples as f32)
+}
+
+/// Asserts that sampling `b` estimates the albedo given by `eval`.
+pub fn assert_sampling_matches_eval<B: Bsdf + ?Sized>(
+    b: &B,
+    normal: &Vector3f,
+    in_dir: &Vector3f,
+) {
+    let expected = quadrature_albedo(b, normal, in_dir);
+    let sampled = sampled_albedo(b, normal, in_dir, 200_000);
+    let close = |a: f32, b: f32| (a - b).abs() < 0.01 + 0.02 * b;
+    assert!(
+        close(sampled.r, expected.r) && close(sampled.g, expected.g)
+            && close(sampled.b, expected.b),
+        "sampled {:?}, integrated {:?}",
+        sampled,
+        expected
+    );
+}
@@ -0,0 +1,227 @@
+//! Thin lens model for depth of field.
+//!
+//! A `Perspective` projection with a `Lens` shoots its rays from a point
+//! sampled on the aperture towards the plane in focus, so only
+//! objects at `focus_distance` stay sharp. The shape of the aperture gives
+//! the shape of the out-of-focus highlights (bokeh).
+
+use math::Real;
+use std::cmp::Ordering::{Greater, Less};
+use std::cmp::min;
+use std::f64::consts::PI;
+use std::sync::Arc;
+use traits::TexView;
+
+/// Shape of the aperture, all shapes fit the unit disk and are scaled by
+/// the lens radius.
+#[derive(Clone, Debug)]
+pub enum Aperture {
+    Circle,
+    /// Regular polygon with `blades` sides, rotated by `rotation` radians.
+    Polygon { blades: u32, rotation: Real },
+    Image(Arc<ApertureImage>),
+}
+
+impl Aperture {
+    /// Maps `(u1, u2)` from `[0, 1)^2` to a point of the aperture.
+    pub fn sample(&self, u1: Real, u2: Real) -> (Real, Real) {
+        match *self {
+            Aperture::Circle => concentric_disk(u1, u2),
+            Aperture::Polygon { blades, rotation } => if blades < 3 {
+                concentric_disk(u1, u2)
+            } else {
+                polygon(blades, rotation, u1, u2)
+            },
+            Aperture::Image(ref img) => img.sample(u1, u2),
+        }
+    }
+}
+
+/// Aperture mask sampled proportionally to its brightness.
+///
+/// The image is stretched over the `[-1, 1]^2` square, its top row is at
+/// the top of the lens.
+#[derive(Clone, Debug)]
+pub struct ApertureImage {
+    width: usize,
+    height: usize,
+    rows_cdf: Vec<Real>,
+    cdf: Vec<Real>,
+}
+
+impl ApertureImage {
+    /// Returns `None` if the image has no bright pixels.
+    pub fn new<C>(mask: &TexView<C>) -> Option<ApertureImage>
+    where
+        Real: From<C>,
+    {
+        let (width, height) = (mask.width(), mask.height());
+        let mut rows_cdf = Vec::with_capacity(height);
+        let mut cdf = Vec::with_capacity(width * height);
+        let mut total = 0.0;
+        for j in 0..height {
+            let mut row_sum = 0.0;
+            for i in 0..width {
+                row_sum += Real::from(mask.pixel(i, j)).max(0.0);
+                cdf.push(row_sum);
+            }
+            if row_sum > 0.0 {
+                for c in &mut cdf[j * width..] {
+                    *c /= row_sum;
+                }
+            }
+            total += row_sum;
+            rows_cdf.push(total);
+        }
+        if total <= 0.0 {
+            return None;
+        }
+        for c in &mut rows_cdf {
+            *c /= total;
+        }
+
+        Some(ApertureImage {
+            width,
+            height,
+            rows_cdf,
+            cdf,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn sample(&self, u1: Real, u2: Real) -> (Real, Real) {
+        let (j, v) = sample_cdf(&self.rows_cdf, u1);
+        let (i, u) = sample_cdf(&self.cdf[j * self.width..(j + 1) * self.width], u2);
+        let x = (i as Real + u) / self.width as Real;
+        let y = (j as Real + v) / self.height as Real;
+        (x * 2.0 - 1.0, 1.0 - y * 2.0)
+    }
+}
+
+/// Thin lens, its radius and focus distance are in world units.
+#[derive(Clone, Debug)]
+pub struct Lens {
+    radius: Real,
+    focus_distance: Real,
+    aperture: Aperture,
+}
+
+impl Lens {
+    pub fn new(radius: Real, focus_distance: Real) -> Lens {
+        Lens {
+            radius,
+            focus_distance,
+            aperture: Aperture::Circle,
+        }
+    }
+
+    pub fn with_aperture(mut self, aperture: Aperture) -> Self {
+        self.aperture = aperture;
+        self
+    }
+
+    pub fn radius(&self) -> Real {
+        self.radius
+    }
+
+    pub fn focus_distance(&self) -> Real {
+        self.focus_distance
+    }
+
+    pub fn aperture(&self) -> &Aperture {
+        &self.aperture
+    }
+
+    /// Returns a point on the lens as offsets along the camera right and up
+    /// vectors.
+    pub fn sample(&self, u1: Real, u2: Real) -> (Real, Real) {
+        let (x, y) = self.aperture.sample(u1, u2);
+        (x * self.radius, y * self.radius)
+    }
+}
+
+/// Shirley-Chiu mapping of the unit square to the unit disk.
+pub fn concentric_disk(u1: Real, u2: Real) -> (Real, Real) {
+    let a = 2.0 * u1 - 1.0;
+    let b = 2.0 * u2 - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (r, phi) = if a.abs() > b.abs() {
+        (a, 0.25 * PI * (b / a))
+    } else {
+        (b, 0.5 * PI - 0.25 * PI * (a / b))
+    };
+    (r * phi.cos(), r * phi.sin())
+}
+
+fn polygon(blades: u32, rotation: Real, u1: Real, u2: Real) -> (Real, Real) {
+    let n = blades as Real;
+    let k = (u1 * n).floor().min(n - 1.0);
+    let u1 = u1 * n - k;
+    let phi0 = rotation + 2.0 * PI * k / n;
+    let phi1 = rotation + 2.0 * PI * (k + 1.0) / n;
+
+    let su = u1.sqrt();
+    let b0 = su * (1.0 - u2);
+    let b1 = su * u2;
+    (
+        b0 * phi0.cos() + b1 * phi1.cos(),
+        b0 * phi0.sin() + b1 * phi1.sin(),
+    )
+}
+
+/// Returns the index of the sampled bin and the position inside it.
+fn sample_cdf(cdf: &[Real], u: Real) -> (usize, Real) {
+    // first bin whose cdf is above `u`, so empty bins are never picked
+    let ix = match cdf.binary_search_by(|&c| if c <= u { Less } else { Greater }) {
+        Ok(ix) | Err(ix) => min(ix, cdf.len() - 1),
+    };
+    let lo = if ix > 0 { cdf[ix - 1] } else { 0.0 };
+    let width = cdf[ix] - lo;
+    let t = if width > 0.0 { (u - lo) / width } else { 0.5 };
+    (ix, t.max(0.0).min(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color::Luma;
+    use texture::Texture;
+
+    #[test]
+    fn polygon_inside_disk() {
+        let aperture = Aperture::Polygon {
+            blades: 6,
+            rotation: 0.3,
+        };
+        for i in 0..32 {
+            for j in 0..32 {
+                let (x, y) = aperture.sample(i as Real / 32.0, j as Real / 32.0);
+                assert!(x * x + y * y <= 1.0 + 1.0e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn image_samples_bright_pixels() {
+        let mut mask = Texture::<Luma<f32>>::new(4, 4);
+        mask.set_pixel(3, 0, Luma::new(1.0));
+        let img = ApertureImage::new::<Luma<f32>>(&mask).unwrap();
+        for &(u1, u2) in &[(0.0, 0.0), (0.5, 0.5), (0.99, 0.2)] {
+            let (x, y) = img.sample(u1, u2);
+            assert!(x >= 0.5 && x <= 1.0);
+            assert!(y >= 0.5 && y <= 1.0);
+        }
+
+        let dark = Texture::<Luma<f32>>::new(2, 2);
+        assert!(ApertureImage::new::<Luma<f32>>(&dark).is_none());
+    }
+}
@@ -0,0 +1,8 @@
+//! Lenses and projections generating the primary rays of a camera.
+
+mod lens;
+mod projection;
+
+pub use self::lens::{concentric_disk, Aperture, ApertureImage, Lens};
+pub use self::projection::{CameraFrame, CameraSample, Cubemap, Equirectangular, Fisheye, Orthographic,
+                           Perspective, Projection};
@@ -0,0 +1,382 @@
+//! Projections mapping points of the image to primary rays.
+//!
+//! Image points are given in pixels, `x` grows to the right and `y` down
+//! from the top left corner of the image.
+
+use super::Lens;
+use math::{Dot, Norm, Point3f, Ray3f, Real, Rotate, Vector3f};
+use motion::Isometry3f;
+use std::f64::consts::PI;
+use std::fmt::Debug;
+use traits::RenderCamera;
+use utils::consts;
+
+/// Position, orientation and image size of a camera.
+#[derive(Copy, Clone, Debug)]
+pub struct CameraFrame {
+    pub origin: Point3f,
+    pub right: Vector3f,
+    pub up: Vector3f,
+    pub forward: Vector3f,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CameraFrame {
+    pub fn new<C: RenderCamera + ?Sized>(camera: &C) -> CameraFrame {
+        CameraFrame {
+            origin: camera.pos(),
+            right: camera.right_vec().normalize(),
+            up: camera.up_vec().normalize(),
+            forward: camera.forward_vec().normalize(),
+            width: camera.width(),
+            height: camera.height(),
+        }
+    }
+
+    /// Frame of the same image seen from the camera-to-world transform
+    /// `trfm`.
+    pub fn with_transform(&self, trfm: &Isometry3f) -> CameraFrame {
+        CameraFrame {
+            origin: *trfm * Point3f::new(0.0, 0.0, 0.0),
+            right: trfm.rotate(&Vector3f::from(&consts::RIGHT_VEC)),
+            up: trfm.rotate(&Vector3f::from(&consts::UP_VEC)),
+            forward: trfm.rotate(&Vector3f::from(&consts::FORWARD_VEC)),
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Maps `(x, y)` in pixels to `[-1, 1]^2`, `y` pointing up.
+    fn ndc(&self, x: Real, y: Real) -> (Real, Real) {
+        (
+            2.0 * x / self.width as Real - 1.0,
+            1.0 - 2.0 * y / self.height as Real,
+        )
+    }
+
+    /// Inverse of `ndc`.
+    fn pixel(&self, nx: Real, ny: Real) -> (Real, Real) {
+        (
+            0.5 * (nx + 1.0) * self.width as Real,
+            0.5 * (1.0 - ny) * self.height as Real,
+        )
+    }
+
+    fn to_world(&self, x: Real, y: Real, z: Real) -> Vector3f {
+        self.right * x + self.up * y + self.forward * z
+    }
+}
+
+/// Camera end of a connection from a point of the scene, see
+/// `Projection::connect`.
+#[derive(Copy, Clone, Debug)]
+pub struct CameraSample {
+    /// Image point in pixels.
+    pub x: Real,
+    pub y: Real,
+    /// Point of the lens the scene point is connected to.
+    pub origin: Point3f,
+    /// Importance the camera emits toward the scene point, including the
+    /// cosine at the lens and the squared distance. A light path vertex
+    /// with throughput `beta` and BSDF value `f` adds
+    /// `beta * f * cos * importance` to the pixel per light path traced
+    /// for every pixel.
+    pub importance: Real,
+}
+
+/// Generates the primary rays of a camera.
+pub trait Projection: Debug + Send + Sync {
+    /// Ray through the image point `(x, y)`, `u` is a random sample in
+    /// `[0, 1]^2` used to sample the lens. Returns `None` for points that
+    /// are not covered by the projection.
+    fn ray(&self, frame: &CameraFrame, x: Real, y: Real, u: (Real, Real)) -> Option<Ray3f>;
+
+    /// Inverse of `ray`, the image point `p` is seen at. `u` samples the
+    /// lens. Returns `None` if `p` is outside of the image or the projection
+    /// does not support connections. Visibility is not checked.
+    fn connect(&self, _: &CameraFrame, _: &Point3f, _: (Real, Real)) -> Option<CameraSample> {
+        None
+    }
+
+    /// Solid angle density of the ray directions over the whole image at
+    /// `dir`, consistent with the importance of `connect`. `None` where
+    /// `connect` is not supported.
+    fn direction_pdf(&self, _: &CameraFrame, _: &Vector3f) -> Option<Real> {
+        None
+    }
+}
+
+/// Pinhole or thin lens perspective projection.
+#[derive(Clone, Debug)]
+pub struct Perspective {
+    fovy: Real,
+    lens: Option<Lens>,
+}
+
+impl Perspective {
+    pub fn new(fovy: Real) -> Perspective {
+        Perspective { fovy, lens: None }
+    }
+
+    pub fn with_lens(mut self, lens: Option<Lens>) -> Self {
+        self.lens = lens;
+        self
+    }
+}
+
+impl Projection for Perspective {
+    fn ray(&self, frame: &CameraFrame, x: Real, y: Real, u: (Real, Real)) -> Option<Ray3f> {
+        let (nx, ny) = frame.ndc(x, y);
+        let h = (0.5 * self.fovy).tan();
+        let w = h * frame.width as Real / frame.height as Real;
+        let dir = frame.to_world(nx * w, ny * h, 1.0);
+
+        let ray = match self.lens {
+            Some(ref lens) if lens.radius() > 0.0 => {
+                let (lx, ly) = lens.sample(u.0, u.1);
+                let focus = frame.origin + dir * lens.focus_distance();
+                let origin = frame.origin + frame.to_world(lx, ly, 0.0);
+                Ray3f::new(&origin, &(focus - origin).normalize())
+            }
+            _ => Ray3f::new(&frame.origin, &dir.normalize()),
+        };
+        Some(ray)
+    }
+
+    fn connect(&self, frame: &CameraFrame, p: &Point3f, u: (Real, Real)) -> Option<CameraSample> {
+        let h = (0.5 * self.fovy).tan();
+        let w = h * frame.width as Real / frame.height as Real;
+
+        let (origin, focus_distance) = match self.lens {
+            Some(ref lens) if lens.radius() > 0.0 => {
+                let (lx, ly) = lens.sample(u.0, u.1);
+                let origin = frame.origin + frame.to_world(lx, ly, 0.0);
+                (origin, Some(lens.focus_distance()))
+            }
+            _ => (frame.origin, None),
+        };
+        let v = *p - origin;
+        let z = v.dot(&frame.forward);
+        if z <= 0.0 {
+            return None;
+        }
+        // the ray from the lens through `p` is generated by the pixel whose
+        // pinhole ray crosses it on the plane of focus
+        let target = match focus_distance {
+            Some(d) => origin + v * (d / z) - frame.origin,
+            None => v,
+        };
+        let tz = target.dot(&frame.forward);
+        let nx = target.dot(&frame.right) / (tz * w);
+        let ny = target.dot(&frame.up) / (tz * h);
+        if nx.abs() > 1.0 || ny.abs() > 1.0 {
+            return None;
+        }
+
+        // We = 1 / (A cos^4) with A the image area at unit distance, the lens
+        // area cancels with the pdf of the lens sample
+        let dist2 = v.norm_squared();
+        let cos = z / dist2.sqrt();
+        let area = 4.0 * w * h;
+        let (x, y) = frame.pixel(nx, ny);
+        Some(CameraSample {
+            x,
+            y,
+            origin,
+            importance: 1.0 / (area * cos * cos * cos * dist2),
+        })
+    }
+
+    fn direction_pdf(&self, frame: &CameraFrame, dir: &Vector3f) -> Option<Real> {
+        let h = (0.5 * self.fovy).tan();
+        let w = h * frame.width as Real / frame.height as Real;
+        let cos = dir.dot(&frame.forward);
+        if cos <= 0.0 {
+            return None;
+        }
+        Some(1.0 / (4.0 * w * h * cos * cos * cos))
+    }
+}
+
+/// Parallel rays along the view direction.
+#[derive(Copy, Clone, Debug)]
+pub struct Orthographic {
+    /// Height of the visible area in world units.
+    pub height: Real,
+}
+
+impl Projection for Orthographic {
+    fn ray(&self, frame: &CameraFrame, x: Real, y: Real, _: (Real, Real)) -> Option<Ray3f> {
+        let (nx, ny) = frame.ndc(x, y);
+        let h = 0.5 * self.height;
+        let w = h * frame.width as Real / frame.height as Real;
+        let origin = frame.origin + frame.to_world(nx * w, ny * h, 0.0);
+        Some(Ray3f::new(&origin, &frame.forward))
+    }
+}
+
+/// Equidistant fisheye, the angle from the view direction grows linearly
+/// with the distance from the image center. The image circle fits the
+/// shorter side of the image.
+#[derive(Copy, Clone, Debug)]
+pub struct Fisheye {
+    /// Field of view across the image circle in radians, up to `2π`.
+    pub fov: Real,
+}
+
+impl Projection for Fisheye {
+    fn ray(&self, frame: &CameraFrame, x: Real, y: Real, _: (Real, Real)) -> Option<Ray3f> {
+        let size = frame.width.min(frame.height) as Real;
+        let px = (2.0 * x - frame.width as Real) / size;
+        let py = (frame.height as Real - 2.0 * y) / size;
+        let r = (px * px + py * py).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+        let theta = r * 0.5 * self.fov;
+        let phi = py.atan2(px);
+        let s = theta.sin();
+        let dir = frame.to_world(s * phi.cos(), s * phi.sin(), theta.cos());
+        Some(Ray3f::new(&frame.origin, &dir))
+    }
+}
+
+/// Latitude-longitude panorama covering the whole sphere, the view
+/// direction is at the image center.
+#[derive(Copy, Clone, Debug)]
+pub struct Equirectangular {
+    /// Interpupillary distance of an omni-directional stereo (ODS) image,
+    /// the left eye is rendered to the top half of the image and the right
+    /// eye to the bottom half. `None` renders a mono panorama.
+    pub stereo_ipd: Option<Real>,
+}
+
+impl Projection for Equirectangular {
+    fn ray(&self, frame: &CameraFrame, x: Real, y: Real, _: (Real, Real)) -> Option<Ray3f> {
+        let height = frame.height as Real;
+        let (v, eye) = match self.stereo_ipd {
+            Some(ipd) => if y < 0.5 * height {
+                (2.0 * y / height, -0.5 * ipd)
+            } else {
+                (2.0 * y / height - 1.0, 0.5 * ipd)
+            },
+            None => (y / height, 0.0),
+        };
+        let phi = (x / frame.width as Real - 0.5) * 2.0 * PI;
+        let theta = (0.5 - v) * PI;
+
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let dir = frame.to_world(
+            theta.cos() * sin_phi,
+            theta.sin(),
+            theta.cos() * cos_phi,
+        );
+        let origin = frame.origin + frame.to_world(cos_phi * eye, 0.0, -sin_phi * eye);
+        Some(Ray3f::new(&origin, &dir))
+    }
+}
+
+/// Six 90 degree faces side by side in the `+X, -X, +Y, -Y, +Z, -Z` order
+/// and orientation of OpenGL cube maps, with `X` the camera right, `Y` the
+/// camera up and `Z` the camera backward vector. The image should be six
+/// times wider than high.
+#[derive(Copy, Clone, Debug)]
+pub struct Cubemap;
+
+impl Projection for Cubemap {
+    fn ray(&self, frame: &CameraFrame, x: Real, y: Real, _: (Real, Real)) -> Option<Ray3f> {
+        let face_w = frame.width as Real / 6.0;
+        let face = ((x / face_w).floor() as i32).max(0).min(5);
+        let s = 2.0 * (x - face as Real * face_w) / face_w - 1.0;
+        let t = 2.0 * y / frame.height as Real - 1.0;
+
+        let (cx, cy, cz) = match face {
+            0 => (1.0, -t, -s),
+            1 => (-1.0, -t, s),
+            2 => (s, 1.0, t),
+            3 => (s, -1.0, -t),
+            4 => (s, -t, 1.0),
+            _ => (-s, -t, -1.0),
+        };
+        let dir = frame.to_world(cx, cy, -cz).normalize();
+        Some(Ray3f::new(&frame.origin, &dir))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::{ApproxEq, Dot};
+
+    fn frame(width: u32, height: u32) -> CameraFrame {
+        CameraFrame {
+            origin: Point3f::new(0.0, 0.0, 0.0),
+            right: Vector3f::new(1.0, 0.0, 0.0),
+            up: Vector3f::new(0.0, 1.0, 0.0),
+            forward: Vector3f::new(0.0, 0.0, -1.0),
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn image_center_looks_forward() {
+        let f = frame(60, 40);
+        let projections: Vec<Box<Projection>> = vec![
+            box Perspective::new(1.0),
+            box Orthographic { height: 2.0 },
+            box Fisheye { fov: PI },
+            box Equirectangular { stereo_ipd: None },
+        ];
+        for p in &projections {
+            let ray = p.ray(&f, 30.0, 20.0, (0.5, 0.5)).unwrap();
+            assert!(ray.dir.approx_eq(&f.forward));
+        }
+    }
+
+    #[test]
+    fn perspective_connect_inverts_ray() {
+        let f = frame(60, 40);
+        let lens = Lens::new(0.1, 4.0);
+        for p in &[Perspective::new(1.0), Perspective::new(1.0).with_lens(Some(lens))] {
+            let u = (0.3, 0.8);
+            let ray = p.ray(&f, 12.5, 30.25, u).unwrap();
+            let cs = p.connect(&f, &(ray.origin + ray.dir * 7.0), u).unwrap();
+            assert!((cs.x - 12.5).abs() < 1e-9 && (cs.y - 30.25).abs() < 1e-9);
+            assert!(cs.origin.approx_eq(&ray.origin));
+        }
+        assert!(
+            Perspective::new(1.0)
+                .connect(&f, &Point3f::new(0.0, 0.0, 1.0), (0.5, 0.5))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn perspective_direction_pdf_matches_importance() {
+        let f = frame(60, 40);
+        let p = Perspective::new(1.0);
+        let ray = p.ray(&f, 5.5, 33.0, (0.5, 0.5)).unwrap();
+        let cs = p.connect(&f, &(ray.origin + ray.dir * 3.0), (0.5, 0.5)).unwrap();
+        let pdf = p.direction_pdf(&f, &ray.dir).unwrap();
+        assert!((cs.importance * 9.0 - pdf).abs() < 1e-9 * pdf);
+    }
+
+    #[test]
+    fn fisheye_outside_circle() {
+        let f = frame(60, 40);
+        assert!(Fisheye { fov: PI }.ray(&f, 1.0, 1.0, (0.5, 0.5)).is_none());
+    }
+
+    #[test]
+    fn cubemap_faces() {
+        let f = frame(6 * 16, 16);
+        let back = Cubemap.ray(&f, 4.5 * 16.0, 8.0, (0.5, 0.5)).unwrap();
+        assert!(back.dir.approx_eq(&Vector3f::new(0.0, 0.0, 1.0)));
+        let front = Cubemap.ray(&f, 5.5 * 16.0, 8.0, (0.5, 0.5)).unwrap();
+        assert!(front.dir.approx_eq(&f.forward));
+        let top = Cubemap.ray(&f, 2.5 * 16.0, 8.0, (0.5, 0.5)).unwrap();
+        assert!(top.dir.dot(&f.up) > 0.99);
+    }
+}
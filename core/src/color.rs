@@ -11,6 +11,11 @@ use utils::clamp;
 
 pub type Color = Rgb;
 pub type Image = Texture<Rgba>;
+
+/// Relative luminance of a linear sRGB color.
+pub fn luminance(c: &Color) -> f32 {
+    0.2126 * c.r + 0.7152 * c.g + 0.0722 * c.b
+}
 // pub type RgbTexture<T: ColorChannel = f32> = Texture<Rgb<T>, [T; 3]>;
 // pub type RgbTexture4<T: ColorChannel = f32> = Texture<Rgb<T>, [T; 4]>;
 
@@ -781,3 +786,49 @@ impl_add!(LumaA, luma, a);
 impl_sub!(LumaA, luma, a);
 impl_mul!(LumaA, luma, a);
 impl_div!(LumaA, luma, a);
+
+
+impl_color!(Xyz, x, y, z);
+
+impl_from_self!(Xyz, f32, f64, x, y, z);
+impl_from_self!(Xyz, f64, f32, x, y, z);
+impl_from_scalar!(Xyz, x, y, z);
+impl_add!(Xyz, x, y, z);
+impl_sub!(Xyz, x, y, z);
+impl_mul!(Xyz, x, y, z);
+impl_div!(Xyz, x, y, z);
+
+impl Xyz<f32> {
+    /// CIE XYZ of a linear sRGB color, D65 white has `y = 1`.
+    pub fn from_linear_srgb(c: &Rgb<f32>) -> Self {
+        Xyz::new(
+            0.412_456_4 * c.r + 0.357_576_1 * c.g + 0.180_437_5 * c.b,
+            0.212_672_9 * c.r + 0.715_152_2 * c.g + 0.072_175 * c.b,
+            0.019_333_9 * c.r + 0.119_192 * c.g + 0.950_304_1 * c.b,
+        )
+    }
+
+    /// Linear sRGB, channels outside of the gamut are negative.
+    pub fn to_linear_srgb(&self) -> Rgb<f32> {
+        Rgb::new(
+            3.240_454_2 * self.x - 1.537_138_5 * self.y - 0.498_531_4 * self.z,
+            -0.969_266 * self.x + 1.876_010_8 * self.y + 0.041_556 * self.z,
+            0.055_643_4 * self.x - 0.204_025_9 * self.y + 1.057_225_2 * self.z,
+        )
+    }
+}
+
+/// CIE 1931 2° color matching functions at `lambda` nm, the multi-lobe fit
+/// of Wyman et al. 2013.
+pub fn cie_1931(lambda: f64) -> Xyz<f64> {
+    fn g(x: f64, mu: f64, sigma1: f64, sigma2: f64) -> f64 {
+        let t = (x - mu) / if x < mu { sigma1 } else { sigma2 };
+        (-0.5 * t * t).exp()
+    }
+    Xyz::new(
+        1.056 * g(lambda, 599.8, 37.9, 31.0) + 0.362 * g(lambda, 442.0, 16.0, 26.7) -
+            0.065 * g(lambda, 501.1, 20.4, 26.2),
+        0.821 * g(lambda, 568.8, 46.9, 40.5) + 0.286 * g(lambda, 530.9, 16.3, 31.1),
+        1.217 * g(lambda, 437.0, 11.8, 36.0) + 0.681 * g(lambda, 459.0, 26.0, 13.8),
+    )
+}
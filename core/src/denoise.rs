@@ -0,0 +1,329 @@
+//! Edge-avoiding À-Trous wavelet denoiser.
+//!
+//! The beauty image is divided by the albedo and the remaining illumination
+//! is blurred with a 5x5 B3-spline kernel whose taps are spread further
+//! apart every iteration. Each tap is weighted by how close its normal,
+//! depth and luminance are to the center pixel, the luminance tolerance
+//! being scaled by the estimated noise, so edges and details that are not
+//! noise are kept. The variance is filtered along with the image (SVGF,
+//! Schied et al. 2017).
+
+use color::{self, Color};
+use renderer::{Aov, AovBuffers};
+use texture::{TexView, Texture};
+
+const KERNEL: [f32; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+const EPSILON: f32 = 1e-4;
+
+/// Feature buffers guiding the filter, usually taken from the AOVs
+/// rendered together with the image.
+pub struct Features<'a> {
+    pub albedo: &'a Texture<Color>,
+    pub normal: &'a Texture<Color>,
+    pub depth: &'a Texture<Color>,
+    pub variance: &'a Texture<Color>,
+}
+
+impl<'a> Features<'a> {
+    /// Returns `None` unless `aovs` contain `Albedo`, `Normal`, `Depth` and
+    /// `Variance`.
+    pub fn from_aovs(aovs: &'a AovBuffers) -> Option<Features<'a>> {
+        match (
+            aovs.get(Aov::Albedo),
+            aovs.get(Aov::Normal),
+            aovs.get(Aov::Depth),
+            aovs.get(Aov::Variance),
+        ) {
+            (Some(albedo), Some(normal), Some(depth), Some(variance)) => Some(Features {
+                albedo,
+                normal,
+                depth,
+                variance,
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Denoiser {
+    iterations: u32,
+    sigma_luminance: f32,
+    sigma_normal: f32,
+    sigma_depth: f32,
+}
+
+impl Denoiser {
+    pub fn new() -> Denoiser {
+        Denoiser {
+            iterations: 5,
+            sigma_luminance: 4.0,
+            sigma_normal: 128.0,
+            sigma_depth: 1.0,
+        }
+    }
+
+    /// Number of À-Trous passes, the filter footprint is `4 * 2^iterations`
+    /// pixels wide.
+    pub fn with_iterations(mut self, iterations: u32) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Luminance tolerance in standard deviations of the noise.
+    pub fn with_sigma_luminance(mut self, sigma: f32) -> Self {
+        self.sigma_luminance = sigma;
+        self
+    }
+
+    /// Exponent of the normal weight `max(0, n_p . n_q)^sigma`.
+    pub fn with_sigma_normal(mut self, sigma: f32) -> Self {
+        self.sigma_normal = sigma;
+        self
+    }
+
+    /// Depth tolerance relative to the local depth gradient.
+    pub fn with_sigma_depth(mut self, sigma: f32) -> Self {
+        self.sigma_depth = sigma;
+        self
+    }
+
+    /// Filters `image` in place, the feature buffers must have its size.
+    pub fn denoise(&self, image: &mut TexView<Color>, features: &Features) {
+        let (w, h) = (image.width(), image.height());
+        assert!(
+            [features.albedo, features.normal, features.depth, features.variance]
+                .iter()
+                .all(|t| t.width() == w && t.height() == h)
+        );
+
+        let mut pixels = Vec::with_capacity(w * h);
+        for y in 0..h {
+            for x in 0..w {
+                let c: Color = image.pixel(x, y);
+                let a = features.albedo.pixel(x, y);
+                let lum_a = color::luminance(&a).max(EPSILON);
+                pixels.push(Pixel {
+                    illum: demodulate(c, a),
+                    variance: features.variance.pixel(x, y).r / (lum_a * lum_a),
+                    normal: features.normal.pixel(x, y),
+                    depth: features.depth.pixel(x, y).r,
+                    depth_grad: 0.0,
+                });
+            }
+        }
+        let mut depth_grad = Vec::with_capacity(w * h);
+        for y in 0..h {
+            for x in 0..w {
+                let d = |x: usize, y: usize| pixels[y * w + x].depth;
+                let dx = d(x.saturating_sub(1), y) - d((x + 1).min(w - 1), y);
+                let dy = d(x, y.saturating_sub(1)) - d(x, (y + 1).min(h - 1));
+                depth_grad.push(0.5 * dx.abs().max(dy.abs()));
+            }
+        }
+        for (p, g) in pixels.iter_mut().zip(depth_grad) {
+            p.depth_grad = g;
+        }
+        let mut variance = prefilter_variance(&pixels, w, h);
+
+        for i in 0..self.iterations {
+            let step = 1isize << i;
+            let mut illum = Vec::with_capacity(w * h);
+            let mut next_variance = Vec::with_capacity(w * h);
+            for y in 0..h {
+                for x in 0..w {
+                    let (c, v) = self.filter_pixel(&pixels, &variance, w, h, (x, y), step);
+                    illum.push(c);
+                    next_variance.push(v);
+                }
+            }
+            for (p, c) in pixels.iter_mut().zip(illum) {
+                p.illum = c;
+            }
+            variance = next_variance;
+        }
+
+        for y in 0..h {
+            for x in 0..w {
+                let a = features.albedo.pixel(x, y);
+                image.set_pixel(x, y, remodulate(pixels[y * w + x].illum, a));
+            }
+        }
+    }
+
+    fn filter_pixel(
+        &self,
+        pixels: &[Pixel],
+        variance: &[f32],
+        w: usize,
+        h: usize,
+        (x, y): (usize, usize),
+        step: isize,
+    ) -> (Color, f32) {
+        let ix = y * w + x;
+        let p = &pixels[ix];
+        if !p.is_hit() {
+            return (p.illum, variance[ix]);
+        }
+        let lum_p = color::luminance(&p.illum);
+        let lum_scale = self.sigma_luminance * variance[ix].max(0.0).sqrt() + EPSILON;
+
+        let mut sum = color::BLACK;
+        let mut sum_var = 0.0;
+        let mut sum_w = 0.0;
+        for j in -2..3 {
+            for i in -2..3 {
+                let qx = x as isize + i * step;
+                let qy = y as isize + j * step;
+                if qx < 0 || qy < 0 || qx >= w as isize || qy >= h as isize {
+                    continue;
+                }
+                let qix = qy as usize * w + qx as usize;
+                let q = &pixels[qix];
+                if !q.is_hit() {
+                    continue;
+                }
+
+                let k = KERNEL[i.abs() as usize] * KERNEL[j.abs() as usize];
+                let cos = p.normal.r * q.normal.r + p.normal.g * q.normal.g
+                    + p.normal.b * q.normal.b;
+                let w_normal = cos.max(0.0).powf(self.sigma_normal);
+                let dist = ((i * i + j * j) as f32).sqrt() * step as f32;
+                let w_depth = -(p.depth - q.depth).abs()
+                    / (self.sigma_depth * p.depth_grad * dist + EPSILON);
+                let w_lum = -(lum_p - color::luminance(&q.illum)).abs() / lum_scale;
+                let weight = k * w_normal * (w_depth + w_lum).exp();
+
+                sum += q.illum * weight;
+                sum_var += weight * weight * variance[qix];
+                sum_w += weight;
+            }
+        }
+
+        // the center tap always has a non-zero weight
+        (sum * (1.0 / sum_w), sum_var / (sum_w * sum_w))
+    }
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Pixel {
+    illum: Color,
+    variance: f32,
+    normal: Color,
+    depth: f32,
+    depth_grad: f32,
+}
+
+impl Pixel {
+    /// Samples that missed the scene have a zero normal.
+    fn is_hit(&self) -> bool {
+        self.normal != color::BLACK
+    }
+}
+
+/// Blurs the variance with a 3x3 Gaussian, a single pixel estimate from a
+/// few samples is itself too noisy to guide the filter.
+fn prefilter_variance(pixels: &[Pixel], w: usize, h: usize) -> Vec<f32> {
+    const GAUSS: [f32; 2] = [1.0 / 2.0, 1.0 / 4.0];
+    let mut res = Vec::with_capacity(w * h);
+    for y in 0..h {
+        for x in 0..w {
+            let mut sum = 0.0;
+            let mut sum_w = 0.0;
+            for j in -1..2 {
+                for i in -1..2 {
+                    let qx = x as isize + i;
+                    let qy = y as isize + j;
+                    if qx < 0 || qy < 0 || qx >= w as isize || qy >= h as isize {
+                        continue;
+                    }
+                    let weight = GAUSS[i.abs() as usize] * GAUSS[j.abs() as usize];
+                    sum += weight * pixels[qy as usize * w + qx as usize].variance;
+                    sum_w += weight;
+                }
+            }
+            res.push(sum / sum_w);
+        }
+    }
+    res
+}
+
+fn demodulate(c: Color, albedo: Color) -> Color {
+    let d = |c: f32, a: f32| if a > EPSILON { c / a } else { c };
+    Color::new(d(c.r, albedo.r), d(c.g, albedo.g), d(c.b, albedo.b))
+}
+
+fn remodulate(c: Color, albedo: Color) -> Color {
+    let m = |c: f32, a: f32| if a > EPSILON { c * a } else { c };
+    Color::new(m(c.r, albedo.r), m(c.g, albedo.g), m(c.b, albedo.b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features(w: usize, h: usize, variance: f32) -> [Texture<Color>; 4] {
+        let mut normal = Texture::new(w, h);
+        let mut albedo = Texture::new(w, h);
+        let mut depth = Texture::new(w, h);
+        let mut var = Texture::new(w, h);
+        for y in 0..h {
+            for x in 0..w {
+                normal.set_pixel(x, y, Color::new(0.0, 0.0, 1.0));
+                albedo.set_pixel(x, y, Color::from(0.5));
+                depth.set_pixel(x, y, Color::from(1.0));
+                var.set_pixel(x, y, Color::from(variance));
+            }
+        }
+        [albedo, normal, depth, var]
+    }
+
+    #[test]
+    fn smooths_noise_on_flat_surface() {
+        let (w, h) = (16, 16);
+        let f = features(w, h, 0.01);
+        let mut img = Texture::<Color>::new(w, h);
+        for y in 0..h {
+            for x in 0..w {
+                let c = if (x + y) % 2 == 0 { 0.4 } else { 0.6 };
+                img.set_pixel(x, y, Color::from(c));
+            }
+        }
+        let features = Features {
+            albedo: &f[0],
+            normal: &f[1],
+            depth: &f[2],
+            variance: &f[3],
+        };
+        Denoiser::new().denoise(&mut img, &features);
+        let c = img.pixel(8, 8);
+        assert!((c.g - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn keeps_normal_edges() {
+        let (w, h) = (16, 16);
+        let mut f = features(w, h, 0.25);
+        let mut img = Texture::<Color>::new(w, h);
+        for y in 0..h {
+            for x in 8..w {
+                f[1].set_pixel(x, y, Color::new(1.0, 0.0, 0.0));
+                img.set_pixel(x, y, Color::from(1.0));
+            }
+        }
+        let features = Features {
+            albedo: &f[0],
+            normal: &f[1],
+            depth: &f[2],
+            variance: &f[3],
+        };
+        Denoiser::new().denoise(&mut img, &features);
+        assert!(img.pixel(7, 8).g < 1e-3);
+        assert!((img.pixel(8, 8).g - 1.0).abs() < 1e-3);
+    }
+}
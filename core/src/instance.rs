@@ -0,0 +1,149 @@
+use {Surface, SurfacePoint};
+use aabb::{Aabb3, HasBounds};
+use color::Color;
+use math::{self, Inverse, Point2f, Point3f, Ray3f, Real, Rotate, Vector3f};
+use motion::{Isometry3f, Motion};
+
+/// Places a surface in the scene by a transform that may change during the
+/// shutter interval.
+pub struct Instance<T> {
+    surface: T,
+    motion: Motion,
+}
+
+impl<T> Instance<T>
+where
+    T: Surface + HasBounds,
+{
+    pub fn new(surface: T, motion: Motion) -> Instance<T> {
+        Instance { surface, motion }
+    }
+
+    pub fn surface(&self) -> &T {
+        &self.surface
+    }
+
+    pub fn motion(&self) -> &Motion {
+        &self.motion
+    }
+
+    fn to_world<'s>(&'s self, trfm: &Isometry3f, sp: SurfacePoint<'s>) -> SurfacePoint<'s> {
+        SurfacePoint {
+            position: *trfm * sp.position,
+            normal: trfm.rotate(&sp.normal),
+            bsdf: sp.bsdf,
+            surface: self,
+        }
+    }
+}
+
+fn inverse(trfm: &Isometry3f) -> Isometry3f {
+    trfm.inverse().unwrap_or_else(math::one)
+}
+
+impl<T> Surface for Instance<T>
+where
+    T: Surface + HasBounds,
+{
+    fn intersection(&self, ray: &Ray3f) -> Option<(Real, SurfacePoint)> {
+        let trfm = self.motion.at(ray.time);
+        let inv = inverse(&trfm);
+        let local_ray = Ray3f::with_time(&(inv * ray.origin), &inv.rotate(&ray.dir), ray.time);
+        self.surface
+            .intersection(&local_ray)
+            .map(|(t, sp)| (t, self.to_world(&trfm, sp)))
+    }
+
+    #[inline]
+    fn is_emitter(&self) -> bool {
+        self.surface.is_emitter()
+    }
+
+    #[inline]
+    fn total_radiance(&self) -> Option<Color> {
+        self.surface.total_radiance()
+    }
+
+    #[inline]
+    fn area(&self) -> Real {
+        self.surface.area()
+    }
+
+    fn normal_at(&self, pos: &Point3f, time: Real) -> Vector3f {
+        let trfm = self.motion.at(time);
+        trfm.rotate(&self.surface.normal_at(&(inverse(&trfm) * *pos), time))
+    }
+
+    fn uv(&self, pos: &Point3f, time: Real) -> Option<Point2f> {
+        self.surface
+            .uv(&(inverse(&self.motion.at(time)) * *pos), time)
+    }
+
+    fn barycentric(&self, pos: &Point3f, time: Real) -> Option<(Real, Real, Real)> {
+        self.surface
+            .barycentric(&(inverse(&self.motion.at(time)) * *pos), time)
+    }
+
+    fn material_key(&self) -> usize {
+        self.surface.material_key()
+    }
+
+    fn sample_surface_uniform(&self, time: Real) -> (SurfacePoint, Real) {
+        let (sp, pdf) = self.surface.sample_surface_uniform(time);
+        (self.to_world(&self.motion.at(time), sp), pdf)
+    }
+
+    fn sample_surface_p(
+        &self,
+        view_point: (&Point3f, &Vector3f),
+        time: Real,
+    ) -> (SurfacePoint, Real) {
+        let trfm = self.motion.at(time);
+        let inv = inverse(&trfm);
+        let pos = inv * *view_point.0;
+        let norm = inv.rotate(view_point.1);
+        let (sp, pdf) = self.surface.sample_surface_p((&pos, &norm), time);
+        (self.to_world(&trfm, sp), pdf)
+    }
+
+    fn pdf_p(
+        &self,
+        point_at_surface: (&Point3f, &Vector3f),
+        view_point: (&Point3f, &Vector3f),
+        time: Real,
+    ) -> Real {
+        let inv = inverse(&self.motion.at(time));
+        let (sp, sn) = (inv * *point_at_surface.0, inv.rotate(point_at_surface.1));
+        let (vp, vn) = (inv * *view_point.0, inv.rotate(view_point.1));
+        self.surface.pdf_p((&sp, &sn), (&vp, &vn), time)
+    }
+}
+
+impl<T> HasBounds for Instance<T>
+where
+    T: Surface + HasBounds,
+{
+    fn aabb(&self) -> Aabb3 {
+        self.motion.bounds(&self.surface.aabb())
+    }
+}
+
+impl<'a, T> AsRef<Surface + 'a> for Instance<T>
+where
+    T: Surface + HasBounds + 'a,
+{
+    #[inline]
+    fn as_ref(&self) -> &(Surface + 'a) {
+        self
+    }
+}
+
+impl<'a, T> AsRef<Surface + 'a> for Box<Instance<T>>
+where
+    T: Surface + HasBounds + 'a,
+{
+    #[inline]
+    fn as_ref(&self) -> &(Surface + 'a) {
+        &**self
+    }
+}
@@ -24,17 +24,26 @@ pub mod scenehandler;
 pub mod bsdf;
 pub mod renderer;
 pub mod color;
+pub mod spectrum;
+pub mod photometry;
 pub mod aabb;
 pub mod mesh;
 pub mod texture;
+pub mod camera;
+pub mod motion;
+pub mod instance;
+pub mod denoise;
 
 
 pub use self::bsdf::BsdfRef;
 pub use self::color::{Color, Image};
 
 use self::math::{Point3f, Vector3f};
+pub use self::instance::Instance;
 pub use self::mesh::Mesh;
+pub use self::motion::Motion;
 pub use self::polygon::{Polygon, PolygonR, PolygonS};
+pub use self::renderer::{AovSet, TileOrder};
 pub use self::polygon::material;
 pub use self::polygon::vertex;
 pub use self::scenehandler::ShapeList;
@@ -59,6 +68,10 @@ pub struct RenderSettings {
 
     render_chunk: (u32, u32),
     threads_num: u32,
+    tile_order: TileOrder,
+    passes_in_flight: u32,
+    seed: u64,
+    aovs: AovSet,
 }
 
 impl RenderSettings {
@@ -70,6 +83,10 @@ impl RenderSettings {
 
             render_chunk: (1, 1),
             threads_num: 1,
+            tile_order: TileOrder::Spiral,
+            passes_in_flight: 1,
+            seed: 0,
+            aovs: AovSet::new(),
         }
     }
 
@@ -79,4 +96,52 @@ impl RenderSettings {
 
         *self
     }
+
+    pub fn with_tile_order(&mut self, order: TileOrder) -> RenderSettings {
+        self.tile_order = order;
+
+        *self
+    }
+
+    /// Number of passes a worker renders for a tile before handing it back.
+    pub fn with_passes_in_flight(&mut self, passes: u32) -> RenderSettings {
+        self.passes_in_flight = if passes > 0 { passes } else { 1 };
+
+        *self
+    }
+
+    /// Seeds the samples of the tiled renders, so the same settings render
+    /// the same image and a resumed render continues where it stopped.
+    pub fn with_seed(&mut self, seed: u64) -> RenderSettings {
+        self.seed = seed;
+
+        *self
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Selects the AOVs written by `Renderer::render_scene_aovs`.
+    pub fn with_aovs(&mut self, aovs: AovSet) -> RenderSettings {
+        self.aovs = aovs;
+
+        *self
+    }
+
+    pub fn aovs(&self) -> AovSet {
+        self.aovs
+    }
+
+    /// Hash of the settings that change the rendered image. The number of
+    /// samples and the threading setup are not included, so a render can be
+    /// resumed with more samples or on another machine.
+    pub fn fingerprint(&self) -> u64 {
+        use std::hash::Hasher;
+        let mut h = utils::FnvHasher::default();
+        h.write_u32(self.path_depth);
+        h.write_u32(self.fog_density.to_bits());
+        h.write_u64(self.seed);
+        h.finish()
+    }
 }
@@ -15,6 +15,7 @@ pub type Point4f = Point4<Real>;
 use std::f32::EPSILON;
 pub const FLOAT_EPSILON: Real = EPSILON as Real;
 use color::Rgb;
+use utils::rng;
 
 #[derive(Copy, Clone, Debug)]
 pub struct Ray3<F>
@@ -23,6 +24,8 @@ where
 {
     pub origin: Point3<F>,
     pub dir: Vector3<F>,
+    /// Moment inside the camera shutter interval the ray is traced at.
+    pub time: Real,
 }
 
 impl<F> Ray3<F>
@@ -30,9 +33,14 @@ where
     F: Copy + Clone,
 {
     pub fn new(origin: &Point3<F>, dir: &Vector3<F>) -> Ray3<F> {
+        Self::with_time(origin, dir, 0.0)
+    }
+
+    pub fn with_time(origin: &Point3<F>, dir: &Vector3<F>, time: Real) -> Ray3<F> {
         Ray3 {
             origin: *origin,
             dir: *dir,
+            time,
         }
     }
 }
@@ -104,6 +112,23 @@ pub fn triangle_area(v0: &Point3f, v1: &Point3f, v2: &Point3f) -> Real {
     0.5 * b.cross(&c).norm()
 }
 
+/// Barycentric coordinates `(w0, w1, w2)` of `p` projected on the
+/// triangle plane.
+pub fn barycentric(v0: &Point3f, v1: &Point3f, v2: &Point3f, p: &Point3f) -> (Real, Real, Real) {
+    let e1 = *v1 - *v0;
+    let e2 = *v2 - *v0;
+    let ep = *p - *v0;
+    let (d11, d12, d22) = (e1.dot(&e1), e1.dot(&e2), e2.dot(&e2));
+    let (dp1, dp2) = (ep.dot(&e1), ep.dot(&e2));
+    let denom = d11 * d22 - d12 * d12;
+    if denom == 0.0 {
+        return (1.0, 0.0, 0.0);
+    }
+    let w1 = (d22 * dp1 - d12 * dp2) / denom;
+    let w2 = (d11 * dp2 - d12 * dp1) / denom;
+    (1.0 - w1 - w2, w1, w2)
+}
+
 #[inline]
 pub fn triangle_normal(v0: &Point3f, v1: &Point3f, v2: &Point3f) -> Vector3f {
     let a = *v1 - *v0;
@@ -111,10 +136,6 @@ pub fn triangle_normal(v0: &Point3f, v1: &Point3f, v2: &Point3f) -> Vector3f {
     b.cross(&a).normalize()
 }
 
-use rand;
-use rand::{random, Closed01};
-use rand::distributions::{IndependentSample, Range};
-
 
 pub fn hs_uniform_sampling(hemisphere_normal: &Vector3f) -> Vector3f {
     let vec = sph_uniform_sampling();
@@ -131,10 +152,8 @@ pub fn hs_cosine_sampling(n: &Vector3f) -> Vector3f {
     //use std::f32::{cos, sin};
     use std::f64::consts::PI;
 
-    let mut rng = rand::thread_rng();
-    let r01 = Range::new(0.0, 1.0 as Real);
-    let u1 = r01.ind_sample(&mut rng);
-    let u2 = r01.ind_sample(&mut rng);
+    let u1 = rng::uniform();
+    let u2 = rng::uniform();
 
     let theta = (1.0 - u1).sqrt().acos();
     let phi = 2.0 * (PI as Real) * u2;
@@ -165,9 +184,9 @@ pub fn hs_cosine_sampling(n: &Vector3f) -> Vector3f {
 
 }
 
-pub fn transform_basis_y(up: &Vector3f, vec: &Vector3f) -> Vector3f {
-    let y = *up;
-    let mut h = y;
+/// Axes `x` and `z` completing `y` to an orthonormal basis.
+pub fn basis_y(y: &Vector3f) -> (Vector3f, Vector3f) {
+    let mut h = *y;
 
     if h.x.abs() <= h.y.abs() && h.x.abs() <= h.z.abs() {
         h.x = 1.0;
@@ -177,8 +196,14 @@ pub fn transform_basis_y(up: &Vector3f, vec: &Vector3f) -> Vector3f {
         h.z = 1.0;
     }
 
-    let x = h.cross(&y).normalize();
-    let z = x.cross(&y).normalize();
+    let x = h.cross(y).normalize();
+    let z = x.cross(y).normalize();
+    (x, z)
+}
+
+pub fn transform_basis_y(up: &Vector3f, vec: &Vector3f) -> Vector3f {
+    let y = *up;
+    let (x, z) = basis_y(&y);
 
     let dir = x * vec.x + y * vec.y + z * vec.z;
 
@@ -216,9 +241,9 @@ pub fn calc_tangent(
 pub fn sph_uniform_sampling() -> Vector3f {
     let vec;
     loop {
-        let Closed01(mut x) = random::<Closed01<Real>>();
-        let Closed01(mut y) = random::<Closed01<Real>>();
-        let Closed01(mut z) = random::<Closed01<Real>>();
+        let mut x = rng::uniform();
+        let mut y = rng::uniform();
+        let mut z = rng::uniform();
         x -= 0.5;
         y -= 0.5;
         z -= 0.5;
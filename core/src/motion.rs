@@ -0,0 +1,159 @@
+//! Rigid transforms changing over time, used for motion blur.
+
+use aabb::Aabb3;
+use math::{self, Inverse, Isometry3, Norm, Point3f, Real, Rotation, Rotation3};
+use std::f64::consts::PI;
+
+pub type Isometry3f = Isometry3<Real>;
+
+/// Largest rotation between two points where the bounds are sampled.
+const BOUNDS_STEP_ANGLE: Real = PI / 8.0;
+
+/// Object-to-world transform given at key times and interpolated between
+/// them, linearly for the translation and along the shortest arc for the
+/// rotation. Outside of the keys the first or the last one is used.
+#[derive(Clone, Debug)]
+pub struct Motion {
+    keys: Vec<(Real, Isometry3f)>,
+}
+
+impl Motion {
+    pub fn fixed(trfm: Isometry3f) -> Motion {
+        Motion {
+            keys: vec![(0.0, trfm)],
+        }
+    }
+
+    pub fn linear(t0: Real, from: Isometry3f, t1: Real, to: Isometry3f) -> Motion {
+        Motion::keyframed(vec![(t0, from), (t1, to)])
+    }
+
+    pub fn keyframed(mut keys: Vec<(Real, Isometry3f)>) -> Motion {
+        assert!(!keys.is_empty());
+        keys.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        Motion { keys }
+    }
+
+    pub fn keys(&self) -> &[(Real, Isometry3f)] {
+        &self.keys
+    }
+
+    pub fn is_static(&self) -> bool {
+        self.keys.len() == 1
+    }
+
+    pub fn at(&self, time: Real) -> Isometry3f {
+        let last = self.keys.len() - 1;
+        if time <= self.keys[0].0 {
+            return self.keys[0].1;
+        }
+        if time >= self.keys[last].0 {
+            return self.keys[last].1;
+        }
+        let ix = self.keys.iter().position(|k| k.0 > time).unwrap();
+        let (t0, ref a) = self.keys[ix - 1];
+        let (t1, ref b) = self.keys[ix];
+        interpolate(a, b, (time - t0) / (t1 - t0))
+    }
+
+    /// Bounds of `aabb`, given in object space, over the whole motion.
+    pub fn bounds(&self, aabb: &Aabb3) -> Aabb3 {
+        let corners = corners(aabb);
+        let radius = corners
+            .iter()
+            .map(|c| c.as_vector().norm())
+            .fold(0.0, Real::max);
+
+        let mut bounds = transform_aabb(&self.keys[0].1, &corners);
+        for w in self.keys.windows(2) {
+            let (a, b) = (&w[0].1, &w[1].1);
+            let angle = rotation_between(a, b).norm();
+            let steps = (angle / BOUNDS_STEP_ANGLE).ceil().max(1.0);
+            for i in 1..(steps as usize + 1) {
+                let trfm = interpolate(a, b, i as Real / steps);
+                bounds.merge(&transform_aabb(&trfm, &corners));
+            }
+
+            // points move along arcs between the samples, cover the arc height
+            let pad = radius * (1.0 - (0.5 * angle / steps).cos());
+            if pad > 0.0 {
+                let d = math::Vector3::new(pad, pad, pad);
+                bounds = Aabb3::new(*bounds.mins() - d, *bounds.maxs() + d);
+            }
+        }
+        bounds
+    }
+}
+
+fn rotation_between(a: &Isometry3f, b: &Isometry3f) -> math::Vector3<Real> {
+    let a_inv = a.rotation.inverse().unwrap_or_else(math::one);
+    (a_inv * b.rotation).rotation()
+}
+
+fn interpolate(a: &Isometry3f, b: &Isometry3f, s: Real) -> Isometry3f {
+    let rotation = a.rotation * Rotation3::new(rotation_between(a, b) * s);
+    let translation = a.translation + (b.translation - a.translation) * s;
+    Isometry3 {
+        translation,
+        rotation,
+    }
+}
+
+fn corners(aabb: &Aabb3) -> [Point3f; 8] {
+    let (p0, p1) = (aabb.mins(), aabb.maxs());
+    [
+        Point3f::new(p0.x, p0.y, p0.z),
+        Point3f::new(p1.x, p0.y, p0.z),
+        Point3f::new(p0.x, p1.y, p0.z),
+        Point3f::new(p1.x, p1.y, p0.z),
+        Point3f::new(p0.x, p0.y, p1.z),
+        Point3f::new(p1.x, p0.y, p1.z),
+        Point3f::new(p0.x, p1.y, p1.z),
+        Point3f::new(p1.x, p1.y, p1.z),
+    ]
+}
+
+fn transform_aabb(trfm: &Isometry3f, corners: &[Point3f; 8]) -> Aabb3 {
+    let p = *trfm * corners[0];
+    let mut bounds = Aabb3::new(p, p);
+    for c in &corners[1..] {
+        let p = *trfm * *c;
+        bounds.merge(&Aabb3::new(p, p));
+    }
+    bounds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::{ApproxEq, Vector3};
+
+    fn translation(x: Real) -> Isometry3f {
+        Isometry3::new(Vector3::new(x, 0.0, 0.0), math::zero())
+    }
+
+    #[test]
+    fn linear_translation() {
+        let m = Motion::linear(0.0, translation(0.0), 1.0, translation(4.0));
+        assert!(m.at(0.25).translation.approx_eq(&Vector3::new(1.0, 0.0, 0.0)));
+        assert!(m.at(2.0).translation.approx_eq(&Vector3::new(4.0, 0.0, 0.0)));
+
+        let aabb = Aabb3::new(Point3f::new(-1.0, -1.0, -1.0), Point3f::new(1.0, 1.0, 1.0));
+        let b = m.bounds(&aabb);
+        assert!(b.mins().approx_eq(&Point3f::new(-1.0, -1.0, -1.0)));
+        assert!(b.maxs().approx_eq(&Point3f::new(5.0, 1.0, 1.0)));
+    }
+
+    #[test]
+    fn rotation_bounds_cover_arc() {
+        let from = Isometry3::new(math::zero(), math::zero());
+        let to = Isometry3::new(math::zero(), Vector3::new(0.0, 0.5 * PI, 0.0));
+        let m = Motion::linear(0.0, from, 1.0, to);
+        let p = Point3f::new(2.0, 0.0, 0.0);
+        let b = m.bounds(&Aabb3::new(p, p));
+        for i in 0..17 {
+            let q = m.at(i as Real / 16.0) * p;
+            assert!(b.contains_point(&q));
+        }
+    }
+}
@@ -0,0 +1,396 @@
+//! Photometric units of emitters and IES LM-63 light profiles.
+//!
+//! Radiance is in nits, cd/m², taking the luminance of an RGB radiance. An
+//! emitter is a Lambertian surface emitting from its front side, so a
+//! luminance `L` over an area `A` gives a luminous flux of `π L A`. A
+//! profile shapes the emission by direction keeping the flux, so
+//! `Surface::total_radiance` still gives the power of an emitter, up to the
+//! intensity lost within about half a degree of the horizon, see
+//! `IesProfile::factor`.
+
+use Surface;
+use color;
+use math::{self, Cross, Dot, Norm, Real, Vector3f};
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Output of an emitter.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Photometric {
+    /// Luminance in cd/m².
+    Nits(Real),
+    /// Luminous intensity in cd along the normal of the emitter.
+    Candela(Real),
+    /// Luminous flux in lm.
+    Lumens(Real),
+}
+
+impl Photometric {
+    /// Luminance of an emitter of `area` m² with this output.
+    pub fn luminance(&self, area: Real) -> Real {
+        match *self {
+            Photometric::Nits(l) => l,
+            Photometric::Candela(i) => i / area,
+            Photometric::Lumens(flux) => flux / (PI as Real * area),
+        }
+    }
+
+    /// Luminance of `surfaces` emitting this output together.
+    pub fn luminance_of(&self, surfaces: &[&Surface]) -> Real {
+        self.luminance(surfaces.iter().map(|s| s.area()).sum())
+    }
+}
+
+/// Luminous flux of `surfaces` in lm, from their total radiance.
+pub fn luminous_flux(surfaces: &[&Surface]) -> Real {
+    let sum: Real = surfaces
+        .iter()
+        .filter_map(|s| s.total_radiance())
+        .map(|e| color::luminance(&e) as Real)
+        .sum();
+    PI as Real * sum
+}
+
+/// Smallest cosine to the normal a profile is evaluated at, bounds the
+/// radiance at grazing angles. Below it the emitted intensity falls off
+/// with the cosine instead of following the profile.
+const MIN_COS: Real = 0.01;
+
+/// Candela distribution of a fixture from an IES LM-63 file, type C
+/// photometry. The nadir, vertical angle 0°, is along the normal of the
+/// emitter.
+#[derive(Clone, Debug)]
+pub struct IesProfile {
+    /// Vertical angles in degrees.
+    vertical: Vec<Real>,
+    /// Horizontal angles in degrees.
+    horizontal: Vec<Real>,
+    /// Candela for each horizontal angle at the vertical angles.
+    candela: Vec<Vec<Real>>,
+    /// Scales the intensity over the cosine to keep the flux.
+    norm: Real,
+}
+
+/// Whitespace or comma separated numbers.
+struct Tokens<I> {
+    iter: I,
+}
+
+impl<'a, I: Iterator<Item = &'a str>> Tokens<I> {
+    fn next(&mut self) -> io::Result<Real> {
+        match self.iter.next() {
+            Some(t) => t.parse::<Real>()
+                .map_err(|_| invalid_data(&format!("invalid number `{}`", t))),
+            None => Err(invalid_data("unexpected end of data")),
+        }
+    }
+
+    fn next_vec(&mut self, n: usize) -> io::Result<Vec<Real>> {
+        let mut v = Vec::with_capacity(n);
+        for _ in 0..n {
+            v.push(self.next()?);
+        }
+        Ok(v)
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn is_ascending(angles: &[Real]) -> bool {
+    angles.windows(2).all(|w| w[0] < w[1])
+}
+
+impl IesProfile {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<IesProfile> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> io::Result<IesProfile> {
+        let mut lines = text.lines();
+        // keywords up to the tilt line
+        let tilt = loop {
+            match lines.next() {
+                Some(line) => if line.trim_left().starts_with("TILT=") {
+                    break line.trim()["TILT=".len()..].trim().to_string();
+                },
+                None => return Err(invalid_data("missing TILT line")),
+            }
+        };
+
+        let rest: Vec<&str> = lines.collect();
+        let rest = rest.join(" ");
+        let mut tokens = Tokens {
+            iter: rest.split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|t| !t.is_empty()),
+        };
+
+        if tilt == "INCLUDE" {
+            // lamp to luminaire geometry, then the angle and factor pairs
+            tokens.next()?;
+            let pairs = tokens.next()? as usize;
+            tokens.next_vec(2 * pairs)?;
+        }
+
+        let _lamps = tokens.next()?;
+        let _lumens_per_lamp = tokens.next()?;
+        let multiplier = tokens.next()?;
+        let vertical_num = tokens.next()? as usize;
+        let horizontal_num = tokens.next()? as usize;
+        let photometric_type = tokens.next()? as u32;
+        // units, width, length, height
+        tokens.next_vec(4)?;
+        let ballast_factor = tokens.next()?;
+        // ballast lamp factor, input watts
+        tokens.next_vec(2)?;
+
+        if photometric_type != 1 {
+            return Err(invalid_data("only type C photometry is supported"));
+        }
+        if vertical_num == 0 || horizontal_num == 0 {
+            return Err(invalid_data("no angles"));
+        }
+        let vertical = tokens.next_vec(vertical_num)?;
+        let horizontal = tokens.next_vec(horizontal_num)?;
+        if !is_ascending(&vertical) || !is_ascending(&horizontal) {
+            return Err(invalid_data("angles not in ascending order"));
+        }
+        let scale = multiplier * ballast_factor;
+        let mut candela = Vec::with_capacity(horizontal_num);
+        for _ in 0..horizontal_num {
+            let values = tokens.next_vec(vertical_num)?;
+            candela.push(values.into_iter().map(|c| c * scale).collect());
+        }
+
+        let mut profile = IesProfile {
+            vertical,
+            horizontal,
+            candela,
+            norm: 0.0,
+        };
+        let flux = profile.lumens();
+        profile.norm = if flux > 0.0 { PI as Real / flux } else { 0.0 };
+        Ok(profile)
+    }
+
+    /// Intensity in cd at the vertical angle `theta` and the horizontal
+    /// angle `phi`, in degrees.
+    pub fn candela(&self, theta: Real, phi: Real) -> Real {
+        let (v, vf) = match interval(&self.vertical, theta) {
+            Some(i) => i,
+            None => return 0.0,
+        };
+        let phi = self.fold(phi);
+        let (h, hf) = interval(&self.horizontal, phi).unwrap_or_else(|| {
+            // between the last angle and 360°, or outside of the range
+            if phi < self.horizontal[0] {
+                (0, 0.0)
+            } else {
+                (self.horizontal.len() - 1, 0.0)
+            }
+        });
+        let at = |h: usize| {
+            let c = &self.candela[h];
+            if vf > 0.0 {
+                c[v] * (1.0 - vf) + c[v + 1] * vf
+            } else {
+                c[v]
+            }
+        };
+        if hf > 0.0 {
+            at(h) * (1.0 - hf) + at(h + 1) * hf
+        } else {
+            at(h)
+        }
+    }
+
+    /// `phi` mapped into the horizontal angles by the symmetry of the
+    /// profile.
+    fn fold(&self, phi: Real) -> Real {
+        let phi = phi - 360.0 * (phi / 360.0).floor();
+        let last = self.horizontal[self.horizontal.len() - 1];
+        if self.horizontal.len() == 1 {
+            self.horizontal[0]
+        } else if last <= 90.0 {
+            // quadrant symmetric
+            let phi = if phi > 180.0 { 360.0 - phi } else { phi };
+            if phi > 90.0 { 180.0 - phi } else { phi }
+        } else if last <= 180.0 {
+            // bilateral
+            if phi > 180.0 { 360.0 - phi } else { phi }
+        } else {
+            phi
+        }
+    }
+
+    /// Luminous flux in lm emitted below the horizon of the fixture.
+    pub fn lumens(&self) -> Real {
+        const STEPS: usize = 180;
+        let d_theta = 90.0 / STEPS as Real;
+        let d_phi = 360.0 / (2 * STEPS) as Real;
+        let mut sum = 0.0;
+        for i in 0..STEPS {
+            let theta = (i as Real + 0.5) * d_theta;
+            let sin = theta.to_radians().sin();
+            for j in 0..2 * STEPS {
+                let phi = (j as Real + 0.5) * d_phi;
+                sum += self.candela(theta, phi) * sin;
+            }
+        }
+        sum * d_theta.to_radians() * d_phi.to_radians()
+    }
+
+    /// Factor of the radiance emitted toward `dir` at a surface with the
+    /// normal `normal`, so that the intensity follows the profile and the
+    /// flux of a Lambertian emitter is kept. The horizontal angles are
+    /// measured around the normal from `axis`, the 0° direction of the
+    /// fixture.
+    ///
+    /// The intensity is the radiance times the cosine, so the factor is
+    /// the candela over the cosine. The cosine is clamped to `MIN_COS`,
+    /// which keeps the radiance finite at grazing angles but dims the
+    /// candela values of the last ~0.6° above the horizon, and the flux
+    /// they carry, by `cos / MIN_COS`.
+    pub fn factor(&self, normal: &Vector3f, axis: &Vector3f, dir: &Vector3f) -> Real {
+        let cos = normal.dot(dir);
+        if cos <= 0.0 {
+            return 0.0;
+        }
+        let x = *axis - *normal * axis.dot(normal);
+        let (x, z) = if x.norm_squared() > 1e-12 {
+            let x = x.normalize();
+            (x, normal.cross(&x))
+        } else {
+            // no horizontal direction, any is as good
+            math::basis_y(normal)
+        };
+        let theta = cos.min(1.0).acos().to_degrees();
+        let phi = dir.dot(&z).atan2(dir.dot(&x)).to_degrees();
+        self.candela(theta, phi) * self.norm / cos.max(MIN_COS)
+    }
+}
+
+/// Index of the angle at or below `x` and the fraction to the next one,
+/// `None` outside of the angles.
+fn interval(angles: &[Real], x: Real) -> Option<(usize, Real)> {
+    let last = angles.len() - 1;
+    if x < angles[0] || x > angles[last] {
+        return None;
+    }
+    if x == angles[last] {
+        return Some((last, 0.0));
+    }
+    let i = angles.iter().rposition(|a| *a <= x).unwrap_or(0);
+    Some((i, (x - angles[i]) / (angles[i + 1] - angles[i])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Downlight with a cosine distribution, 100 cd at the nadir.
+    const COSINE: &str = "IESNA:LM-63-2002
+[TEST] synthetic
+[MANUFAC] none
+TILT=NONE
+1 -1 1.0 7 1 1 2 0.0 0.0 0.0
+1.0 1.0 10
+0 15 30 45 60 75 90
+0
+100.0 96.593 86.603 70.711 50.0 25.882 0.0
+";
+
+    #[test]
+    fn parses_cosine_profile() {
+        let p = IesProfile::parse(COSINE).unwrap();
+        assert_eq!(p.candela(0.0, 123.0), 100.0);
+        assert!((p.candela(60.0, 10.0) - 50.0).abs() < 1e-9);
+        assert_eq!(p.candela(100.0, 0.0), 0.0);
+        // π I0 for a cosine distribution, up to the interpolation
+        assert!((p.lumens() - PI * 100.0).abs() < 3.0);
+
+        // a Lambertian emitter is not changed
+        let n = Vector3f::new(0.0, 0.0, 1.0);
+        let dir = Vector3f::new(0.6, 0.0, 0.8);
+        assert!((p.factor(&n, &Vector3f::new(1.0, 0.0, 0.0), &dir) - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn grazing_intensity_is_dimmed() {
+        // the same intensity in every direction below the horizon
+        let text = "IESNA:LM-63-2002
+TILT=NONE
+1 -1 1.0 3 1 1 2 0.0 0.0 0.0
+1.0 1.0 10
+0 45 90
+0
+100.0 100.0 100.0
+";
+        let p = IesProfile::parse(text).unwrap();
+        let n = Vector3f::new(0.0, 0.0, 1.0);
+        let intensity = |cos: Real| {
+            let dir = Vector3f::new((1.0 - cos * cos).sqrt(), 0.0, cos);
+            p.factor(&n, &Vector3f::new(1.0, 0.0, 0.0), &dir) * cos
+        };
+        assert!((intensity(0.02) - intensity(0.5)).abs() < 1e-9);
+        assert!((intensity(0.005) / intensity(0.5) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn folds_symmetric_angles() {
+        // quadrant symmetric, dark at 90°
+        let text = "IESNA:LM-63-2002
+TILT=NONE
+1 -1 1.0 7 2 1 2 0.0 0.0 0.0
+1.0 1.0 10
+0 15 30 45 60 75 90
+0 90
+100.0 96.593 86.603 70.711 50.0 25.882 0.0
+0 0 0 0 0 0 0
+";
+        let p = IesProfile::parse(text).unwrap();
+        assert_eq!(p.candela(0.0, 0.0), 100.0);
+        assert_eq!(p.candela(0.0, 180.0), 100.0);
+        assert_eq!(p.candela(0.0, 90.0), 0.0);
+        assert_eq!(p.candela(0.0, 270.0), 0.0);
+        assert!((p.candela(0.0, 135.0) - 50.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn factor_follows_axis() {
+        // bright in the 0° and 180° planes, dark at 90° and 270°
+        let text = "IESNA:LM-63-2002
+TILT=NONE
+1 -1 1.0 3 2 1 2 0.0 0.0 0.0
+1.0 1.0 10
+0 45 90
+0 90
+100.0 70.711 0.0
+100.0 0.0 0.0
+";
+        let p = IesProfile::parse(text).unwrap();
+        let n = Vector3f::new(0.0, -1.0, 0.0);
+        let dir = Vector3f::new(0.8, -0.6, 0.0);
+        let along_x = p.factor(&n, &Vector3f::new(1.0, 0.0, 0.0), &dir);
+        let along_z = p.factor(&n, &Vector3f::new(0.0, 0.0, 1.0), &dir);
+        assert!(along_x > 0.0);
+        assert_eq!(along_z, 0.0);
+        // only the direction across the normal matters
+        let tilted = p.factor(&n, &Vector3f::new(2.0, 1.0, 0.0), &dir);
+        assert!((tilted - along_x).abs() < 1e-12);
+    }
+
+    #[test]
+    fn photometric_conversions() {
+        let area = 0.5;
+        let l = Photometric::Lumens(800.0).luminance(area);
+        assert!((PI * l * area - 800.0).abs() < 1e-9);
+        assert_eq!(Photometric::Candela(10.0).luminance(area), 20.0);
+        assert!(IesProfile::parse("IESNA:LM-63-2002\n1 1").is_err());
+    }
+}
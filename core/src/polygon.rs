@@ -3,9 +3,9 @@ pub use self::vertex::{BaseVertex, TbnVertex, TexturedVertex, Vertex};
 use {BsdfRef, Surface, SurfacePoint};
 use aabb::{Aabb3, HasBounds};
 use color::Color;
-use math::{self, Cross, Norm, Point3f, Ray3f, Real, Vector3f};
-use rand::{self, Closed01};
+use math::{self, Cross, Norm, Point2f, Point3f, Ray3f, Real, Vector3f};
 use std::marker::PhantomData;
+use utils::rng;
 
 use std::sync::Arc;
 
@@ -53,6 +53,16 @@ where
         ))
     }
 
+    /// Barycentric coordinates of `pos` for `v0`, `v1` and `v2`.
+    pub fn barycentric(&self, pos: &Point3f) -> (Real, Real, Real) {
+        math::barycentric(
+            &self.v0().position(),
+            &self.v1().position(),
+            &self.v2().position(),
+            pos,
+        )
+    }
+
     #[inline]
     pub fn v0(&self) -> &R {
         self.v0.as_ref()
@@ -111,9 +121,11 @@ where
     }
 
     #[inline]
-    default fn normal_at(&self, _: &Point3f) -> Vector3f {
-        unimplemented!()
-        //V::normal(self.v0(), self.v1(), self.v2(), p)
+    /// Geometric normal of the front face.
+    default fn normal_at(&self, _: &Point3f, _: Real) -> Vector3f {
+        let e1 = self.v2().position() - self.v0().position();
+        let e2 = self.v1().position() - self.v0().position();
+        e1.cross(&e2).normalize()
     }
 
     #[inline]
@@ -121,13 +133,25 @@ where
         self.total_radiance.is_some()
     }
 
-    fn sample_surface_p(&self, (_, _): (&Point3f, &Vector3f)) -> (SurfacePoint, Real) {
+    fn uv(&self, pos: &Point3f, _: Real) -> Option<Point2f> {
+        Vertex::interpolate(self.v0(), self.v1(), self.v2(), self.barycentric(pos)).uv()
+    }
+
+    fn barycentric(&self, pos: &Point3f, _: Real) -> Option<(Real, Real, Real)> {
+        Some(Polygon::barycentric(self, pos))
+    }
+
+    fn material_key(&self) -> usize {
+        self.mat.as_ref() as *const _ as *const u8 as usize
+    }
+
+    fn sample_surface_p(&self, (_, _): (&Point3f, &Vector3f), _: Real) -> (SurfacePoint, Real) {
         let a = self.v0().position().to_vector();
         let b = self.v1().position().to_vector();
         let c = self.v2().position().to_vector();
 
-        let Closed01(r1) = rand::random::<Closed01<Real>>();
-        let Closed01(r2) = rand::random::<Closed01<Real>>();
+        let r1 = rng::uniform();
+        let r2 = rng::uniform();
         let r1s = r1.sqrt();
 
         //P = (1 − √r1) A + √r1(1 − r2) B + √r1r2 C -- uniform sampling
@@ -151,7 +175,12 @@ where
         )
     }
 
-    fn pdf_p(&self, (_, _): (&Point3f, &Vector3f), (_, _): (&Point3f, &Vector3f)) -> Real {
+    fn pdf_p(
+        &self,
+        (_, _): (&Point3f, &Vector3f),
+        (_, _): (&Point3f, &Vector3f),
+        _: Real,
+    ) -> Real {
         1.0 / self.area()
     }
 }
@@ -226,11 +255,14 @@ where
 
 pub mod material {
     use super::vertex::{BaseVertex, TbnVertex, TexturedVertex, Vertex};
-    use bsdf::{BsdfRef, CookTorrance, Diffuse, Phong};
+    use bsdf::{BsdfRef, Coat, Conductor, CookTorrance, Diffuse, Layered, Metal, Mix, OrenNayar,
+               Phong, Principled, Profiled};
     use color::{self, Color, Rgb};
     use math;
     use math::{Cross, Norm, Point2f, Real, Vector3f};
     use num::{Float, NumCast};
+    use photometry::IesProfile;
+    use spectrum::Spd;
     use std::marker::PhantomData;
     use std::sync::Arc;
     use texture::{TexView, Texture};
@@ -258,6 +290,12 @@ pub mod material {
                 bsdf: Diffuse::new(color, radiance),
             }
         }
+
+        /// Emits `spd` with luminance `luminance`.
+        pub fn with_emission(mut self, spd: Spd, luminance: f32) -> Self {
+            self.bsdf = self.bsdf.with_emission(spd, luminance);
+            self
+        }
     }
 
     impl<V: Vertex> Material<V> for DiffuseMat {
@@ -277,6 +315,194 @@ pub mod material {
 
 
 
+    /// `mat` with its emission shaped by an IES profile.
+    pub struct ProfiledMat<M> {
+        pub mat: M,
+        pub profile: Arc<IesProfile>,
+        /// 0° direction of the horizontal angles of the profile.
+        pub axis: Vector3f,
+    }
+
+    impl<M> ProfiledMat<M> {
+        /// The 0° plane of the profile contains the x axis.
+        pub fn new(mat: M, profile: Arc<IesProfile>) -> Self {
+            ProfiledMat {
+                mat,
+                profile,
+                axis: Vector3f::new(1.0, 0.0, 0.0),
+            }
+        }
+
+        /// Orients the fixture, its 0° plane contains `axis`.
+        pub fn with_axis(mut self, axis: Vector3f) -> Self {
+            self.axis = axis;
+            self
+        }
+    }
+
+    impl<V: Vertex, M: Material<V>> Material<V> for ProfiledMat<M> {
+        fn bsdf<'s>(&'s self, v: &V) -> BsdfRef<'s> {
+            let bsdf =
+                Profiled::new(self.mat.bsdf(v), self.profile.clone()).with_axis(self.axis);
+            BsdfRef::Shared(Arc::new(bsdf))
+        }
+
+        /// The profile keeps the emitted power.
+        fn total_radiance(&self, v0: &V, v1: &V, v2: &V) -> Option<Color> {
+            self.mat.total_radiance(v0, v1, v2)
+        }
+
+        fn normal(&self, v0: &V, v1: &V, v2: &V, coords: (Real, Real, Real)) -> Vector3f {
+            self.mat.normal(v0, v1, v2, coords)
+        }
+    }
+
+
+
+    /// `mat` under a dielectric coat.
+    pub struct LayeredMat<M> {
+        pub mat: M,
+        pub coat: Coat,
+    }
+
+    impl<M> LayeredMat<M> {
+        pub fn new(mat: M, coat: Coat) -> Self {
+            LayeredMat { mat, coat }
+        }
+    }
+
+    impl<V: Vertex, M: Material<V>> Material<V> for LayeredMat<M> {
+        fn bsdf<'s>(&'s self, v: &V) -> BsdfRef<'s> {
+            BsdfRef::Shared(Arc::new(Layered::new(self.mat.bsdf(v), self.coat)))
+        }
+
+        fn total_radiance(&self, v0: &V, v1: &V, v2: &V) -> Option<Color> {
+            self.mat.total_radiance(v0, v1, v2)
+        }
+
+        fn normal(&self, v0: &V, v1: &V, v2: &V, coords: (Real, Real, Real)) -> Vector3f {
+            self.mat.normal(v0, v1, v2, coords)
+        }
+    }
+
+
+
+    /// Weight of `b` in a `MixMat`.
+    pub trait Mask: Sync + Send {
+        /// Weight at `uv`, `None` for vertices without UV.
+        fn weight(&self, uv: Option<Point2f>) -> Real;
+    }
+
+    impl Mask for Real {
+        fn weight(&self, _: Option<Point2f>) -> Real {
+            *self
+        }
+    }
+
+    /// Mask sampled from a texture, vertices without UV take a weight of 0.
+    pub struct TexMask<'a, C, T = Texture<C>>
+    where
+        T: 'a + AsRef<TexView<C> + 'a> + Sync + Send,
+        Real: From<C>,
+        C: 'a + Send + Sync,
+    {
+        pub tex: T,
+        _marker_t: PhantomData<&'a (TexView<C> + 'a)>,
+        _marker_c: PhantomData<C>,
+    }
+
+    impl<'a, C, T> TexMask<'a, C, T>
+    where
+        T: 'a + AsRef<TexView<C> + 'a> + Sync + Send,
+        Real: From<C>,
+        C: 'a + Send + Sync,
+    {
+        pub fn new(tex: T) -> Self {
+            TexMask {
+                tex,
+                _marker_t: PhantomData,
+                _marker_c: PhantomData,
+            }
+        }
+    }
+
+    impl<'a, C, T> Mask for TexMask<'a, C, T>
+    where
+        T: 'a + AsRef<TexView<C> + 'a> + Sync + Send,
+        Real: From<C>,
+        C: 'a + Send + Sync,
+    {
+        fn weight(&self, uv: Option<Point2f>) -> Real {
+            match uv {
+                Some(uv) => self.tex.as_ref().sample(uv.x as f32, uv.y as f32).into(),
+                None => 0.0,
+            }
+        }
+    }
+
+    /// `b` blended over `a` by `mask`, a constant weight or a `TexMask`.
+    pub struct MixMat<A, B, K = Real> {
+        pub a: A,
+        pub b: B,
+        pub mask: K,
+    }
+
+    impl<A, B, K: Mask> MixMat<A, B, K> {
+        pub fn new(a: A, b: B, mask: K) -> Self {
+            MixMat { a, b, mask }
+        }
+
+        fn weight<V: Vertex>(&self, v: &V) -> Real {
+            use utils::clamp;
+            clamp(self.mask.weight(v.uv()), 0.0, 1.0)
+        }
+    }
+
+    impl<V, A, B, K> Material<V> for MixMat<A, B, K>
+    where
+        V: Vertex,
+        A: Material<V>,
+        B: Material<V>,
+        K: Mask,
+    {
+        fn bsdf<'s>(&'s self, v: &V) -> BsdfRef<'s> {
+            let w = self.weight(v);
+            if w <= 0.0 {
+                self.a.bsdf(v)
+            } else if w >= 1.0 {
+                self.b.bsdf(v)
+            } else {
+                BsdfRef::Shared(Arc::new(Mix::new(self.a.bsdf(v), self.b.bsdf(v), w)))
+            }
+        }
+
+        /// Blended by the weight at the centroid.
+        fn total_radiance(&self, v0: &V, v1: &V, v2: &V) -> Option<Color> {
+            let c = V::interpolate(v0, v1, v2, (1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0));
+            let w = self.weight(&c) as f32;
+            match (self.a.total_radiance(v0, v1, v2), self.b.total_radiance(v0, v1, v2)) {
+                (None, None) => None,
+                (a, b) => {
+                    Some(a.unwrap_or(color::BLACK) * (1.0 - w) + b.unwrap_or(color::BLACK) * w)
+                }
+            }
+        }
+
+        fn normal(&self, v0: &V, v1: &V, v2: &V, coords: (Real, Real, Real)) -> Vector3f {
+            let w = self.weight(&V::interpolate(v0, v1, v2, coords));
+            if w <= 0.0 {
+                return self.a.normal(v0, v1, v2, coords);
+            } else if w >= 1.0 {
+                return self.b.normal(v0, v1, v2, coords);
+            }
+            let na = self.a.normal(v0, v1, v2, coords);
+            let nb = self.b.normal(v0, v1, v2, coords);
+            (na * (1.0 - w) + nb * w).normalize()
+        }
+    }
+
+
+
     pub struct PhongMat {
         pub bsdf: Phong,
     }
@@ -306,6 +532,7 @@ pub mod material {
     {
         pub albedo: T,
         pub radiance: Option<T>,
+        spd: Option<Spd>,
         _marker_r: PhantomData<&'a (TexView<Color> + 'a)>,
         _marker_c: PhantomData<C>,
     }
@@ -321,10 +548,24 @@ pub mod material {
             Self {
                 albedo,
                 radiance,
+                spd: None,
                 _marker_r: PhantomData,
                 _marker_c: PhantomData,
             }
         }
+
+        /// Emits `spd` scaled by the luminance of the radiance texture.
+        pub fn with_emission_spd(mut self, spd: Spd) -> Self {
+            self.spd = Some(spd);
+            self
+        }
+
+        fn tint(&self, radiance: Color) -> Color {
+            match self.spd {
+                Some(ref spd) => spd.radiance(color::luminance(&radiance)),
+                None => radiance,
+            }
+        }
     }
 
     impl<'a, C, T> Material<TexturedVertex> for DiffuseTex<'a, C, T>
@@ -339,8 +580,12 @@ pub mod material {
             let albedo = self.albedo.as_ref().sample(uv.x, uv.y);
             let radiance = self.radiance
                 .as_ref()
-                .map(|e| e.as_ref().sample(uv.x, uv.y).into());
-            BsdfRef::Shared(Arc::new(Diffuse::new(albedo.into(), radiance)))
+                .map(|e| self.tint(e.as_ref().sample(uv.x, uv.y).into()));
+            let mut bsdf = Diffuse::new(albedo.into(), radiance);
+            if radiance.is_some() {
+                bsdf.spd = self.spd;
+            }
+            BsdfRef::Shared(Arc::new(bsdf))
         }
 
         fn total_radiance(
@@ -396,13 +641,106 @@ pub mod material {
                 // println!("  - calc area: {:?}, true area: {:?}", uv_area, tr_area);
                 // println!("  - texture total radiance: {:?}", sum);
 
-                Some(sum.into())
+                Some(self.tint(sum.into()))
+            } else {
+                None
+            }
+        }
+    }
+
+
+
+    pub struct OrenNayarMat {
+        pub bsdf: OrenNayar,
+    }
+
+    impl OrenNayarMat {
+        /// `sigma` is the slope deviation in radians.
+        pub fn new(color: Color, sigma: Real, radiance: Option<Color>) -> OrenNayarMat {
+            OrenNayarMat {
+                bsdf: OrenNayar::new(color, sigma, radiance),
+            }
+        }
+    }
+
+    impl<V: Vertex> Material<V> for OrenNayarMat {
+        fn bsdf<'s>(&'s self, _: &V) -> BsdfRef<'s> {
+            BsdfRef::Ref(&self.bsdf)
+        }
+
+        fn total_radiance(&self, v0: &V, v1: &V, v2: &V) -> Option<Color> {
+            if let Some(e) = self.bsdf.radiance {
+                let area = math::triangle_area(&v0.position(), &v1.position(), &v2.position());
+                Some(e * (area as f32))
             } else {
                 None
             }
         }
     }
 
+
+
+    /// Oren-Nayar reflector with the albedo and the roughness from textures.
+    pub struct OrenNayarTex<'a, C3, C1, Tx3 = Texture<C3>, Tx1 = Texture<C1>>
+    where
+        Tx3: 'a + AsRef<TexView<C3> + 'a> + Sync + Send,
+        Tx1: 'a + AsRef<TexView<C1> + 'a> + Sync + Send,
+        C3: Into<Color>,
+        Real: From<C1>,
+        C3: 'a + Send + Sync,
+        C1: 'a + Send + Sync,
+    {
+        pub albedo: Tx3,
+        pub roughness: Tx1,
+        /// Slope deviation in radians at a roughness of 1.
+        pub sigma: Real,
+        _marker_t3: PhantomData<&'a (TexView<C3> + 'a)>,
+        _marker_t1: PhantomData<&'a (TexView<C1> + 'a)>,
+        _marker_c3: PhantomData<C3>,
+        _marker_c1: PhantomData<C1>,
+    }
+
+    impl<'a, C3, C1, Tx3, Tx1> OrenNayarTex<'a, C3, C1, Tx3, Tx1>
+    where
+        Tx3: 'a + AsRef<TexView<C3> + 'a> + Sync + Send,
+        Tx1: 'a + AsRef<TexView<C1> + 'a> + Sync + Send,
+        C3: Into<Color>,
+        Real: From<C1>,
+        C3: 'a + Send + Sync,
+        C1: 'a + Send + Sync,
+    {
+        pub fn new(albedo: Tx3, roughness: Tx1, sigma: Real) -> Self {
+            Self {
+                albedo,
+                roughness,
+                sigma,
+                _marker_t3: PhantomData,
+                _marker_t1: PhantomData,
+                _marker_c3: PhantomData,
+                _marker_c1: PhantomData,
+            }
+        }
+    }
+
+    impl<'a, C3, C1, Tx3, Tx1> Material<TexturedVertex> for OrenNayarTex<'a, C3, C1, Tx3, Tx1>
+    where
+        Tx3: 'a + AsRef<TexView<C3> + 'a> + Sync + Send,
+        Tx1: 'a + AsRef<TexView<C1> + 'a> + Sync + Send,
+        C3: Into<Color>,
+        Real: From<C1>,
+        C3: 'a + Send + Sync,
+        C1: 'a + Send + Sync,
+    {
+        fn bsdf<'s>(&'s self, v: &TexturedVertex) -> BsdfRef<'s> {
+            use utils::clamp;
+
+            let albedo: Color = self.albedo.as_ref().sample(v.uv.x, v.uv.y).into();
+            let roughness: Real = self.roughness.as_ref().sample(v.uv.x, v.uv.y).into();
+            let sigma = clamp(roughness, 0.0, 1.0) * self.sigma;
+            BsdfRef::Shared(Arc::new(OrenNayar::new(albedo, sigma, None)))
+        }
+    }
+
     pub struct CookTorranceMat {
         pub bsdf: CookTorrance,
     }
@@ -442,6 +780,27 @@ pub mod material {
     }
 
 
+    /// Metal with the complex index of refraction of `metal`, a mirror at
+    /// a `roughness` of 0.
+    pub struct ConductorMat {
+        pub bsdf: Conductor,
+    }
+
+    impl ConductorMat {
+        pub fn new(metal: Metal, roughness: Real) -> ConductorMat {
+            ConductorMat {
+                bsdf: Conductor::new(metal, roughness),
+            }
+        }
+    }
+
+    impl<V: Vertex> Material<V> for ConductorMat {
+        fn bsdf<'s>(&'s self, _: &V) -> BsdfRef<'s> {
+            BsdfRef::Ref(&self.bsdf)
+        }
+    }
+
+
     pub struct PbrTex<'a, C3, C1, Tx3 = Texture<C3>, Tx1 = Texture<C1>>
     where
         Tx3: 'a + AsRef<TexView<C3> + 'a> + Sync + Send,
@@ -617,10 +976,241 @@ pub mod material {
             normal.normalize()
         }
     }
+
+
+    pub struct PrincipledMat {
+        pub bsdf: Principled,
+    }
+
+    impl PrincipledMat {
+        pub fn new(bsdf: Principled) -> PrincipledMat {
+            PrincipledMat { bsdf }
+        }
+    }
+
+    impl<V: Vertex> Material<V> for PrincipledMat {
+        fn bsdf<'s>(&'s self, _: &V) -> BsdfRef<'s> {
+            BsdfRef::Ref(&self.bsdf)
+        }
+    }
+
+
+
+    /// `Principled` with every parameter from a texture. Parameters without
+    /// a texture keep the defaults of `Principled`, the normal map is
+    /// optional. With `TbnVertex` the anisotropy follows the vertex
+    /// tangent.
+    pub struct PrincipledTex<'a, C3, C1, Tx3 = Texture<C3>, Tx1 = Texture<C1>>
+    where
+        Tx3: 'a + AsRef<TexView<C3> + 'a> + Sync + Send,
+        Tx1: 'a + AsRef<TexView<C1> + 'a> + Sync + Send,
+        C3: Into<Rgb<Real>> + Into<Color>,
+        Real: From<C1>,
+        C3: 'a + Send + Sync,
+        C1: 'a + Send + Sync,
+    {
+        pub base_color: Tx3,
+        pub normal: Option<Tx3>,
+        pub metallic: Tx1,
+        pub roughness: Tx1,
+        pub specular: Option<(Tx1, Tx1)>,
+        pub anisotropic: Option<Tx1>,
+        pub sheen: Option<(Tx1, Tx1)>,
+        pub clearcoat: Option<(Tx1, Tx1)>,
+        pub subsurface: Option<Tx1>,
+        pub transmission: Option<Tx1>,
+        _marker_t3: PhantomData<&'a (TexView<C3> + 'a)>,
+        _marker_t1: PhantomData<&'a (TexView<C1> + 'a)>,
+        _marker_c3: PhantomData<C3>,
+        _marker_c1: PhantomData<C1>,
+    }
+
+    impl<'a, C3, C1, Tx3, Tx1> PrincipledTex<'a, C3, C1, Tx3, Tx1>
+    where
+        Tx3: 'a + AsRef<TexView<C3> + 'a> + Sync + Send,
+        Tx1: 'a + AsRef<TexView<C1> + 'a> + Sync + Send,
+        C3: Into<Rgb<Real>> + Into<Color>,
+        Real: From<C1>,
+        C3: 'a + Send + Sync,
+        C1: 'a + Send + Sync,
+    {
+        pub fn new(base_color: Tx3, metallic: Tx1, roughness: Tx1) -> Self {
+            Self {
+                base_color,
+                normal: None,
+                metallic,
+                roughness,
+                specular: None,
+                anisotropic: None,
+                sheen: None,
+                clearcoat: None,
+                subsurface: None,
+                transmission: None,
+                _marker_t3: PhantomData,
+                _marker_t1: PhantomData,
+                _marker_c3: PhantomData,
+                _marker_c1: PhantomData,
+            }
+        }
+
+        pub fn with_normal(mut self, normal: Tx3) -> Self {
+            self.normal = Some(normal);
+            self
+        }
+
+        pub fn with_specular(mut self, specular: Tx1, tint: Tx1) -> Self {
+            self.specular = Some((specular, tint));
+            self
+        }
+
+        pub fn with_anisotropic(mut self, anisotropic: Tx1) -> Self {
+            self.anisotropic = Some(anisotropic);
+            self
+        }
+
+        pub fn with_sheen(mut self, sheen: Tx1, tint: Tx1) -> Self {
+            self.sheen = Some((sheen, tint));
+            self
+        }
+
+        pub fn with_clearcoat(mut self, clearcoat: Tx1, gloss: Tx1) -> Self {
+            self.clearcoat = Some((clearcoat, gloss));
+            self
+        }
+
+        pub fn with_subsurface(mut self, subsurface: Tx1) -> Self {
+            self.subsurface = Some(subsurface);
+            self
+        }
+
+        pub fn with_transmission(mut self, transmission: Tx1) -> Self {
+            self.transmission = Some(transmission);
+            self
+        }
+
+        fn principled(&self, u: f32, v: f32) -> Principled {
+            let sample = |tex: &Tx1| -> Real { tex.as_ref().sample(u, v).into() };
+            let base_color: Rgb<Real> = self.base_color.as_ref().sample(u, v).into();
+            let mut bsdf = Principled::new(
+                base_color,
+                sample(&self.metallic),
+                sample(&self.roughness),
+            );
+            if let Some((ref s, ref t)) = self.specular {
+                bsdf = bsdf.with_specular(sample(s), sample(t));
+            }
+            if let Some(ref a) = self.anisotropic {
+                bsdf = bsdf.with_anisotropic(sample(a));
+            }
+            if let Some((ref s, ref t)) = self.sheen {
+                bsdf = bsdf.with_sheen(sample(s), sample(t));
+            }
+            if let Some((ref c, ref g)) = self.clearcoat {
+                bsdf = bsdf.with_clearcoat(sample(c), sample(g));
+            }
+            if let Some(ref s) = self.subsurface {
+                bsdf = bsdf.with_subsurface(sample(s));
+            }
+            if let Some(ref t) = self.transmission {
+                bsdf = bsdf.with_transmission(sample(t));
+            }
+            bsdf
+        }
+
+        /// Normal from the normal map in the frame `t`, `b`, `n`.
+        fn map_normal(
+            &self,
+            normal: &Tx3,
+            uv: (f32, f32),
+            (t, b, n): (Vector3f, Vector3f, Vector3f),
+        ) -> Vector3f {
+            let mut rgb_normal: Rgb<Real> = normal.as_ref().sample(uv.0, uv.1).into();
+            rgb_normal = rgb_normal * 2.0 - Rgb::<Real>::from(1.0);
+            let tex_normal = Vector3f::new(rgb_normal.r, rgb_normal.g, rgb_normal.b).normalize();
+
+            let normal = Vector3f::new(
+                tex_normal.x * t.x + tex_normal.y * b.x + tex_normal.z * n.x,
+                tex_normal.x * t.y + tex_normal.y * b.y + tex_normal.z * n.y,
+                tex_normal.x * t.z + tex_normal.y * b.z + tex_normal.z * n.z,
+            );
+
+            normal.normalize()
+        }
+    }
+
+    impl<'a, C3, C1, Tx3, Tx1> Material<TexturedVertex> for PrincipledTex<'a, C3, C1, Tx3, Tx1>
+    where
+        Tx3: 'a + AsRef<TexView<C3> + 'a> + Sync + Send,
+        Tx1: 'a + AsRef<TexView<C1> + 'a> + Sync + Send,
+        C3: Into<Rgb<Real>> + Into<Color>,
+        Real: From<C1>,
+        C3: 'a + Send + Sync,
+        C1: 'a + Send + Sync,
+    {
+        fn bsdf<'s>(&'s self, v: &TexturedVertex) -> BsdfRef<'s> {
+            BsdfRef::Shared(Arc::new(self.principled(v.uv.x, v.uv.y)))
+        }
+
+        fn normal(
+            &self,
+            v0: &TexturedVertex,
+            v1: &TexturedVertex,
+            v2: &TexturedVertex,
+            wuv: (Real, Real, Real),
+        ) -> Vector3f {
+            let normal = match self.normal {
+                Some(ref normal) => normal,
+                None => {
+                    return math::triangle_normal(&v0.position, &v1.position, &v2.position);
+                }
+            };
+            let p = Vertex::interpolate(v0, v1, v2, wuv);
+            let duv1 = v1.uv - v0.uv;
+            let duv2 = v2.uv - v0.uv;
+            let (t, b) = math::calc_tangent(
+                (&(v1.position - v0.position), duv1.x as Real, duv1.y as Real),
+                (&(v2.position - v0.position), duv2.x as Real, duv2.y as Real),
+            );
+            let n = t.cross(&b).normalize();
+            self.map_normal(normal, (p.uv.x, p.uv.y), (t, b, n))
+        }
+    }
+
+    impl<'a, C3, C1, Tx3, Tx1> Material<TbnVertex> for PrincipledTex<'a, C3, C1, Tx3, Tx1>
+    where
+        Tx3: 'a + AsRef<TexView<C3> + 'a> + Sync + Send,
+        Tx1: 'a + AsRef<TexView<C1> + 'a> + Sync + Send,
+        C3: Into<Rgb<Real>> + Into<Color>,
+        Real: From<C1>,
+        C3: 'a + Send + Sync,
+        C1: 'a + Send + Sync,
+    {
+        fn bsdf<'s>(&'s self, v: &TbnVertex) -> BsdfRef<'s> {
+            let bsdf = self.principled(v.uv.x, v.uv.y).with_tangent(v.tangent);
+            BsdfRef::Shared(Arc::new(bsdf))
+        }
+
+        fn normal(
+            &self,
+            v0: &TbnVertex,
+            v1: &TbnVertex,
+            v2: &TbnVertex,
+            wuv: (Real, Real, Real),
+        ) -> Vector3f {
+            let normal = match self.normal {
+                Some(ref normal) => normal,
+                None => {
+                    return math::triangle_normal(&v0.position, &v1.position, &v2.position);
+                }
+            };
+            let p = TbnVertex::interpolate(v0, v1, v2, wuv);
+            self.map_normal(normal, (p.uv.x, p.uv.y), (p.tangent, p.bitangent, p.normal))
+        }
+    }
 }
 
 pub mod vertex {
-    use math::{Point3f, Real, Vector2, Vector3f};
+    use math::{Point2f, Point3f, Real, Vector2, Vector3f};
     use math::Norm;
 
     pub trait Vertex: Copy + Clone + Sync + Send {
@@ -629,6 +1219,10 @@ pub mod vertex {
             Self: Sized;
 
         fn position(&self) -> Point3f;
+
+        fn uv(&self) -> Option<Point2f> {
+            None
+        }
     }
 
     macro_rules! impl_asref_for_vertex {
@@ -705,6 +1299,11 @@ pub mod vertex {
         fn position(&self) -> Point3f {
             self.position
         }
+
+        #[inline]
+        fn uv(&self) -> Option<Point2f> {
+            Some(Point2f::new(self.uv.x as Real, self.uv.y as Real))
+        }
     }
 
     #[derive(Copy, Clone, Debug, PartialEq)]
@@ -754,5 +1353,10 @@ pub mod vertex {
         fn position(&self) -> Point3f {
             self.position
         }
+
+        #[inline]
+        fn uv(&self) -> Option<Point2f> {
+            Some(Point2f::new(self.uv.x as Real, self.uv.y as Real))
+        }
     }
 }
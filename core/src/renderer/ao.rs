@@ -0,0 +1,112 @@
+use super::WorkerPool;
+use super::inner::{CameraRayGenerator, RendererHelper};
+use {Color, RenderSettings};
+use color;
+use math::{self, Dot, Norm, Ray3f, Real, Vector3f};
+use traits::{RenderCamera, Renderer, SceneHandler};
+use utils::consts;
+
+/// Fraction of the hemisphere above the first hit that is not occluded
+/// within a distance, weighted by the cosine to the normal.
+pub struct AmbientOcclusion {
+    ray_gen: CameraRayGenerator,
+    workers: WorkerPool,
+    samples: u32,
+    max_distance: Real,
+    bent_normals: bool,
+}
+
+impl AmbientOcclusion {
+    /// `samples` occlusion rays per camera ray, hits farther than
+    /// `max_distance` do not occlude.
+    pub fn new(samples: u32, max_distance: Real) -> AmbientOcclusion {
+        AmbientOcclusion {
+            ray_gen: CameraRayGenerator::new(),
+            workers: WorkerPool::new(),
+            samples: samples.max(1),
+            max_distance,
+            bent_normals: false,
+        }
+    }
+
+    /// Renders the bent normals, the mean unoccluded direction, encoded as
+    /// `0.5 + 0.5 * n` instead of the occlusion. Fully occluded points get
+    /// the shading normal.
+    pub fn with_bent_normals(mut self, bent_normals: bool) -> Self {
+        self.bent_normals = bent_normals;
+        self
+    }
+
+    /// Unoccluded fraction, the sum of the unoccluded directions and the
+    /// normal facing the ray at its first hit.
+    fn occlusion<S>(&self, scene: &S, ray: &Ray3f) -> Option<(Real, Vector3f, Vector3f)>
+    where
+        S: SceneHandler + ?Sized,
+    {
+        let sp = match scene.intersection(ray) {
+            Some(sp) => sp,
+            None => return None,
+        };
+        // the hit point is offset along the normal, move it to the seen side
+        let (position, normal) = if sp.normal.dot(&ray.dir) > 0.0 {
+            (
+                sp.position - sp.normal * (2.0 * consts::POSITION_EPSILON),
+                -sp.normal,
+            )
+        } else {
+            (sp.position, sp.normal)
+        };
+
+        let mut visible = 0;
+        let mut bent: Vector3f = math::zero();
+        for _ in 0..self.samples {
+            let dir = math::hs_cosine_sampling(&normal);
+            let occ_ray = Ray3f::with_time(&position, &dir, ray.time);
+            let occluded = match scene.intersection(&occ_ray) {
+                Some(ip) => (ip.position - position).norm() < self.max_distance,
+                None => false,
+            };
+            if !occluded {
+                visible += 1;
+                bent += dir;
+            }
+        }
+        Some((visible as Real / self.samples as Real, bent, normal))
+    }
+}
+
+impl<S: SceneHandler + ?Sized, C: RenderCamera + ?Sized> RendererHelper<S, C>
+    for AmbientOcclusion {
+    fn trace_path(&self, scene: &S, initial_ray: &Ray3f, _: &RenderSettings) -> Color {
+        match self.occlusion(scene, initial_ray) {
+            Some((_, bent, normal)) if self.bent_normals => {
+                let n = if bent.norm_squared() > 0.0 {
+                    bent.normalize()
+                } else {
+                    normal
+                };
+                Color::new(
+                    (0.5 + n.x * 0.5) as f32,
+                    (0.5 + n.y * 0.5) as f32,
+                    (0.5 + n.z * 0.5) as f32,
+                )
+            }
+            Some((ao, _, _)) => Color::from(ao as f32),
+            None => color::BLACK,
+        }
+    }
+
+    fn get_ray(&self, _: &C, x: u32, y: u32) -> Option<Ray3f> {
+        self.ray_gen.get_ray(x, y)
+    }
+
+    fn workers(&self) -> &WorkerPool {
+        &self.workers
+    }
+}
+
+impl<S: SceneHandler + ?Sized, C: RenderCamera + ?Sized> Renderer<S, C> for AmbientOcclusion {
+    fn pre_render(&mut self, _: &S, camera: &C, _: &RenderSettings) {
+        self.ray_gen = CameraRayGenerator::with_camera(camera);
+    }
+}
@@ -0,0 +1,336 @@
+//! Arbitrary output variables: buffers rendered next to the beauty image.
+
+use {Color, SurfacePoint};
+use color;
+use math::{self, Norm, Point2f, Point3f, Ray3f, Real, Vector3f};
+use texture::Texture;
+use traits::SceneHandler;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Aov {
+    /// Distance from the ray origin to the first hit.
+    Depth,
+    /// World position of the first hit.
+    Position,
+    /// Shading normal of the first hit, components in `[-1, 1]`.
+    Normal,
+    /// Hemispherical reflectance of the first hit.
+    Albedo,
+    /// Texture coordinates of the first hit in the red and green channels.
+    Uv,
+    /// Id of the object hit first, see `SceneIds`.
+    ObjectId,
+    /// Id of the material of the first hit surface, see `SceneIds`.
+    MaterialId,
+    /// Emitted light and light reaching the camera after one bounce.
+    Direct,
+    /// Light reaching the camera after two and more bounces.
+    Indirect,
+    /// Light reflected by the diffuse part of the first hit BSDF.
+    Diffuse,
+    /// Light reflected by the rest of the first hit BSDF.
+    Specular,
+    /// Number of samples taken in the pixel.
+    SampleCount,
+    /// Variance of the mean luminance of the pixel, an estimate of the
+    /// noise left in the beauty image.
+    Variance,
+}
+
+pub const AOVS: [Aov; 13] = [
+    Aov::Depth,
+    Aov::Position,
+    Aov::Normal,
+    Aov::Albedo,
+    Aov::Uv,
+    Aov::ObjectId,
+    Aov::MaterialId,
+    Aov::Direct,
+    Aov::Indirect,
+    Aov::Diffuse,
+    Aov::Specular,
+    Aov::SampleCount,
+    Aov::Variance,
+];
+
+impl Aov {
+    fn index(&self) -> usize {
+        AOVS.iter().position(|a| a == self).unwrap()
+    }
+
+    /// Ids are taken from the first sample instead of being averaged.
+    fn is_id(&self) -> bool {
+        *self == Aov::ObjectId || *self == Aov::MaterialId
+    }
+}
+
+/// Set of AOVs requested in `RenderSettings`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct AovSet(u32);
+
+impl AovSet {
+    pub fn new() -> AovSet {
+        AovSet(0)
+    }
+
+    pub fn all() -> AovSet {
+        AOVS.iter().fold(AovSet::new(), |set, &aov| set.with(aov))
+    }
+
+    pub fn with(self, aov: Aov) -> AovSet {
+        AovSet(self.0 | 1 << aov.index())
+    }
+
+    pub fn contains(&self, aov: Aov) -> bool {
+        self.0 & (1 << aov.index()) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(&self) -> Vec<Aov> {
+        AOVS.iter().cloned().filter(|&a| self.contains(a)).collect()
+    }
+}
+
+/// AOV values of one camera sample.
+#[derive(Copy, Clone, Debug)]
+pub struct AovSample {
+    pub hit: bool,
+    pub depth: Real,
+    pub position: Point3f,
+    pub normal: Vector3f,
+    pub albedo: Color,
+    pub uv: Point2f,
+    pub object_id: u32,
+    pub material_id: u32,
+    pub direct: Color,
+    pub indirect: Color,
+    pub diffuse: Color,
+    pub specular: Color,
+    /// Beauty value of the sample.
+    pub color: Color,
+}
+
+impl AovSample {
+    pub fn new() -> AovSample {
+        AovSample {
+            hit: false,
+            depth: 0.0,
+            position: math::origin(),
+            normal: math::zero(),
+            albedo: color::BLACK,
+            uv: math::origin(),
+            object_id: 0,
+            material_id: 0,
+            direct: color::BLACK,
+            indirect: color::BLACK,
+            diffuse: color::BLACK,
+            specular: color::BLACK,
+            color: color::BLACK,
+        }
+    }
+
+    /// Fills the geometric values from the first hit of `ray` in `scene`.
+    pub fn record_hit<S>(&mut self, scene: &S, ray: &Ray3f, sp: &SurfacePoint)
+    where
+        S: SceneHandler + ?Sized,
+    {
+        self.hit = true;
+        self.depth = (sp.position - ray.origin).norm();
+        self.position = sp.position;
+        self.normal = sp.normal;
+        self.albedo = sp.bsdf.albedo(&sp.normal, &ray.dir);
+        self.uv = sp.surface
+            .uv(&sp.position, ray.time)
+            .unwrap_or_else(math::origin);
+        self.object_id = scene.object_id(sp.surface);
+        self.material_id = scene.material_id(sp.surface);
+    }
+
+    fn value(&self, aov: Aov, samples: u32) -> Color {
+        let vec = |v: &Vector3f| Color::new(v.x as f32, v.y as f32, v.z as f32);
+        match aov {
+            Aov::Depth => Color::from(self.depth as f32),
+            Aov::Position => vec(self.position.as_vector()),
+            Aov::Normal => vec(&self.normal),
+            Aov::Albedo => self.albedo,
+            Aov::Uv => Color::new(self.uv.x as f32, self.uv.y as f32, 0.0),
+            Aov::ObjectId => Color::from(self.object_id as f32),
+            Aov::MaterialId => Color::from(self.material_id as f32),
+            Aov::Direct => self.direct,
+            Aov::Indirect => self.indirect,
+            Aov::Diffuse => self.diffuse,
+            Aov::Specular => self.specular,
+            Aov::SampleCount => Color::from(samples as f32),
+            Aov::Variance => Color::from(color::luminance(&self.color)),
+        }
+    }
+}
+
+impl Default for AovSample {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Textures the selected AOVs are written to, one per AOV.
+///
+/// Every pixel holds the mean over its samples, except the ids, which are
+/// taken from the first sample that hit a surface, the sample count and the
+/// variance. Geometric AOVs of samples that miss the scene are zero.
+pub struct AovBuffers {
+    set: AovSet,
+    width: usize,
+    height: usize,
+    textures: Vec<Option<Texture<Color>>>,
+    samples: Vec<u32>,
+    /// Sums of the luminance and of its square, kept for `Aov::Variance`.
+    moments: Vec<(f64, f64)>,
+}
+
+impl AovBuffers {
+    pub fn new(set: AovSet, width: usize, height: usize) -> AovBuffers {
+        AovBuffers {
+            set,
+            width,
+            height,
+            textures: AOVS.iter()
+                .map(|&a| if set.contains(a) {
+                    Some(Texture::new(width, height))
+                } else {
+                    None
+                })
+                .collect(),
+            samples: vec![0; width * height],
+            moments: if set.contains(Aov::Variance) {
+                vec![(0.0, 0.0); width * height]
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    pub fn aovs(&self) -> AovSet {
+        self.set
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, aov: Aov) -> Option<&Texture<Color>> {
+        self.textures[aov.index()].as_ref()
+    }
+
+    pub fn take(&mut self, aov: Aov) -> Option<Texture<Color>> {
+        self.textures[aov.index()].take()
+    }
+
+    pub fn add_sample(&mut self, x: usize, y: usize, sample: &AovSample) {
+        let ix = y * self.width + x;
+        self.samples[ix] += 1;
+        let n = self.samples[ix];
+        for (aov, tex) in AOVS.iter().zip(self.textures.iter_mut()) {
+            if let Some(ref mut tex) = *tex {
+                let value = sample.value(*aov, n);
+                let pixel = if aov.is_id() {
+                    if !sample.hit || tex.pixel(x, y) != color::BLACK {
+                        continue;
+                    }
+                    value
+                } else if *aov == Aov::SampleCount {
+                    value
+                } else if *aov == Aov::Variance {
+                    let l = value.r as f64;
+                    let (sum, sum_sq) = self.moments[ix];
+                    self.moments[ix] = (sum + l, sum_sq + l * l);
+                    Color::from(mean_variance(self.moments[ix], n) as f32)
+                } else {
+                    let mean = tex.pixel(x, y);
+                    mean + (value - mean) * (1.0 / n as f32)
+                };
+                tex.set_pixel(x, y, pixel);
+            }
+        }
+    }
+}
+
+/// Variance of the mean of `n` samples from the sums of the samples and of
+/// their squares. A single sample gives no estimate, its square is used as
+/// a conservative bound.
+fn mean_variance((sum, sum_sq): (f64, f64), n: u32) -> f64 {
+    if n < 2 {
+        return sum_sq;
+    }
+    let n = n as f64;
+    let var = (sum_sq - sum * sum / n) / (n - 1.0);
+    var.max(0.0) / n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use RenderSettings;
+    use renderer::PathTracer;
+    use renderer::testing;
+    use traits::Renderer;
+
+    #[test]
+    fn set_contains() {
+        let set = AovSet::new().with(Aov::Depth).with(Aov::SampleCount);
+        assert!(set.contains(Aov::Depth));
+        assert!(!set.contains(Aov::Albedo));
+        assert_eq!(set.iter(), vec![Aov::Depth, Aov::SampleCount]);
+        assert_eq!(AovSet::all().iter().len(), AOVS.len());
+    }
+
+    #[test]
+    fn buffers_average_samples() {
+        let set = AovSet::new().with(Aov::Depth).with(Aov::SampleCount);
+        let mut buffers = AovBuffers::new(set, 2, 1);
+        let mut s = AovSample::new();
+        s.depth = 1.0;
+        buffers.add_sample(1, 0, &s);
+        s.depth = 3.0;
+        buffers.add_sample(1, 0, &s);
+
+        assert_eq!(buffers.get(Aov::Depth).unwrap().pixel(1, 0), Color::from(2.0));
+        assert_eq!(buffers.get(Aov::SampleCount).unwrap().pixel(1, 0), Color::from(2.0));
+        assert!(buffers.get(Aov::Albedo).is_none());
+    }
+
+    #[test]
+    fn variance_of_mean() {
+        let mut buffers = AovBuffers::new(AovSet::new().with(Aov::Variance), 1, 1);
+        let mut s = AovSample::new();
+        for &l in &[1.0, 3.0, 1.0, 3.0] {
+            s.color = Color::from(l);
+            buffers.add_sample(0, 0, &s);
+        }
+        // sample variance 4/3, over 4 samples
+        let var = buffers.get(Aov::Variance).unwrap().pixel(0, 0).r;
+        assert!((var - 1.0 / 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn pass_zero_is_not_counted() {
+        let scene = testing::diffuse_scene();
+        let camera = testing::diffuse_camera(4, 4);
+        for &in_flight in &[1, 2] {
+            let setup = RenderSettings::new(3, 2).with_passes_in_flight(in_flight);
+            let mut buffers = AovBuffers::new(AovSet::new().with(Aov::SampleCount), 4, 4);
+            let mut img = Texture::<Color>::new(4, 4);
+            let mut pt = PathTracer::new(&setup);
+            pt.render_scene_aovs(&scene, &camera, &setup, &mut img, &mut buffers);
+            // the image averages passes 1 and 2
+            let counts = buffers.get(Aov::SampleCount).unwrap();
+            assert!(counts.pixels().all(|c| c == Color::from(2.0)));
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use {Color, RenderSettings};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use traits::TexView;
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 1;
+
+/// Accumulated film of an unfinished render.
+///
+/// Holds the mean of every pixel together with its sample count, the number
+/// of finished passes and the settings the render was started with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    width: u32,
+    height: u32,
+    passes: u32,
+    seed: u64,
+    fingerprint: u64,
+    pixels: Vec<Color>,
+    samples: Vec<u32>,
+}
+
+impl Checkpoint {
+    /// Captures `image` after `passes` finished passes.
+    pub fn capture(setup: &RenderSettings, passes: u32, image: &TexView<Color>) -> Checkpoint {
+        let (width, height) = (image.width(), image.height());
+        let mut pixels = Vec::with_capacity(width * height);
+        for j in 0..height {
+            for i in 0..width {
+                pixels.push(image.pixel(i, j));
+            }
+        }
+        Checkpoint {
+            width: width as u32,
+            height: height as u32,
+            passes,
+            seed: setup.seed(),
+            fingerprint: setup.fingerprint(),
+            samples: vec![Self::samples_per_pixel(passes); pixels.len()],
+            pixels,
+        }
+    }
+
+    /// Number of samples averaged in a pixel after `passes` passes, the
+    /// second pass replaces the first one.
+    pub fn samples_per_pixel(passes: u32) -> u32 {
+        if passes > 1 { passes - 1 } else { passes }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn passes(&self) -> u32 {
+        self.passes
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> (Color, u32) {
+        let ix = (y * self.width + x) as usize;
+        (self.pixels[ix], self.samples[ix])
+    }
+
+    /// Checks that the checkpoint was made with `setup` and fits `image`.
+    pub fn check(&self, setup: &RenderSettings, image: &TexView<Color>) -> io::Result<()> {
+        if self.fingerprint != setup.fingerprint() {
+            return Err(invalid_data("checkpoint was made with other render settings"));
+        }
+        if self.width as usize != image.width() || self.height as usize != image.height() {
+            return Err(invalid_data("checkpoint size does not match the image size"));
+        }
+        Ok(())
+    }
+
+    /// Writes the accumulated film to `out_image` and returns the pass the
+    /// render continues from.
+    pub fn restore(&self, setup: &RenderSettings, out_image: &mut TexView<Color>) -> io::Result<u32> {
+        self.check(setup, out_image)?;
+        for j in 0..self.height {
+            for i in 0..self.width {
+                let (c, _) = self.pixel(i, j);
+                out_image.set_pixel(i as usize, j as usize, c);
+            }
+        }
+        Ok(self.passes)
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        write_u32(w, VERSION)?;
+        write_u32(w, self.width)?;
+        write_u32(w, self.height)?;
+        write_u32(w, self.passes)?;
+        write_u64(w, self.seed)?;
+        write_u64(w, self.fingerprint)?;
+        for (c, &n) in self.pixels.iter().zip(self.samples.iter()) {
+            write_u32(w, c.r.to_bits())?;
+            write_u32(w, c.g.to_bits())?;
+            write_u32(w, c.b.to_bits())?;
+            write_u32(w, n)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Checkpoint> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a checkpoint file"));
+        }
+        if read_u32(r)? != VERSION {
+            return Err(invalid_data("unsupported checkpoint version"));
+        }
+        let width = read_u32(r)?;
+        let height = read_u32(r)?;
+        let passes = read_u32(r)?;
+        let seed = read_u64(r)?;
+        let fingerprint = read_u64(r)?;
+
+        let len = match (width as usize).checked_mul(height as usize) {
+            Some(len) => len,
+            None => return Err(invalid_data("checkpoint image too large")),
+        };
+        // not reserved up front, the header may be corrupt
+        let mut pixels = Vec::new();
+        let mut samples = Vec::new();
+        for _ in 0..len {
+            let pixel = read_pixel(r).map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => invalid_data("truncated checkpoint"),
+                _ => e,
+            })?;
+            pixels.push(pixel.0);
+            samples.push(pixel.1);
+        }
+
+        Ok(Checkpoint {
+            width,
+            height,
+            passes,
+            seed,
+            fingerprint,
+            pixels,
+            samples,
+        })
+    }
+
+    /// Saves the checkpoint next to `path` and renames it over `path`, so a
+    /// crash while saving keeps the previous checkpoint.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut w = BufWriter::new(File::create(&tmp_path)?);
+            self.write_to(&mut w)?;
+            w.flush()?;
+        }
+        fs::rename(&tmp_path, path)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Checkpoint> {
+        let mut r = BufReader::new(File::open(path)?);
+        Self::read_from(&mut r)
+    }
+}
+
+fn read_pixel<R: Read>(r: &mut R) -> io::Result<(Color, u32)> {
+    let red = f32::from_bits(read_u32(r)?);
+    let green = f32::from_bits(read_u32(r)?);
+    let blue = f32::from_bits(read_u32(r)?);
+    Ok((Color::new(red, green, blue), read_u32(r)?))
+}
+
+pub(crate) fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub(crate) fn write_u32<W: Write>(w: &mut W, v: u32) -> io::Result<()> {
+    w.write_all(&[v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8])
+}
+
+pub(crate) fn write_u64<W: Write>(w: &mut W, v: u64) -> io::Result<()> {
+    write_u32(w, v as u32)?;
+    write_u32(w, (v >> 32) as u32)
+}
+
+pub(crate) fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    Ok(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+}
+
+pub(crate) fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let lo = read_u32(r)? as u64;
+    let hi = read_u32(r)? as u64;
+    Ok(lo | hi << 32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use texture::Texture;
+
+    #[test]
+    fn write_read() {
+        let setup = RenderSettings::new(16, 4).with_seed(42);
+        let mut img = Texture::<Color>::new(3, 2);
+        img.set_pixel(1, 1, Color::new(0.25, 0.5, 2.0));
+
+        let ckpt = Checkpoint::capture(&setup, 5, &img);
+        let mut buf = Vec::new();
+        ckpt.write_to(&mut buf).unwrap();
+        let loaded = Checkpoint::read_from(&mut buf.as_slice()).unwrap();
+
+        assert_eq!(ckpt, loaded);
+        assert_eq!(loaded.pixel(1, 1), (Color::new(0.25, 0.5, 2.0), 4));
+        assert_eq!(loaded.seed(), 42);
+    }
+
+    #[test]
+    fn corrupt_header() {
+        let setup = RenderSettings::new(16, 4);
+        let img = Texture::<Color>::new(2, 2);
+        let mut buf = Vec::new();
+        Checkpoint::capture(&setup, 3, &img).write_to(&mut buf).unwrap();
+        // a huge image with the pixels of a small one
+        for b in &mut buf[8..16] {
+            *b = 0xff;
+        }
+
+        let err = Checkpoint::read_from(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn settings_mismatch() {
+        let setup = RenderSettings::new(16, 4);
+        let img = Texture::<Color>::new(2, 2);
+        let ckpt = Checkpoint::capture(&setup, 3, &img);
+
+        let mut out = Texture::<Color>::new(2, 2);
+        assert!(ckpt.restore(&RenderSettings::new(64, 4), &mut out).is_ok());
+        assert!(ckpt.restore(&RenderSettings::new(16, 5), &mut out).is_err());
+    }
+}
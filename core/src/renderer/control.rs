@@ -0,0 +1,431 @@
+use {Color, RenderSettings};
+use super::checkpoint::Checkpoint;
+use std::cmp::min;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use traits::TexView;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Progress {
+    /// Fully rendered passes.
+    pub passes_done: u32,
+    /// Passes being rendered, fewer than set by
+    /// `RenderSettings::with_passes_in_flight` in the last batch.
+    pub passes_in_flight: u32,
+    pub passes_total: u32,
+    /// Finished tiles of the passes currently being rendered.
+    pub tiles_done: usize,
+    pub tiles_total: usize,
+    pub elapsed: Duration,
+    /// `None` until the first tile is finished.
+    pub eta: Option<Duration>,
+}
+
+impl Progress {
+    /// Fraction of the whole render that is finished, in `[0, 1]`.
+    pub fn fraction(&self) -> f64 {
+        if self.passes_total == 0 {
+            return 1.0;
+        }
+        let tiles = if self.tiles_total > 0 {
+            self.tiles_done as f64 / self.tiles_total as f64
+        } else {
+            0.0
+        };
+        let passes = self.passes_done as f64 + tiles * self.passes_in_flight as f64;
+        passes / self.passes_total as f64
+    }
+}
+
+/// Outcome of a controlled render, each variant holds the number of
+/// completed passes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RenderStatus {
+    Completed(u32),
+    Cancelled(u32),
+    BudgetExhausted(u32),
+}
+
+impl RenderStatus {
+    pub fn passes(&self) -> u32 {
+        match *self {
+            RenderStatus::Completed(p) |
+            RenderStatus::Cancelled(p) |
+            RenderStatus::BudgetExhausted(p) => p,
+        }
+    }
+}
+
+/// Cloneable handle that cancels a render from another thread.
+#[derive(Clone, Debug)]
+pub struct Canceller {
+    flag: Arc<AtomicBool>,
+}
+
+impl Canceller {
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::SeqCst)
+    }
+}
+
+pub type ProgressFn = Fn(&Progress) + Send + Sync;
+
+/// Controls a render started with `Renderer::render_scene_controlled`.
+///
+/// Cancellation is checked after every tile, the budgets are checked
+/// between passes, so the output image always holds whole passes or, after
+/// a cancellation, whole tiles.
+pub struct RenderControl {
+    cancelled: Arc<AtomicBool>,
+    time_budget: Option<Duration>,
+    spp_budget: Option<u32>,
+    progress: Option<Box<ProgressFn>>,
+    checkpoint: Option<(PathBuf, u32)>,
+    start_time: Mutex<Option<Instant>>,
+}
+
+impl RenderControl {
+    pub fn new() -> RenderControl {
+        RenderControl {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            time_budget: None,
+            spp_budget: None,
+            progress: None,
+            checkpoint: None,
+            start_time: Mutex::new(None),
+        }
+    }
+
+    /// Stops the render after the pass that exceeds the wall-clock budget.
+    pub fn with_time_budget(mut self, budget: Duration) -> Self {
+        self.time_budget = Some(budget);
+        self
+    }
+
+    /// Limits the number of passes below `RenderSettings::samples_per_pixel`.
+    pub fn with_spp_budget(mut self, spp: u32) -> Self {
+        self.spp_budget = Some(spp);
+        self
+    }
+
+    /// Sets a callback invoked from the worker threads after every tile.
+    pub fn on_progress<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        self.progress = Some(Box::new(f));
+        self
+    }
+
+    /// Saves a `Checkpoint` to `path` every `every_passes` passes and when
+    /// the render stops. Checkpoints hold only whole passes, a render
+    /// cancelled in the middle of passes saves the image from before them.
+    pub fn with_checkpoint<P: AsRef<Path>>(mut self, path: P, every_passes: u32) -> Self {
+        let every_passes = if every_passes > 0 { every_passes } else { 1 };
+        self.checkpoint = Some((path.as_ref().to_path_buf(), every_passes));
+        self
+    }
+
+    pub fn canceller(&self) -> Canceller {
+        Canceller {
+            flag: self.cancelled.clone(),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn passes_total(&self, samples_per_pixel: u32) -> u32 {
+        match self.spp_budget {
+            Some(spp) => min(spp, samples_per_pixel),
+            None => samples_per_pixel,
+        }
+    }
+
+    /// Whether the spp budget stops the render before `samples_per_pixel`.
+    pub fn spp_budget_reached(&self, samples_per_pixel: u32) -> bool {
+        match self.spp_budget {
+            Some(spp) => spp < samples_per_pixel,
+            None => false,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        match *self.start_time.lock().unwrap() {
+            Some(t) => t.elapsed(),
+            None => Duration::from_secs(0),
+        }
+    }
+
+    pub fn is_time_exceeded(&self) -> bool {
+        match self.time_budget {
+            Some(budget) => self.elapsed() >= budget,
+            None => false,
+        }
+    }
+
+    /// Saves `image` after `passes` finished passes if it is time for a
+    /// checkpoint or `force` is set.
+    pub(crate) fn save_checkpoint(
+        &self,
+        setup: &RenderSettings,
+        passes: u32,
+        image: &TexView<Color>,
+        force: bool,
+    ) -> io::Result<()> {
+        match self.checkpoint {
+            Some((ref path, every_passes)) if force || passes % every_passes == 0 => {
+                Checkpoint::capture(setup, passes, image).save(path)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Captures `image` at a pass boundary, `None` without checkpoints.
+    pub(crate) fn capture_checkpoint(
+        &self,
+        setup: &RenderSettings,
+        passes: u32,
+        image: &TexView<Color>,
+    ) -> Option<Checkpoint> {
+        self.checkpoint
+            .as_ref()
+            .map(|_| Checkpoint::capture(setup, passes, image))
+    }
+
+    pub(crate) fn write_checkpoint(&self, checkpoint: &Checkpoint) -> io::Result<()> {
+        match self.checkpoint {
+            Some((ref path, _)) => checkpoint.save(path),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn start(&self) {
+        *self.start_time.lock().unwrap() = Some(Instant::now());
+    }
+
+    pub(crate) fn report(
+        &self,
+        (passes_done, passes_in_flight, passes_total): (u32, u32, u32),
+        (tiles_done, tiles_total): (usize, usize),
+    ) {
+        if let Some(ref f) = self.progress {
+            let elapsed = self.elapsed();
+            let mut progress = Progress {
+                passes_done,
+                passes_in_flight,
+                passes_total,
+                tiles_done,
+                tiles_total,
+                elapsed,
+                eta: None,
+            };
+            let fraction = progress.fraction();
+            if fraction > 0.0 {
+                let secs = duration_secs(&elapsed) * (1.0 - fraction) / fraction;
+                let mut eta = secs_duration(secs);
+                if let Some(budget) = self.time_budget {
+                    let left = if budget > elapsed {
+                        budget - elapsed
+                    } else {
+                        Duration::from_secs(0)
+                    };
+                    eta = min(eta, left);
+                }
+                progress.eta = Some(eta);
+            }
+            f(&progress);
+        }
+    }
+}
+
+impl Default for RenderControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn duration_secs(d: &Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 * 1.0e-9
+}
+
+fn secs_duration(secs: f64) -> Duration {
+    let secs = secs.max(0.0);
+    Duration::new(secs.trunc() as u64, (secs.fract() * 1.0e9) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color;
+    use math::{Point3f, Ray3f, Vector3f};
+    use renderer::{Renderer, WorkerPool};
+    use renderer::inner::RendererHelper;
+    use renderer::testing::{self, TestCamera};
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+    use texture::Texture;
+    use traits::{RenderCamera, SceneHandler};
+
+    /// Traces white paths taking `path_time` each and counts them.
+    struct CountingRenderer {
+        paths: AtomicUsize,
+        path_time: Duration,
+        workers: WorkerPool,
+    }
+
+    impl CountingRenderer {
+        fn new(path_time: Duration) -> CountingRenderer {
+            CountingRenderer {
+                paths: AtomicUsize::new(0),
+                path_time,
+                workers: WorkerPool::new(),
+            }
+        }
+
+        fn paths(&self) -> usize {
+            self.paths.load(Ordering::SeqCst)
+        }
+    }
+
+    impl<S, C> RendererHelper<S, C> for CountingRenderer
+    where
+        S: SceneHandler + ?Sized,
+        C: RenderCamera + ?Sized,
+    {
+        fn trace_path(&self, _: &S, _: &Ray3f, _: &RenderSettings) -> Color {
+            self.paths.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(self.path_time);
+            color::WHITE
+        }
+
+        fn get_ray(&self, _: &C, _: u32, _: u32) -> Option<Ray3f> {
+            Some(Ray3f::new(&Point3f::new(0.0, 0.0, 0.0), &Vector3f::new(0.0, 0.0, -1.0)))
+        }
+
+        fn workers(&self) -> &WorkerPool {
+            &self.workers
+        }
+    }
+
+    impl<S, C> Renderer<S, C> for CountingRenderer
+    where
+        S: SceneHandler + ?Sized,
+        C: RenderCamera + ?Sized,
+    {
+        fn pre_render(&mut self, _: &S, _: &C, _: &RenderSettings) {}
+    }
+
+    fn try_render(
+        r: &mut CountingRenderer,
+        spp: u32,
+        control: &RenderControl,
+    ) -> io::Result<RenderStatus> {
+        let scene = testing::empty_scene();
+        let camera = TestCamera::new(4, 4);
+        let setup = RenderSettings::new(spp, 4);
+        let mut img = Texture::<Color>::new(4, 4);
+        r.render_scene_controlled(&scene, &camera, &setup, &mut img, control)
+    }
+
+    fn render(r: &mut CountingRenderer, spp: u32, control: &RenderControl) -> RenderStatus {
+        try_render(r, spp, control).unwrap()
+    }
+
+    #[test]
+    fn spp_budget_stops_after_passes() {
+        let mut r = CountingRenderer::new(Duration::from_secs(0));
+        let control = RenderControl::new().with_spp_budget(3);
+        assert_eq!(render(&mut r, 10, &control), RenderStatus::BudgetExhausted(3));
+        assert_eq!(r.paths(), 3 * 16);
+
+        let mut r = CountingRenderer::new(Duration::from_secs(0));
+        let control = RenderControl::new().with_spp_budget(20);
+        assert_eq!(render(&mut r, 10, &control), RenderStatus::Completed(10));
+    }
+
+    #[test]
+    fn time_budget_stops_after_deadline() {
+        let mut r = CountingRenderer::new(Duration::from_millis(1));
+        let budget = Duration::from_millis(40);
+        let control = RenderControl::new().with_time_budget(budget);
+        let status = render(&mut r, 1000, &control);
+        // stopped between passes once the deadline has passed
+        assert_eq!(status, RenderStatus::BudgetExhausted(status.passes()));
+        assert!(status.passes() > 0 && status.passes() < 1000);
+        assert_eq!(r.paths(), status.passes() as usize * 16);
+        assert!(control.elapsed() >= budget);
+    }
+
+    #[test]
+    fn cancel_stops_after_current_tile() {
+        let mut r = CountingRenderer::new(Duration::from_secs(0));
+        let control = RenderControl::new();
+        let canceller = control.canceller();
+        let control = control.on_progress(move |p| if p.tiles_done == 5 {
+            canceller.cancel();
+        });
+        // single pixel tiles on one thread
+        assert_eq!(render(&mut r, 10, &control), RenderStatus::Cancelled(0));
+        assert_eq!(r.paths(), 5);
+        assert!(control.is_cancelled());
+    }
+
+    #[test]
+    fn cancelled_checkpoint_holds_whole_passes() {
+        let path = ::std::env::temp_dir().join("raytron-control-test.ckpt");
+        let mut r = CountingRenderer::new(Duration::from_secs(0));
+        let control = RenderControl::new().with_checkpoint(&path, 10);
+        let canceller = control.canceller();
+        let control = control.on_progress(move |p| if p.passes_done == 2 && p.tiles_done == 5 {
+            canceller.cancel();
+        });
+        assert_eq!(render(&mut r, 10, &control), RenderStatus::Cancelled(2));
+        assert_eq!(Checkpoint::load(&path).unwrap().passes(), 2);
+        let _ = ::std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn progress_counts_last_batch() {
+        let mut r = CountingRenderer::new(Duration::from_secs(0));
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let control = {
+            let reports = reports.clone();
+            RenderControl::new().on_progress(move |p| reports.lock().unwrap().push(*p))
+        };
+        let scene = testing::empty_scene();
+        let camera = TestCamera::new(4, 4);
+        let setup = RenderSettings::new(3, 4).with_passes_in_flight(2);
+        let mut img = Texture::<Color>::new(4, 4);
+        r.render_scene_controlled(&scene, &camera, &setup, &mut img, &control)
+            .unwrap();
+
+        let reports = reports.lock().unwrap();
+        let last = reports[reports.len() - 1];
+        assert_eq!((last.passes_done, last.passes_in_flight), (2, 1));
+        assert_eq!(last.fraction(), 1.0);
+        assert_eq!(reports[15].fraction(), 2.0 / 3.0);
+    }
+
+    #[test]
+    fn checkpoint_error_is_returned() {
+        let path = ::std::env::temp_dir().join("raytron-missing-dir").join("ckpt");
+        let mut r = CountingRenderer::new(Duration::from_secs(0));
+        let control = RenderControl::new().with_checkpoint(&path, 1);
+        assert!(try_render(&mut r, 2, &control).is_err());
+        assert_eq!(r.paths(), 16);
+    }
+}
@@ -1,60 +1,223 @@
-
-
+use super::WorkerPool;
 use super::inner::{CameraRayGenerator, RendererHelper};
-use {Color, RenderSettings};
+use {Color, RenderSettings, SurfacePoint};
 use color;
-use math::{Dot, Norm, Ray3f};
+use math::{Dot, Norm, Ray3f, Real, Vector3f};
+use std::hash::Hasher;
 use traits::{RenderCamera, Renderer, SceneHandler};
+use utils;
+
+/// What `DbgRayCaster` shows at the first hit of every camera ray.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DbgMode {
+    /// Shading normal as color times the cosine to the first light source.
+    Lit,
+    ShadingNormal,
+    GeometricNormal,
+    /// Fractional part of the texture coordinates, magenta if there are
+    /// none.
+    Uv,
+    /// Barycentric coordinates of triangles, magenta for other surfaces.
+    Barycentric,
+    /// White at the camera fading to black at the depth range.
+    Depth,
+    Albedo,
+    /// Random color per object.
+    ObjectId,
+    /// Surfaces tested per ray, blue for none to red for the cost range.
+    TraversalCost,
+}
 
+pub const DBG_MODES: [DbgMode; 9] = [
+    DbgMode::Lit,
+    DbgMode::ShadingNormal,
+    DbgMode::GeometricNormal,
+    DbgMode::Uv,
+    DbgMode::Barycentric,
+    DbgMode::Depth,
+    DbgMode::Albedo,
+    DbgMode::ObjectId,
+    DbgMode::TraversalCost,
+];
+
+impl DbgMode {
+    /// The mode after this one in `DBG_MODES`, wrapping around.
+    pub fn next(&self) -> DbgMode {
+        let ix = DBG_MODES.iter().position(|m| m == self).unwrap();
+        DBG_MODES[(ix + 1) % DBG_MODES.len()]
+    }
+}
 
 pub struct DbgRayCaster {
     ray_gen: CameraRayGenerator,
+    workers: WorkerPool,
+    mode: DbgMode,
+    /// (color, width in barycentric coordinates)
+    wireframe: Option<(Color, Real)>,
+    depth_range: Option<Real>,
+    cost_range: u32,
+    camera_zfar: Real,
 }
 
 impl DbgRayCaster {
     pub fn new() -> DbgRayCaster {
         DbgRayCaster {
             ray_gen: CameraRayGenerator::new(),
+            workers: WorkerPool::new(),
+            mode: DbgMode::Lit,
+            wireframe: None,
+            depth_range: None,
+            cost_range: 64,
+            camera_zfar: 1.0,
         }
     }
 
+    pub fn with_mode(mut self, mode: DbgMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Draws triangle edges over any mode, `width` is the distance from
+    /// the edge in barycentric coordinates.
+    pub fn with_wireframe(mut self, color: Color, width: Real) -> Self {
+        self.wireframe = Some((color, width));
+        self
+    }
+
+    /// Depth shown as black, the camera far plane by default.
+    pub fn with_depth_range(mut self, depth: Real) -> Self {
+        self.depth_range = Some(depth);
+        self
+    }
+
+    /// Number of surface tests shown as red.
+    pub fn with_cost_range(mut self, tests: u32) -> Self {
+        self.cost_range = tests;
+        self
+    }
+
+    pub fn mode(&self) -> DbgMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: DbgMode) {
+        self.mode = mode;
+    }
+
+    pub fn set_wireframe(&mut self, wireframe: Option<(Color, Real)>) {
+        self.wireframe = wireframe;
+    }
+
+    pub fn wireframe(&self) -> Option<(Color, Real)> {
+        self.wireframe
+    }
+
     fn trace_path_rec<S>(&self, scene: &S, ray: &Ray3f, _: u32) -> Color
     where
         S: SceneHandler + ?Sized,
     {
+        if self.mode == DbgMode::TraversalCost {
+            return match scene.traversal_cost(ray) {
+                Some(cost) => heatmap(cost.tests as Real / self.cost_range as Real),
+                None => color::MAGENTA,
+            };
+        }
 
         if let Some(sp) = scene.intersection(ray) {
-            let mat = sp.bsdf;
-            if let Some(c) = mat.radiance() {
-                return c;
-            } else if let Some(light) = scene.light_sources().iter().into_iter().next() {
-                let (light_point, _) = light.sample_surface_p((&sp.position, &sp.normal));
-                let shadow_ray = Ray3f::new(
-                    &sp.position,
-                    &(light_point.position - sp.position).normalize(),
-                );
-
-                let color = Color::new(
-                    (0.5 + sp.normal.x * 0.5) as f32,
-                    (0.5 + sp.normal.y * 0.5) as f32,
-                    (0.5 + sp.normal.z * 0.5) as f32,
-                );
-                //return color;
-                if let Some(lp) = scene.intersection(&shadow_ray) {
-
-                    if lp.bsdf.radiance().is_some() {
-                        let cos_theta = sp.normal.dot(&shadow_ray.dir);
-
-                        return color * (cos_theta as f32);
-
-                    }
-                }
+            let color = self.shade(scene, ray, &sp);
+            match (self.wireframe, sp.surface.barycentric(&sp.position, ray.time)) {
+                (Some((wire, width)), Some((b0, b1, b2))) if b0.min(b1).min(b2) < width => wire,
+                _ => color,
             }
         } else {
-            return color::BLACK;
+            color::BLACK
         }
-        color::BLACK
+    }
+
+    fn shade<S>(&self, scene: &S, ray: &Ray3f, sp: &SurfacePoint) -> Color
+    where
+        S: SceneHandler + ?Sized,
+    {
+        match self.mode {
+            DbgMode::Lit => lit(scene, ray, sp),
+            DbgMode::ShadingNormal => normal_color(&sp.normal),
+            DbgMode::GeometricNormal => {
+                normal_color(&sp.surface.normal_at(&sp.position, ray.time))
+            }
+            DbgMode::Uv => match sp.surface.uv(&sp.position, ray.time) {
+                Some(uv) => Color::new(
+                    (uv.x - uv.x.floor()) as f32,
+                    (uv.y - uv.y.floor()) as f32,
+                    0.0,
+                ),
+                None => color::MAGENTA,
+            },
+            DbgMode::Barycentric => match sp.surface.barycentric(&sp.position, ray.time) {
+                Some((b0, b1, b2)) => Color::new(b0 as f32, b1 as f32, b2 as f32),
+                None => color::MAGENTA,
+            },
+            DbgMode::Depth => {
+                let range = self.depth_range.unwrap_or(self.camera_zfar);
+                let d = (sp.position - ray.origin).norm() / range;
+                Color::from(utils::clamp(1.0 - d, 0.0, 1.0) as f32)
+            }
+            DbgMode::Albedo => sp.bsdf.albedo(&sp.normal, &ray.dir),
+            DbgMode::ObjectId => id_color(scene.object_id(sp.surface)),
+            DbgMode::TraversalCost => unreachable!(),
+        }
+    }
+}
+
+fn lit<S>(scene: &S, ray: &Ray3f, sp: &SurfacePoint) -> Color
+where
+    S: SceneHandler + ?Sized,
+{
+    if let Some(c) = sp.bsdf.radiance() {
+        return c;
+    }
+    if let Some(light) = scene.light_sources().iter().into_iter().next() {
+        let (light_point, _) = light.sample_surface_p((&sp.position, &sp.normal), ray.time);
+        let shadow_ray = Ray3f::with_time(
+            &sp.position,
+            &(light_point.position - sp.position).normalize(),
+            ray.time,
+        );
+
+        if let Some(lp) = scene.intersection(&shadow_ray) {
+            if lp.bsdf.radiance().is_some() {
+                let cos_theta = sp.normal.dot(&shadow_ray.dir);
+                return normal_color(&sp.normal) * (cos_theta as f32);
+            }
+        }
+    }
+    color::BLACK
+}
 
+fn normal_color(n: &Vector3f) -> Color {
+    Color::new(
+        (0.5 + n.x * 0.5) as f32,
+        (0.5 + n.y * 0.5) as f32,
+        (0.5 + n.z * 0.5) as f32,
+    )
+}
+
+/// Bright color from the bits of the hashed `id`, so that consecutive ids
+/// differ.
+fn id_color(id: u32) -> Color {
+    let mut h = utils::FnvHasher::default();
+    h.write_u32(id);
+    let hash = h.finish();
+    let c = |shift: u32| 0.2 + 0.8 * ((hash >> shift) & 0xff) as f32 / 255.0;
+    Color::new(c(0), c(8), c(16))
+}
+
+/// Blue through green to red for `x` in `[0, 1]`.
+fn heatmap(x: Real) -> Color {
+    let x = utils::clamp(x, 0.0, 1.0) as f32;
+    if x < 0.5 {
+        Color::new(0.0, 2.0 * x, 1.0 - 2.0 * x)
+    } else {
+        Color::new(2.0 * x - 1.0, 2.0 - 2.0 * x, 0.0)
     }
 }
 
@@ -63,14 +226,19 @@ impl<S: SceneHandler + ?Sized, C: RenderCamera + ?Sized> RendererHelper<S, C> fo
         self.trace_path_rec::<S>(scene, initial_ray, 0)
     }
 
-    fn get_ray(&self, _: &C, x: u32, y: u32) -> Ray3f {
+    fn get_ray(&self, _: &C, x: u32, y: u32) -> Option<Ray3f> {
         self.ray_gen.get_ray(x, y)
     }
+
+    fn workers(&self) -> &WorkerPool {
+        &self.workers
+    }
 }
 
 impl<S: SceneHandler + ?Sized, C: RenderCamera + ?Sized> Renderer<S, C> for DbgRayCaster {
     fn pre_render(&mut self, _: &S, camera: &C, _: &RenderSettings) {
         self.ray_gen = CameraRayGenerator::with_camera(camera);
+        self.camera_zfar = camera.zfar();
     }
 }
 
@@ -0,0 +1,436 @@
+//! Rendering on several processes connected over TCP.
+//!
+//! A `Coordinator` splits the image into tiles and pass ranges and hands
+//! them out to workers started with `run_worker`. Every worker loads the
+//! same scene, renders the jobs it gets and sends back the per-pixel sums.
+//! Jobs of a worker that disconnects or does not answer within the job
+//! timeout are handed out again. Renderers whose `render_job` does not
+//! render a tile on its own, e.g. splatting ones, cannot be distributed.
+
+use {Color, RenderSettings};
+use super::checkpoint::{invalid_data, read_u32, read_u64, write_u32, write_u64};
+use super::inner::{tile_source, RendererHelper};
+use super::scheduler::split_tiles;
+use color::Rgb;
+use std::cmp::{max, min};
+use std::collections::VecDeque;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use traits::{RenderCamera, Renderer, SceneHandler, TexView};
+use utils::rng;
+
+const MSG_HELLO: u32 = 0x5254_0001;
+const MSG_JOB: u32 = 0x5254_0002;
+const MSG_DONE: u32 = 0x5254_0003;
+const MSG_RESULT: u32 = 0x5254_0004;
+
+const POLL_INTERVAL_MS: u64 = 20;
+/// Time a connected worker has to introduce itself.
+const HELLO_TIMEOUT_MS: u64 = 10_000;
+
+pub type WorkerErrorFn = Fn(&io::Error) + Send + Sync;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Job {
+    id: u32,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    first_pass: u32,
+    passes: u32,
+}
+
+impl Job {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_u32(w, MSG_JOB)?;
+        for &v in &[self.id, self.x, self.y, self.width, self.height, self.first_pass, self.passes] {
+            write_u32(w, v)?;
+        }
+        Ok(())
+    }
+
+    fn read_from<R: Read>(r: &mut R) -> io::Result<Job> {
+        Ok(Job {
+            id: read_u32(r)?,
+            x: read_u32(r)?,
+            y: read_u32(r)?,
+            width: read_u32(r)?,
+            height: read_u32(r)?,
+            first_pass: read_u32(r)?,
+            passes: read_u32(r)?,
+        })
+    }
+}
+
+struct Film {
+    width: u32,
+    sum: Vec<Rgb<f64>>,
+    samples: Vec<u32>,
+}
+
+struct Shared {
+    jobs: Mutex<VecDeque<Job>>,
+    jobs_left: Mutex<usize>,
+    film: Mutex<Film>,
+    fingerprint: u64,
+    image_size: (u32, u32),
+    job_timeout: Duration,
+}
+
+impl Shared {
+    fn is_finished(&self) -> bool {
+        *self.jobs_left.lock().unwrap() == 0
+    }
+
+    fn merge(&self, job: &Job, sums: &[Color]) {
+        let mut film = self.film.lock().unwrap();
+        for j in 0..job.height {
+            for i in 0..job.width {
+                let ix = ((job.y + j) * film.width + job.x + i) as usize;
+                film.sum[ix] += Rgb::<f64>::from(sums[(j * job.width + i) as usize]);
+                film.samples[ix] += job.passes;
+            }
+        }
+        *self.jobs_left.lock().unwrap() -= 1;
+    }
+
+    fn serve(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut r = BufReader::new(stream.try_clone()?);
+        let mut w = BufWriter::new(stream.try_clone()?);
+
+        stream.set_read_timeout(Some(Duration::from_millis(HELLO_TIMEOUT_MS)))?;
+        if read_u32(&mut r)? != MSG_HELLO {
+            return Err(invalid_data("unexpected message"));
+        }
+        let fingerprint = read_u64(&mut r)?;
+        let size = (read_u32(&mut r)?, read_u32(&mut r)?);
+        if fingerprint != self.fingerprint || size != self.image_size {
+            return Err(invalid_data("worker has other render settings"));
+        }
+        stream.set_read_timeout(Some(self.job_timeout))?;
+
+        loop {
+            let job = self.jobs.lock().unwrap().pop_front();
+            let job = match job {
+                Some(job) => job,
+                None => if self.is_finished() {
+                    write_u32(&mut w, MSG_DONE)?;
+                    return w.flush();
+                } else {
+                    thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                    continue;
+                },
+            };
+
+            match self.dispatch(&job, &mut r, &mut w) {
+                Ok(sums) => self.merge(&job, &sums),
+                Err(e) => {
+                    self.jobs.lock().unwrap().push_front(job);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    fn dispatch<R: Read, W: Write>(&self, job: &Job, r: &mut R, w: &mut W) -> io::Result<Vec<Color>> {
+        job.write_to(w)?;
+        w.flush()?;
+
+        if read_u32(r)? != MSG_RESULT || read_u32(r)? != job.id {
+            return Err(invalid_data("unexpected message"));
+        }
+        let len = (job.width * job.height) as usize;
+        let mut sums = Vec::with_capacity(len);
+        for _ in 0..len {
+            let red = f32::from_bits(read_u32(r)?);
+            let green = f32::from_bits(read_u32(r)?);
+            let blue = f32::from_bits(read_u32(r)?);
+            sums.push(Color::new(red, green, blue));
+        }
+        Ok(sums)
+    }
+}
+
+/// Hands out the jobs of a render to the connected workers and merges the
+/// returned samples.
+pub struct Coordinator {
+    listener: TcpListener,
+    setup: RenderSettings,
+    job_timeout: Duration,
+    on_worker_error: Option<Arc<WorkerErrorFn>>,
+}
+
+impl Coordinator {
+    pub fn bind<A: ToSocketAddrs>(addr: A, setup: &RenderSettings) -> io::Result<Coordinator> {
+        Ok(Coordinator {
+            listener: TcpListener::bind(addr)?,
+            setup: *setup,
+            job_timeout: Duration::from_secs(600),
+            on_worker_error: None,
+        })
+    }
+
+    /// A worker that does not return a job within `timeout` is dropped and
+    /// its job is handed out again.
+    pub fn with_job_timeout(mut self, timeout: Duration) -> Self {
+        self.job_timeout = timeout;
+        self
+    }
+
+    /// Sets a callback invoked with the error of every dropped worker.
+    pub fn on_worker_error<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&io::Error) + Send + Sync + 'static,
+    {
+        self.on_worker_error = Some(Arc::new(f));
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Renders `setup.samples_per_pixel` passes on the workers and writes
+    /// the mean of the returned samples to `out_image`, which like a local
+    /// render leaves out pass 0 unless it is the only one. Blocks until
+    /// every job is done, waiting for new workers if all of them fail.
+    pub fn render(&self, out_image: &mut TexView<Color>) -> io::Result<()> {
+        let image_size = (out_image.width() as u32, out_image.height() as u32);
+        let passes_step = max(self.setup.passes_in_flight, 1);
+
+        let mut jobs = VecDeque::new();
+        // pass 0 is replaced by pass 1 in a local render, see `add_to_pixel`
+        let mut first_pass = if self.setup.samples_per_pixel > 1 { 1 } else { 0 };
+        while first_pass < self.setup.samples_per_pixel {
+            let passes = min(passes_step, self.setup.samples_per_pixel - first_pass);
+            for tile in split_tiles(image_size, self.setup.render_chunk) {
+                let id = jobs.len() as u32;
+                jobs.push_back(Job {
+                    id,
+                    x: tile.x,
+                    y: tile.y,
+                    width: tile.width,
+                    height: tile.height,
+                    first_pass,
+                    passes,
+                });
+            }
+            first_pass += passes;
+        }
+
+        let pixels_num = (image_size.0 * image_size.1) as usize;
+        let shared = Arc::new(Shared {
+            jobs_left: Mutex::new(jobs.len()),
+            jobs: Mutex::new(jobs),
+            film: Mutex::new(Film {
+                width: image_size.0,
+                sum: vec![Rgb::from(0.0); pixels_num],
+                samples: vec![0; pixels_num],
+            }),
+            fingerprint: self.setup.fingerprint(),
+            image_size,
+            job_timeout: self.job_timeout,
+        });
+
+        self.listener.set_nonblocking(true)?;
+        let mut handlers = Vec::new();
+        while !shared.is_finished() {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(false)?;
+                    let shared = shared.clone();
+                    let on_error = self.on_worker_error.clone();
+                    handlers.push(thread::spawn(move || {
+                        if let (Err(e), Some(f)) = (shared.serve(stream), on_error) {
+                            f(&e);
+                        }
+                    }));
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        for h in handlers {
+            let _ = h.join();
+        }
+
+        let film = shared.film.lock().unwrap();
+        for j in 0..image_size.1 {
+            for i in 0..image_size.0 {
+                let ix = (j * image_size.0 + i) as usize;
+                let n = max(film.samples[ix], 1) as f64;
+                let c: Color = (film.sum[ix] * (1.0 / n)).into();
+                out_image.set_pixel(i as usize, j as usize, c);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Connects `setup.threads_num` worker threads to the coordinator at `addr`
+/// and renders the jobs they get until the coordinator is done. Returns the
+/// number of rendered jobs, fails for renderers that do not support jobs,
+/// see `RendererHelper::supports_jobs`.
+pub fn run_worker<A, R, S, C>(
+    addr: A,
+    renderer: &mut R,
+    scene: &S,
+    camera: &C,
+    setup: &RenderSettings,
+) -> io::Result<usize>
+where
+    A: ToSocketAddrs,
+    R: Renderer<S, C> + ?Sized,
+    S: SceneHandler + ?Sized,
+    C: RenderCamera + ?Sized,
+{
+    if !renderer.supports_jobs() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "renderer does not support distributed rendering",
+        ));
+    }
+    let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+    renderer.pre_render(scene, camera, setup);
+    let renderer: &R = renderer;
+
+    let results = Mutex::new(Vec::new());
+    renderer.workers().with_pool(setup.threads_num, |pool| {
+        pool.scoped(|scope| {
+            for _ in 0..max(setup.threads_num, 1) {
+                let addrs = &addrs;
+                let results = &results;
+                scope.execute(move || {
+                    let res = work(addrs.as_slice(), renderer, scene, camera, setup);
+                    results.lock().unwrap().push(res);
+                });
+            }
+            scope.join_all();
+        });
+    });
+
+    let mut jobs_num = 0;
+    for res in results.into_inner().unwrap() {
+        jobs_num += res?;
+    }
+    Ok(jobs_num)
+}
+
+fn work<R, S, C>(
+    addrs: &[SocketAddr],
+    renderer: &R,
+    scene: &S,
+    camera: &C,
+    setup: &RenderSettings,
+) -> io::Result<usize>
+where
+    R: Renderer<S, C> + ?Sized,
+    S: SceneHandler + ?Sized,
+    C: RenderCamera + ?Sized,
+{
+    let stream = TcpStream::connect(addrs)?;
+    stream.set_nodelay(true)?;
+    let mut r = BufReader::new(stream.try_clone()?);
+    let mut w = BufWriter::new(stream);
+
+    write_u32(&mut w, MSG_HELLO)?;
+    write_u64(&mut w, setup.fingerprint())?;
+    write_u32(&mut w, camera.width())?;
+    write_u32(&mut w, camera.height())?;
+    w.flush()?;
+
+    let mut jobs_num = 0;
+    loop {
+        match read_u32(&mut r)? {
+            MSG_JOB => {}
+            MSG_DONE => return Ok(jobs_num),
+            _ => return Err(invalid_data("unexpected message")),
+        }
+        let job = Job::read_from(&mut r)?;
+        let rect = ((job.x, job.y), (job.width, job.height));
+        let mut sums = vec![Rgb::<f32>::from(0.0); (job.width * job.height) as usize];
+        renderer.start_passes(job.first_pass);
+        for p in 0..job.passes {
+            let mut source = tile_source(setup, job.first_pass + p, (job.x, job.y), camera.width());
+            let chunk = rng::with_source(&mut source, || {
+                renderer.render_job(scene, camera, setup, rect)
+            });
+            for (s, c) in sums.iter_mut().zip(chunk.into_iter()) {
+                *s += c;
+            }
+        }
+
+        write_u32(&mut w, MSG_RESULT)?;
+        write_u32(&mut w, job.id)?;
+        for c in &sums {
+            write_u32(&mut w, c.r.to_bits())?;
+            write_u32(&mut w, c.g.to_bits())?;
+            write_u32(&mut w, c.b.to_bits())?;
+        }
+        w.flush()?;
+        jobs_num += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use renderer::{LightTracer, PathTracer};
+    use renderer::testing;
+    use texture::Texture;
+
+    #[test]
+    fn localhost_round_trip() {
+        let setup = RenderSettings::new(3, 4)
+            .with_threads(2, (4, 4))
+            .with_passes_in_flight(2);
+        let scene = testing::diffuse_scene();
+        let camera = testing::diffuse_camera(8, 8);
+
+        let coordinator = Coordinator::bind("127.0.0.1:0", &setup).unwrap();
+        let addr = coordinator.local_addr().unwrap();
+        let render = thread::spawn(move || {
+            let mut img = Texture::<Color>::new(8, 8);
+            coordinator.render(&mut img).map(|_| img)
+        });
+        let mut pt = PathTracer::new(&setup);
+        let jobs = run_worker(addr, &mut pt, &scene, &camera, &setup).unwrap();
+        let img = render.join().unwrap().unwrap();
+        // passes 1 and 2 of the 4 tiles, pass 0 is not kept
+        assert_eq!(jobs, 4);
+
+        // the workers draw the samples of a local render of the same seed,
+        // only summed in another order
+        let mut local = Texture::<Color>::new(8, 8);
+        pt.render_scene_threads(&scene, &camera, &setup, &mut local);
+        for j in 0..8 {
+            for i in 0..8 {
+                let (a, b) = (img.pixel(i, j), local.pixel(i, j));
+                let eps = 1e-5 * (1.0 + b.r.max(b.g).max(b.b));
+                assert!(
+                    (a.r - b.r).abs() < eps && (a.g - b.g).abs() < eps && (a.b - b.b).abs() < eps,
+                    "{:?} vs {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn splatting_renderer_is_rejected() {
+        let setup = RenderSettings::new(1, 4);
+        let mut lt = LightTracer::new(&setup);
+        let scene = testing::diffuse_scene();
+        let camera = testing::diffuse_camera(8, 8);
+        let res = run_worker("127.0.0.1:1", &mut lt, &scene, &camera, &setup);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,552 @@
+//! Path guiding with an SD-tree (Müller et al. 2017).
+//!
+//! A binary tree over space holds in every leaf a quadtree over the
+//! directions, mapped to the unit square by cylindrical coordinates, of the
+//! radiance arriving in the leaf. The radiance carried by the continuation
+//! rays is recorded during training iterations of 1, 2, 4, ... passes. At
+//! the end of an iteration the recorded quadtrees become the sampling
+//! distributions, leaves that got many records are split and the
+//! quadtrees are refined where they hold much of the energy.
+
+use {Color, SurfacePoint};
+use aabb::Aabb3;
+use color;
+use math::{Dot, Point3f, Real, Vector3f};
+use std::f64::consts::PI;
+use std::mem;
+use std::sync::{Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use utils::rng;
+
+/// Records a spatial leaf gets per iteration before it is split, scaled by
+/// the square root of the passes of the iteration.
+const SPATIAL_THRESHOLD: Real = 12000.0;
+/// Fraction of the energy above which a directional cell is subdivided.
+const DIRECTIONAL_THRESHOLD: Real = 0.01;
+const MAX_DIRECTIONAL_DEPTH: u32 = 20;
+
+pub struct Guide {
+    tree: RwLock<SdTree>,
+    bsdf_fraction: Real,
+    training_passes: u32,
+    iterations: AtomicUsize,
+}
+
+impl Guide {
+    /// Trains over the first `training_passes` passes, `bsdf_fraction` of
+    /// the guided directions sample the BSDF.
+    pub fn new(training_passes: u32, bsdf_fraction: Real) -> Guide {
+        Guide {
+            tree: RwLock::new(SdTree::new()),
+            bsdf_fraction,
+            training_passes,
+            iterations: AtomicUsize::new(0),
+        }
+    }
+
+    /// Passes rendered before the end of training iteration `k`.
+    fn iteration_end(k: usize) -> u32 {
+        (1 << (k + 1)) - 1
+    }
+
+    fn is_training_at(&self, k: usize) -> bool {
+        Guide::iteration_end(k) <= self.training_passes
+    }
+
+    fn is_training(&self) -> bool {
+        self.is_training_at(self.iterations.load(Ordering::SeqCst))
+    }
+
+    /// Ends the training iterations whose passes all come before
+    /// `pass_num`. Called before the pass is rendered.
+    pub fn start_pass(&self, pass_num: u32) {
+        let mut tree = self.tree.write().unwrap();
+        let mut k = self.iterations.load(Ordering::SeqCst);
+        while self.is_training_at(k) && Guide::iteration_end(k) <= pass_num {
+            tree.refine(SPATIAL_THRESHOLD * ((1 << k) as Real).sqrt());
+            k += 1;
+        }
+        self.iterations.store(k, Ordering::SeqCst);
+    }
+
+    /// Records radiance arriving at `p` from `-dir` over the pdf `dir` was
+    /// sampled with.
+    pub fn record(&self, p: &Point3f, dir: &Vector3f, radiance: Color, pdf: Real) {
+        if pdf <= 0.0 || !self.is_training() {
+            return;
+        }
+        let value = color::luminance(&radiance) as Real / pdf;
+        if value.is_finite() {
+            self.tree.read().unwrap().record(p, dir_to_square(dir), value);
+        }
+    }
+
+    /// Direction at `sp` sampled from the mixture of the BSDF and the
+    /// learned distribution, with the BSDF times cosine over the pdf and
+    /// the solid angle pdf. `None` where nothing was learned yet.
+    pub fn sample(&self, sp: &SurfacePoint, in_dir: &Vector3f) -> Option<(Vector3f, Color, Real)> {
+        let tree = self.tree.read().unwrap();
+        let dtree = match tree.distribution(&sp.position) {
+            Some(dtree) => dtree,
+            None => return None,
+        };
+
+        let dir = if rng::uniform() < self.bsdf_fraction {
+            sp.bsdf.sample(&sp.normal, in_dir).0
+        } else {
+            square_to_dir(dtree.sample())
+        };
+        let (f, pdf_bsdf) = sp.bsdf.eval(&sp.normal, in_dir, &dir);
+        let pdf = self.mixture_pdf(dtree, pdf_bsdf, &dir);
+        let cos = sp.normal.dot(&dir);
+        let weight = if cos > 0.0 && pdf > 0.0 {
+            f * (cos / pdf) as f32
+        } else {
+            color::BLACK
+        };
+        Some((dir, weight, pdf))
+    }
+
+    /// Solid angle pdf of `sample` taking `dir`, `None` where it falls back
+    /// to the BSDF alone.
+    pub fn pdf(&self, sp: &SurfacePoint, in_dir: &Vector3f, dir: &Vector3f) -> Option<Real> {
+        let tree = self.tree.read().unwrap();
+        tree.distribution(&sp.position).map(|dtree| {
+            let pdf_bsdf = sp.bsdf.eval(&sp.normal, in_dir, dir).1;
+            self.mixture_pdf(dtree, pdf_bsdf, dir)
+        })
+    }
+
+    fn mixture_pdf(&self, dtree: &DTree, pdf_bsdf: Real, dir: &Vector3f) -> Real {
+        let pdf_guide = dtree.pdf(dir_to_square(dir)) / (4.0 * PI as Real);
+        self.bsdf_fraction * pdf_bsdf + (1.0 - self.bsdf_fraction) * pdf_guide
+    }
+}
+
+/// Area preserving map of the sphere to the unit square, `dω = 4π du dv`.
+fn dir_to_square(dir: &Vector3f) -> (Real, Real) {
+    let u = 0.5 * (dir.z.max(-1.0).min(1.0) + 1.0);
+    let phi = dir.y.atan2(dir.x);
+    let v = phi / (2.0 * PI as Real);
+    (u.min(1.0 - 1e-9), (v - v.floor()).min(1.0 - 1e-9))
+}
+
+fn square_to_dir((u, v): (Real, Real)) -> Vector3f {
+    let cos_theta = 2.0 * u - 1.0;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI as Real * v;
+    Vector3f::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+enum SNode {
+    Split {
+        axis: usize,
+        pos: Real,
+        children: [usize; 2],
+    },
+    Leaf(usize),
+}
+
+struct SLeaf {
+    sampling: DTree,
+    recording: Mutex<Recording>,
+}
+
+struct Recording {
+    dtree: DTree,
+    records: usize,
+    bounds: Option<Aabb3>,
+}
+
+impl Recording {
+    fn new(dtree: DTree) -> Recording {
+        Recording {
+            dtree,
+            records: 0,
+            bounds: None,
+        }
+    }
+}
+
+/// Spatial binary tree of directional quadtrees, the root is the last
+/// node.
+struct SdTree {
+    nodes: Vec<SNode>,
+    leaves: Vec<SLeaf>,
+}
+
+impl SdTree {
+    fn new() -> SdTree {
+        SdTree {
+            nodes: vec![SNode::Leaf(0)],
+            leaves: vec![
+                SLeaf {
+                    sampling: DTree::new(),
+                    recording: Mutex::new(Recording::new(DTree::new())),
+                },
+            ],
+        }
+    }
+
+    fn leaf(&self, p: &Point3f) -> &SLeaf {
+        let mut ix = self.nodes.len() - 1;
+        loop {
+            match self.nodes[ix] {
+                SNode::Split {
+                    axis,
+                    pos,
+                    children,
+                } => ix = children[if p[axis] < pos { 0 } else { 1 }],
+                SNode::Leaf(leaf) => return &self.leaves[leaf],
+            }
+        }
+    }
+
+    fn distribution(&self, p: &Point3f) -> Option<&DTree> {
+        let dtree = &self.leaf(p).sampling;
+        if dtree.total() > 0.0 {
+            Some(dtree)
+        } else {
+            None
+        }
+    }
+
+    fn record(&self, p: &Point3f, square: (Real, Real), value: Real) {
+        let mut rec = self.leaf(p).recording.lock().unwrap();
+        rec.dtree.deposit(square, value);
+        rec.records += 1;
+        let point = Aabb3::new(*p, *p);
+        match rec.bounds {
+            Some(ref mut bounds) => bounds.merge(&point),
+            None => rec.bounds = Some(point),
+        }
+    }
+
+    /// Makes the recorded distributions the sampling ones, splits leaves
+    /// with more than `threshold` records and starts new recordings.
+    fn refine(&mut self, threshold: Real) {
+        let nodes = mem::replace(&mut self.nodes, Vec::new());
+        let mut leaves: Vec<Option<SLeaf>> = mem::replace(&mut self.leaves, Vec::new())
+            .into_iter()
+            .map(Some)
+            .collect();
+        let root = nodes.len() - 1;
+        self.rebuild(&nodes, root, &mut leaves, threshold);
+    }
+
+    fn rebuild(
+        &mut self,
+        nodes: &[SNode],
+        ix: usize,
+        leaves: &mut [Option<SLeaf>],
+        threshold: Real,
+    ) -> usize {
+        match nodes[ix] {
+            SNode::Split {
+                axis,
+                pos,
+                children,
+            } => {
+                let c0 = self.rebuild(nodes, children[0], leaves, threshold);
+                let c1 = self.rebuild(nodes, children[1], leaves, threshold);
+                self.nodes.push(SNode::Split {
+                    axis,
+                    pos,
+                    children: [c0, c1],
+                });
+                self.nodes.len() - 1
+            }
+            SNode::Leaf(leaf) => {
+                let old = leaves[leaf].take().unwrap();
+                let rec = old.recording.into_inner().unwrap();
+                // keep the old distribution where nothing was recorded
+                let sampling = if rec.dtree.total() > 0.0 {
+                    rec.dtree
+                } else {
+                    old.sampling
+                };
+                self.split(sampling, rec.bounds, rec.records as Real, threshold)
+            }
+        }
+    }
+
+    fn split(
+        &mut self,
+        sampling: DTree,
+        bounds: Option<Aabb3>,
+        records: Real,
+        threshold: Real,
+    ) -> usize {
+        if let Some(bounds) = bounds {
+            let extent = *bounds.maxs() - *bounds.mins();
+            let axis = if extent.x >= extent.y && extent.x >= extent.z {
+                0
+            } else if extent.y >= extent.z {
+                1
+            } else {
+                2
+            };
+            if records > threshold && extent[axis] > 0.0 {
+                let pos = bounds.center()[axis];
+                let mut max0 = *bounds.maxs();
+                max0[axis] = pos;
+                let mut min1 = *bounds.mins();
+                min1[axis] = pos;
+                let b0 = Aabb3::new(*bounds.mins(), max0);
+                let b1 = Aabb3::new(min1, *bounds.maxs());
+                let c0 = self.split(sampling.clone(), Some(b0), 0.5 * records, threshold);
+                let c1 = self.split(sampling, Some(b1), 0.5 * records, threshold);
+                self.nodes.push(SNode::Split {
+                    axis,
+                    pos,
+                    children: [c0, c1],
+                });
+                return self.nodes.len() - 1;
+            }
+        }
+
+        let recording = sampling.refined(DIRECTIONAL_THRESHOLD, MAX_DIRECTIONAL_DEPTH);
+        self.leaves.push(SLeaf {
+            sampling,
+            recording: Mutex::new(Recording::new(recording)),
+        });
+        self.nodes.push(SNode::Leaf(self.leaves.len() - 1));
+        self.nodes.len() - 1
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct QuadNode {
+    sums: [Real; 4],
+    /// Index of the node subdividing each quadrant, 0 for none.
+    children: [usize; 4],
+}
+
+impl QuadNode {
+    fn new() -> QuadNode {
+        QuadNode {
+            sums: [0.0; 4],
+            children: [0; 4],
+        }
+    }
+
+    fn total(&self) -> Real {
+        self.sums.iter().sum()
+    }
+}
+
+/// Quadtree over the unit square holding the recorded energy per cell,
+/// the root is the first node.
+#[derive(Clone, Debug)]
+struct DTree {
+    nodes: Vec<QuadNode>,
+}
+
+impl DTree {
+    fn new() -> DTree {
+        DTree {
+            nodes: vec![QuadNode::new()],
+        }
+    }
+
+    fn total(&self) -> Real {
+        self.nodes[0].total()
+    }
+
+    /// Quadrant of `p` in a node, moving `p` to the quadrant's coordinates.
+    fn quadrant(p: &mut (Real, Real)) -> usize {
+        let mut q = 0;
+        if p.0 >= 0.5 {
+            q |= 1;
+            p.0 -= 0.5;
+        }
+        if p.1 >= 0.5 {
+            q |= 2;
+            p.1 -= 0.5;
+        }
+        p.0 *= 2.0;
+        p.1 *= 2.0;
+        q
+    }
+
+    fn deposit(&mut self, mut p: (Real, Real), value: Real) {
+        let mut ix = 0;
+        loop {
+            let q = DTree::quadrant(&mut p);
+            self.nodes[ix].sums[q] += value;
+            match self.nodes[ix].children[q] {
+                0 => return,
+                c => ix = c,
+            }
+        }
+    }
+
+    /// Density of `sample` over the unit square.
+    fn pdf(&self, mut p: (Real, Real)) -> Real {
+        let mut density = 1.0;
+        let mut ix = 0;
+        loop {
+            let node = &self.nodes[ix];
+            let total = node.total();
+            // uniform within cells without energy
+            if total <= 0.0 {
+                return density;
+            }
+            let q = DTree::quadrant(&mut p);
+            density *= 4.0 * node.sums[q] / total;
+            match node.children[q] {
+                0 => return density,
+                c => ix = c,
+            }
+        }
+    }
+
+    /// Point of the unit square chosen in proportion to the energy.
+    fn sample(&self) -> (Real, Real) {
+        let mut origin = (0.0, 0.0);
+        let mut size = 1.0;
+        let mut ix = 0;
+        loop {
+            let node = &self.nodes[ix];
+            let total = node.total();
+            if total <= 0.0 {
+                break;
+            }
+            let mut e = rng::uniform() * total;
+            let mut q = (0..4).rev().find(|&i| node.sums[i] > 0.0).unwrap();
+            for i in 0..4 {
+                if e < node.sums[i] {
+                    q = i;
+                    break;
+                }
+                e -= node.sums[i];
+            }
+            size *= 0.5;
+            if q & 1 != 0 {
+                origin.0 += size;
+            }
+            if q & 2 != 0 {
+                origin.1 += size;
+            }
+            match node.children[q] {
+                0 => break,
+                c => ix = c,
+            }
+        }
+        (
+            origin.0 + size * rng::uniform(),
+            origin.1 + size * rng::uniform(),
+        )
+    }
+
+    /// Empty tree subdividing the cells holding more than `threshold` of
+    /// the energy.
+    fn refined(&self, threshold: Real, max_depth: u32) -> DTree {
+        let mut tree = DTree::new();
+        let total = self.total();
+        if total > 0.0 {
+            let sums = self.nodes[0].sums;
+            self.refine_node(Some(0), sums, total * threshold, &mut tree, 0, max_depth);
+        }
+        tree
+    }
+
+    fn refine_node(
+        &self,
+        old: Option<usize>,
+        sums: [Real; 4],
+        min_energy: Real,
+        out: &mut DTree,
+        out_ix: usize,
+        depth_left: u32,
+    ) {
+        if depth_left == 0 {
+            return;
+        }
+        for q in 0..4 {
+            if sums[q] <= min_energy {
+                continue;
+            }
+            // cells that were not subdivided are assumed uniform
+            let (old_child, child_sums) = match old.map(|ix| self.nodes[ix].children[q]) {
+                Some(c) if c != 0 => (Some(c), self.nodes[c].sums),
+                _ => (None, [0.25 * sums[q]; 4]),
+            };
+            out.nodes.push(QuadNode::new());
+            let c = out.nodes.len() - 1;
+            out.nodes[out_ix].children[q] = c;
+            self.refine_node(old_child, child_sums, min_energy, out, c, depth_left - 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::Norm;
+
+    fn trained() -> DTree {
+        let mut dtree = DTree::new().refined(0.01, 20);
+        dtree.deposit((0.1, 0.2), 1.0);
+        let mut dtree = dtree.refined(0.01, 20);
+        for i in 0..64 {
+            let x = (i % 8) as Real / 8.0 + 0.01;
+            let y = (i / 8) as Real / 8.0 + 0.01;
+            dtree.deposit((x, y), if x < 0.25 && y < 0.25 { 10.0 } else { 1.0 });
+        }
+        dtree
+    }
+
+    #[test]
+    fn iterations_follow_passes() {
+        let guide = Guide::new(7, 0.5);
+        let iterations = |p| {
+            guide.start_pass(p);
+            guide.iterations.load(Ordering::SeqCst)
+        };
+        assert_eq!(iterations(0), 0);
+        assert_eq!(iterations(1), 1);
+        assert_eq!(iterations(2), 1);
+        // pass 3 may follow a cancelled pass 2, the iteration still ends
+        assert_eq!(iterations(3), 2);
+        assert!(guide.is_training());
+        assert_eq!(iterations(9), 3);
+        assert!(!guide.is_training());
+        assert_eq!(iterations(100), 3);
+    }
+
+    #[test]
+    fn dtree_pdf_integrates_to_one() {
+        let dtree = trained();
+        let n = 64;
+        let mut sum = 0.0;
+        for j in 0..n {
+            for i in 0..n {
+                let p = ((i as Real + 0.5) / n as Real, (j as Real + 0.5) / n as Real);
+                sum += dtree.pdf(p);
+            }
+        }
+        assert!((sum / (n * n) as Real - 1.0).abs() < 1e-9);
+        assert!(dtree.pdf((0.05, 0.05)) > dtree.pdf((0.9, 0.9)));
+    }
+
+    #[test]
+    fn dtree_samples_follow_energy() {
+        let dtree = trained();
+        let n = 4000;
+        let hits = (0..n)
+            .map(|_| dtree.sample())
+            .filter(|&(x, y)| x < 0.25 && y < 0.25)
+            .count();
+        // 4 of the 64 deposits, 10 times brighter
+        let expected = 40.0 / 100.0;
+        assert!((hits as Real / n as Real - expected).abs() < 0.05);
+    }
+
+    #[test]
+    fn square_mapping_round_trip() {
+        let dir = Vector3f::new(0.3, -0.5, 0.2).normalize();
+        let back = square_to_dir(dir_to_square(&dir));
+        assert!((back - dir).norm() < 1e-9);
+    }
+}
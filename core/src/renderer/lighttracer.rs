@@ -0,0 +1,147 @@
+use super::WorkerPool;
+use super::inner::{first_hit_emission, sample_light_ray, CameraRayGenerator, RendererHelper};
+use {Color, RenderSettings, SurfacePoint};
+use camera::CameraSample;
+use color;
+use math::{Dot, Norm, Ray3f};
+use traits::{RenderCamera, Renderer, SceneHandler};
+use utils::consts;
+
+/// Traces paths from the light sources and connects every vertex to the
+/// camera, splatting the contribution to the pixel it is seen at.
+///
+/// Caustics seen directly by the camera converge much faster than with
+/// `PathTracer`, glossy and specular surfaces seen by the camera do not.
+/// Emitters seen directly are rendered by the camera rays. Only projections
+/// implementing `Projection::connect` get the light paths.
+pub struct LightTracer {
+    ray_gen: CameraRayGenerator,
+    workers: WorkerPool,
+    setup: RenderSettings,
+}
+
+impl LightTracer {
+    pub fn new(setup: &RenderSettings) -> LightTracer {
+        LightTracer {
+            ray_gen: CameraRayGenerator::new(),
+            workers: WorkerPool::new(),
+            setup: *setup,
+        }
+    }
+
+    fn trace_light_path<S>(
+        &self,
+        scene: &S,
+        image_size: (u32, u32),
+        splats: &mut Vec<(u32, u32, Color)>,
+    ) where
+        S: SceneHandler + ?Sized,
+    {
+        let time = self.ray_gen.sample_time();
+        let (mut ray, mut beta) = match sample_light_ray(scene, time) {
+            Some(r) => r,
+            None => return,
+        };
+
+        // a light path with n vertices after the emitter matches a camera
+        // path hitting the emitter at depth n
+        for _ in 1..self.setup.path_depth {
+            let sp = match scene.intersection(&ray) {
+                Some(sp) => sp,
+                None => break,
+            };
+            if sp.normal.dot(&(-ray.dir)) <= 0.0 {
+                break;
+            }
+
+            if let Some(cs) = self.ray_gen.connect(&sp.position, time) {
+                if let Some(c) = connection(scene, &ray, &sp, &cs, beta) {
+                    let x = (cs.x as u32).min(image_size.0 - 1);
+                    let y = (cs.y as u32).min(image_size.1 - 1);
+                    splats.push((x, y, c));
+                }
+            }
+
+            let (new_ray_dir, fr, pdf_p) = sp.bsdf.sample_proj(&sp.normal, &ray.dir);
+            if pdf_p <= 0.0 {
+                break;
+            }
+            beta = (beta * fr) * (1.0 / pdf_p) as f32;
+            ray = Ray3f::with_time(&sp.position, &new_ray_dir, time);
+        }
+    }
+}
+
+/// Contribution of the light path vertex `sp`, reached by `ray` with
+/// throughput `beta`, to the pixel of `cs`.
+fn connection<S>(
+    scene: &S,
+    ray: &Ray3f,
+    sp: &SurfacePoint,
+    cs: &CameraSample,
+    beta: Color,
+) -> Option<Color>
+where
+    S: SceneHandler + ?Sized,
+{
+    let to_camera = cs.origin - sp.position;
+    let dist = to_camera.norm();
+    let dir = to_camera / dist;
+    let cos_theta = sp.normal.dot(&dir);
+    if cos_theta <= 0.0 {
+        return None;
+    }
+
+    let shadow_ray = Ray3f::with_time(&sp.position, &dir, ray.time);
+    if let Some(ip) = scene.intersection(&shadow_ray) {
+        if (ip.position - sp.position).norm() < dist - consts::POSITION_EPSILON * 2.0 {
+            return None;
+        }
+    }
+
+    // seen from the camera the light comes from `-ray.dir`
+    let (fr, _) = sp.bsdf.eval(&sp.normal, &(-dir), &(-ray.dir));
+    Some((beta * fr) * (cos_theta * cs.importance) as f32)
+}
+
+impl<S: SceneHandler + ?Sized, C: RenderCamera + ?Sized> RendererHelper<S, C> for LightTracer {
+    fn trace_path(&self, scene: &S, initial_ray: &Ray3f, _: &RenderSettings) -> Color {
+        if self.setup.path_depth == 0 {
+            return color::BLACK;
+        }
+        first_hit_emission(scene, initial_ray)
+    }
+
+    fn get_ray(&self, _: &C, x: u32, y: u32) -> Option<Ray3f> {
+        self.ray_gen.get_ray(x, y)
+    }
+
+    fn has_splats(&self) -> bool {
+        true
+    }
+
+    fn splat_job(
+        &self,
+        scene: &S,
+        camera: &C,
+        _: &RenderSettings,
+        paths: u32,
+    ) -> Vec<(u32, u32, Color)> {
+        let image_size = (camera.width(), camera.height());
+        let mut splats = Vec::new();
+        for _ in 0..paths {
+            self.trace_light_path(scene, image_size, &mut splats);
+        }
+        splats
+    }
+
+    fn workers(&self) -> &WorkerPool {
+        &self.workers
+    }
+}
+
+impl<S: SceneHandler + ?Sized, C: RenderCamera + ?Sized> Renderer<S, C> for LightTracer {
+    fn pre_render(&mut self, _: &S, camera: &C, _: &RenderSettings) {
+        self.ray_gen = CameraRayGenerator::with_camera(camera);
+    }
+}
@@ -1,23 +1,58 @@
 pub mod pathtracer;
+pub mod aov;
+pub mod ao;
 pub mod dbgraycaster;
-
-pub use self::dbgraycaster::DbgRayCaster;
+mod guiding;
+pub mod lighttracer;
+pub mod pssmlt;
+pub mod scheduler;
+pub mod sppm;
+pub mod vcm;
+pub mod control;
+pub mod checkpoint;
+pub mod distributed;
+#[cfg(test)]
+mod testing;
+
+pub use self::ao::AmbientOcclusion;
+pub use self::aov::{Aov, AovBuffers, AovSample, AovSet};
+pub use self::checkpoint::Checkpoint;
+pub use self::control::{Canceller, Progress, RenderControl, RenderStatus};
+pub use self::dbgraycaster::{DbgMode, DbgRayCaster};
+pub use self::distributed::{run_worker, Coordinator};
+pub use self::lighttracer::LightTracer;
 
 use self::inner::RendererHelper;
-pub use self::pathtracer::PathTracer;
+pub use self::pathtracer::{MisHeuristic, PathTracer};
+pub use self::pssmlt::Pssmlt;
+pub use self::scheduler::{TileOrder, WorkerPool};
+pub use self::sppm::Sppm;
+pub use self::vcm::Vcm;
 use {Color, RenderSettings};
+use color;
 
-use scoped_threadpool::Pool;
-use std::ops::DerefMut;
-use std::sync::{Arc, Mutex};
+use std::cmp::min;
+use std::io;
 
 use traits::{RenderCamera, SceneHandler, TexView};
 
 mod inner {
-    use {Color, RenderSettings};
-    use math::{self, Norm, Point3f, Ray3f, Real, Vector3f};
-    use rand::{self, Closed01};
-    use traits::{RenderCamera, SceneHandler, TexView};
+    use {Color, RenderSettings, SurfacePoint};
+    use camera::{CameraFrame, CameraSample, Perspective, Projection};
+    use color;
+    use math::{self, ApproxEq, Dot, Norm, Point3f, Ray3f, Real, Vector3f};
+    use motion::Motion;
+    use std::collections::HashMap;
+    use std::f64::consts::PI;
+    use std::ops::DerefMut;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::Instant;
+    use super::WorkerPool;
+    use super::aov::{AovBuffers, AovSample};
+    use traits::{RenderCamera, SceneHandler, Surface, TexView};
+    use utils::consts;
+    use utils::rng::{self, SeededSource};
 
     pub trait RendererHelper<S, C>: Sync
     where
@@ -26,7 +61,48 @@ mod inner {
     {
         fn trace_path(&self, scene: &S, initial_ray: &Ray3f, setup: &RenderSettings) -> Color;
 
-        fn get_ray(&self, camera: &C, x: u32, y: u32) -> Ray3f;
+        /// `trace_path` that also fills `aov`. The default records only the
+        /// geometry of the first hit.
+        fn trace_path_aov(
+            &self,
+            scene: &S,
+            initial_ray: &Ray3f,
+            setup: &RenderSettings,
+            aov: &mut AovSample,
+        ) -> Color {
+            if let Some(sp) = scene.intersection(initial_ray) {
+                aov.record_hit(scene, initial_ray, &sp);
+            }
+            self.trace_path(scene, initial_ray, setup)
+        }
+
+        fn get_ray(&self, camera: &C, x: u32, y: u32) -> Option<Ray3f>;
+
+        /// Whether the renderer adds `splat_job` contributions to the image.
+        fn has_splats(&self) -> bool {
+            false
+        }
+
+        /// Whether `render_job` alone renders any tile and pass, which the
+        /// workers of a distributed render rely on. Not the case for
+        /// splatting renderers and renderers with state shared by the whole
+        /// image.
+        fn supports_jobs(&self) -> bool {
+            !self.has_splats()
+        }
+
+        /// Traces `paths` paths that may contribute to any pixel, returned as
+        /// `(x, y, color)`. The splats of a pass are summed and added to the
+        /// colors of its camera rays.
+        fn splat_job(&self, _: &S, _: &C, _: &RenderSettings, _: u32) -> Vec<(u32, u32, Color)> {
+            Vec::new()
+        }
+
+        fn workers(&self) -> &WorkerPool;
+
+        /// Called before passes from `first_pass` on are rendered, once all
+        /// the earlier passes are.
+        fn start_passes(&self, _: u32) {}
 
         fn render_job(
             &self,
@@ -39,8 +115,10 @@ mod inner {
             let mut result = Vec::with_capacity((img_w * img_h) as usize);
             for y in 0..img_h {
                 for x in 0..img_w {
-                    let ray = self.get_ray(camera, x + x0, y + y0);
-                    let color = self.trace_path(scene, &ray, setup);
+                    let color = match self.get_ray(camera, x + x0, y + y0) {
+                        Some(ray) => self.trace_path(scene, &ray, setup),
+                        None => color::BLACK,
+                    };
                     result.push(color);
                     //result[(y * img_w + x) as usize] = color;
                 }
@@ -49,6 +127,31 @@ mod inner {
 
         }
 
+        fn render_job_aov(
+            &self,
+            scene: &S,
+            camera: &C,
+            setup: &RenderSettings,
+            img_rect: ((u32, u32), (u32, u32)),
+        ) -> (Vec<Color>, Vec<AovSample>) {
+            let ((x0, y0), (img_w, img_h)) = img_rect;
+            let mut colors = Vec::with_capacity((img_w * img_h) as usize);
+            let mut aovs = Vec::with_capacity((img_w * img_h) as usize);
+            for y in 0..img_h {
+                for x in 0..img_w {
+                    let mut aov = AovSample::new();
+                    let color = match self.get_ray(camera, x + x0, y + y0) {
+                        Some(ray) => self.trace_path_aov(scene, &ray, setup, &mut aov),
+                        None => color::BLACK,
+                    };
+                    aov.color = color;
+                    colors.push(color);
+                    aovs.push(aov);
+                }
+            }
+            (colors, aovs)
+        }
+
         fn add_to_pixel(
             &self,
             c: &Color,
@@ -63,74 +166,478 @@ mod inner {
             pixel *= 1.0 / pnum;
             out_image.set_pixel(x as usize, y as usize, pixel);
         }
+
+        /// Renders `passes = (first_pass, passes_num)` tile by tile on the
+        /// worker pool, adding the samples to `aovs` if given.
+        /// `on_tile(tiles_done, tiles_total)` is called after every finished
+        /// tile, returning `false` stops the workers. Returns `false` if the
+        /// passes were not finished.
+        fn render_tiles(
+            &self,
+            scene: &S,
+            camera: &C,
+            setup: &RenderSettings,
+            passes: (u32, u32),
+            out_image: &mut TexView<Color>,
+            aovs: Option<&mut AovBuffers>,
+            on_tile: &(Fn(usize, usize) -> bool + Sync),
+        ) -> bool {
+            let (first_pass, passes_num) = passes;
+            self.start_passes(first_pass);
+            let image_size = (camera.width(), camera.height());
+            let chunk = setup.render_chunk;
+            let tiles = self.workers().schedule(image_size, chunk, setup.tile_order);
+            let next_tile = AtomicUsize::new(0);
+            let tiles_done = AtomicUsize::new(0);
+            let stop = AtomicBool::new(false);
+            let out_img = Mutex::new(out_image);
+            let aov_bufs = aovs.map(Mutex::new);
+            let last_pass = first_pass + passes_num - 1;
+            let splat_buf = if self.has_splats() {
+                Some(Mutex::new(vec![color::BLACK; (image_size.0 * image_size.1) as usize]))
+            } else {
+                None
+            };
+
+            self.workers().with_pool(setup.threads_num, |pool| {
+                pool.scoped(|scope| {
+                    for _ in 0..::std::cmp::max(setup.threads_num, 1) {
+                        let tiles = &tiles;
+                        let next_tile = &next_tile;
+                        let tiles_done = &tiles_done;
+                        let stop = &stop;
+                        let out_img = &out_img;
+                        let aov_bufs = &aov_bufs;
+                        let splat_buf = &splat_buf;
+
+                        scope.execute(move || loop {
+                            if stop.load(Ordering::Relaxed) {
+                                break;
+                            }
+                            let ix = next_tile.fetch_add(1, Ordering::Relaxed);
+                            if ix >= tiles.len() {
+                                break;
+                            }
+                            let tile = &tiles[ix];
+
+                            let start_time = Instant::now();
+                            let rect = ((tile.x, tile.y), (tile.width, tile.height));
+                            let mut chunks = Vec::with_capacity(passes_num as usize);
+                            let mut aov_chunks = Vec::new();
+                            for p in 0..passes_num {
+                                // pass 0 is replaced by pass 1 in the image, see `add_to_pixel`
+                                let pass_num = first_pass + p;
+                                let mut source =
+                                    tile_source(setup, pass_num, (tile.x, tile.y), image_size.0);
+                                rng::with_source(&mut source, || {
+                                    if let Some(ref buf) = *splat_buf {
+                                        if pass_num > 0 || last_pass == 0 {
+                                            let paths = tile.width * tile.height;
+                                            let splats =
+                                                self.splat_job(scene, camera, setup, paths);
+                                            let mut buf = buf.lock().unwrap();
+                                            for (x, y, c) in splats {
+                                                buf[(y * image_size.0 + x) as usize] += c;
+                                            }
+                                        }
+                                    }
+                                    if aov_bufs.is_some() {
+                                        let (colors, samples) =
+                                            self.render_job_aov(scene, camera, setup, rect);
+                                        chunks.push(colors);
+                                        aov_chunks.push(samples);
+                                    } else {
+                                        chunks.push(self.render_job(scene, camera, setup, rect));
+                                    }
+                                });
+                            }
+                            self.workers().record_cost(
+                                image_size,
+                                chunk,
+                                tiles.len(),
+                                tile,
+                                start_time.elapsed(),
+                            );
+
+                            {
+                                let mut img = out_img.lock().unwrap();
+                                for (p, pass_chunk) in chunks.iter().enumerate() {
+                                    let pass_num = first_pass + p as u32;
+                                    let pnum: f32 = if pass_num == 0 {
+                                        1.0
+                                    } else {
+                                        pass_num as f32
+                                    };
+                                    for j in 0..tile.height {
+                                        for i in 0..tile.width {
+                                            let c = pass_chunk[(j * tile.width + i) as usize];
+                                            self.add_to_pixel(
+                                                &c,
+                                                pnum,
+                                                tile.x + i,
+                                                tile.y + j,
+                                                *img.deref_mut(),
+                                            );
+                                        }
+                                    }
+                                }
+                            }
+
+                            if let Some(ref bufs) = *aov_bufs {
+                                let mut bufs = bufs.lock().unwrap();
+                                for (p, samples) in aov_chunks.iter().enumerate() {
+                                    // dropped like pass 0 in the image
+                                    if first_pass + p as u32 == 0 && setup.samples_per_pixel > 1 {
+                                        continue;
+                                    }
+                                    for j in 0..tile.height {
+                                        for i in 0..tile.width {
+                                            bufs.add_sample(
+                                                (tile.x + i) as usize,
+                                                (tile.y + j) as usize,
+                                                &samples[(j * tile.width + i) as usize],
+                                            );
+                                        }
+                                    }
+                                }
+                            }
+
+                            let done = tiles_done.fetch_add(1, Ordering::SeqCst) + 1;
+                            if !on_tile(done, tiles.len()) {
+                                stop.store(true, Ordering::Relaxed);
+                            }
+                        });
+                    }
+
+                    scope.join_all();
+                });
+            });
+
+            let finished = tiles_done.load(Ordering::SeqCst) == tiles.len();
+            if let (Some(buf), true) = (splat_buf, finished) {
+                let buf = buf.into_inner().unwrap();
+                let img = out_img.into_inner().unwrap();
+                let scale = 1.0 / ::std::cmp::max(last_pass, 1) as f32;
+                for y in 0..image_size.1 {
+                    for x in 0..image_size.0 {
+                        let (x, y) = (x as usize, y as usize);
+                        let pixel = img.pixel(x, y) + buf[y * image_size.0 as usize + x] * scale;
+                        img.set_pixel(x, y, pixel);
+                    }
+                }
+            }
+            finished
+        }
+    }
+
+    /// Samples of pass `pass_num` of the tile at `corner` in an image of
+    /// `width` pixels. They depend only on the seed, the pass and the tile,
+    /// so a resumed or distributed render repeats the samples of an
+    /// uninterrupted local one.
+    pub fn tile_source(
+        setup: &RenderSettings,
+        pass_num: u32,
+        corner: (u32, u32),
+        width: u32,
+    ) -> SeededSource {
+        let stream = (pass_num as u64) << 32 | (corner.1 * width + corner.0) as u64;
+        SeededSource::new(setup.seed(), stream)
+    }
+
+    /// Runs `job(i)` for every `i` in `0..jobs_num` on the worker pool and
+    /// returns the results in order.
+    pub fn parallel_map<T, F>(
+        workers: &WorkerPool,
+        threads_num: u32,
+        jobs_num: usize,
+        job: F,
+    ) -> Vec<T>
+    where
+        T: Send,
+        F: Fn(usize) -> T + Sync,
+    {
+        let next_job = AtomicUsize::new(0);
+        let results = Mutex::new((0..jobs_num).map(|_| None).collect::<Vec<Option<T>>>());
+        workers.with_pool(threads_num, |pool| {
+            pool.scoped(|scope| {
+                for _ in 0..::std::cmp::max(threads_num, 1) {
+                    let next_job = &next_job;
+                    let results = &results;
+                    let job = &job;
+                    scope.execute(move || loop {
+                        let ix = next_job.fetch_add(1, Ordering::Relaxed);
+                        if ix >= jobs_num {
+                            break;
+                        }
+                        let res = job(ix);
+                        results.lock().unwrap()[ix] = Some(res);
+                    });
+                }
+                scope.join_all();
+            });
+        });
+        results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect()
+    }
+
+    /// Starts a light path at a light source chosen by
+    /// `SceneHandler::light_sources`, returns the ray leaving it and the
+    /// throughput, emitted radiance over the sampling pdf.
+    pub fn sample_light_ray<S>(scene: &S, time: Real) -> Option<(Ray3f, Color)>
+    where
+        S: SceneHandler + ?Sized,
+    {
+        let origin: Point3f = math::origin();
+        let zero: Vector3f = math::zero();
+        let (lp, pdf) = match scene
+            .light_sources()
+            .sample((&origin, &zero), time, sample_emission_point)
+        {
+            Some(s) => s,
+            None => return None,
+        };
+        // cosine sampling, pdf = cos / π cancels the cosine at the light
+        let dir = math::hs_cosine_sampling(&lp.normal);
+        let le = match lp.bsdf.radiance_dir(&lp.normal, &dir) {
+            Some(le) => le,
+            None => return None,
+        };
+        let start = lp.position + lp.normal * consts::POSITION_EPSILON;
+        Some((
+            Ray3f::with_time(&start, &dir, time),
+            le * (PI as Real / pdf) as f32,
+        ))
+    }
+
+    /// Point distributed uniformly over an emitter at `time`, the view
+    /// point is ignored.
+    pub fn sample_emission_point<'a>(
+        surface: &'a Surface,
+        _: (&Point3f, &Vector3f),
+        time: Real,
+    ) -> (SurfacePoint<'a>, Real) {
+        surface.sample_surface_uniform(time)
+    }
+
+    /// Area pdf of `sample_emission_point`.
+    pub fn emission_point_pdf<'a>(
+        surface: &'a Surface,
+        _: (&Point3f, &Vector3f),
+        _: (&Point3f, &Vector3f),
+        _: Real,
+    ) -> Real {
+        1.0 / surface.area()
+    }
+
+    /// Light reaching `sp` from one point sampled on the light sources,
+    /// `ray` is the ray that hit `sp`.
+    pub fn sample_direct_light<S>(scene: &S, ray: &Ray3f, sp: &SurfacePoint) -> Color
+    where
+        S: SceneHandler + ?Sized,
+    {
+        if let Some((lp, pdf_ls)) = scene
+            .light_sources()
+            .sample((&sp.position, &sp.normal), ray.time, Surface::sample_surface_d_proj)
+        {
+            let shadow_ray = Ray3f::with_time(
+                &sp.position,
+                &(lp.position - sp.position).normalize(),
+                ray.time,
+            );
+            let cos_theta = sp.normal.dot(&shadow_ray.dir);
+            let cos_theta_l = lp.normal.dot(&(-shadow_ray.dir));
+
+            if cos_theta > 0.0 && cos_theta_l > 0.0 {
+                if let Some(ip) = scene.intersection(&shadow_ray) {
+                    if ip.position
+                        .approx_eq_eps(&lp.position, &(consts::POSITION_EPSILON * 2.0))
+                    {
+                        let (fr, _) = sp.bsdf.eval_proj(&sp.normal, &ray.dir, &shadow_ray.dir);
+                        let le = lp.bsdf
+                            .radiance_dir(&lp.normal, &(-shadow_ray.dir))
+                            .unwrap();
+                        return (fr * le) * (1.0 / pdf_ls) as f32;
+                    }
+                }
+            }
+        }
+        color::BLACK
+    }
+
+    /// Light emitted by the first hit of `ray` towards its origin, the part
+    /// of the image the renderers starting paths at the lights leave to
+    /// the camera rays.
+    pub fn first_hit_emission<S>(scene: &S, ray: &Ray3f) -> Color
+    where
+        S: SceneHandler + ?Sized,
+    {
+        match scene.intersection(ray) {
+            Some(ref sp) if sp.normal.dot(&(-ray.dir)) > 0.0 => sp.bsdf
+                .radiance_dir(&sp.normal, &(-ray.dir))
+                .unwrap_or(color::BLACK),
+            _ => color::BLACK,
+        }
+    }
+
+    /// Whether `ray` scatters at `sp`, surfaces are only hit from the front
+    /// unless they refract.
+    pub fn scatters(sp: &SurfacePoint, ray: &Ray3f) -> bool {
+        sp.normal.dot(&(-ray.dir)) > 0.0 || sp.bsdf.ior().is_some()
+    }
+
+    /// Uniform grid hashing points by position, for the density estimates
+    /// of the photon mapping renderers.
+    pub struct PointGrid {
+        cell_size: Real,
+        cells: HashMap<(i64, i64, i64), Vec<usize>>,
+    }
+
+    impl PointGrid {
+        /// Grid of cells `2 * radius` wide, `radius` being the largest
+        /// search radius.
+        pub fn new(radius: Real) -> PointGrid {
+            PointGrid {
+                cell_size: 2.0 * radius.max(1e-6),
+                cells: HashMap::new(),
+            }
+        }
+
+        /// Adds point `i` at `p` to the cells overlapping the sphere of
+        /// `radius` around it.
+        pub fn insert(&mut self, i: usize, p: &Point3f, radius: Real) {
+            let (c0, c1) = self.cell_range(p, radius);
+            for x in c0.0..c1.0 + 1 {
+                for y in c0.1..c1.1 + 1 {
+                    for z in c0.2..c1.2 + 1 {
+                        self.cells.entry((x, y, z)).or_insert_with(Vec::new).push(i);
+                    }
+                }
+            }
+        }
+
+        /// Points added to the cell of `p`.
+        pub fn lookup(&self, p: &Point3f) -> &[usize] {
+            match self.cells.get(&self.cell(p)) {
+                Some(points) => points,
+                None => &[],
+            }
+        }
+
+        /// Calls `f` with the points of the cells overlapping the sphere of
+        /// `radius` around `p`.
+        pub fn visit<F: FnMut(usize)>(&self, p: &Point3f, radius: Real, mut f: F) {
+            let (c0, c1) = self.cell_range(p, radius);
+            for x in c0.0..c1.0 + 1 {
+                for y in c0.1..c1.1 + 1 {
+                    for z in c0.2..c1.2 + 1 {
+                        if let Some(points) = self.cells.get(&(x, y, z)) {
+                            for &i in points {
+                                f(i);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        fn cell(&self, p: &Point3f) -> (i64, i64, i64) {
+            (
+                (p.x / self.cell_size).floor() as i64,
+                (p.y / self.cell_size).floor() as i64,
+                (p.z / self.cell_size).floor() as i64,
+            )
+        }
+
+        fn cell_range(&self, p: &Point3f, radius: Real) -> ((i64, i64, i64), (i64, i64, i64)) {
+            let r = Vector3f::new(radius, radius, radius);
+            (self.cell(&(*p - r)), self.cell(&(*p + r)))
+        }
     }
 
     pub struct CameraRayGenerator {
-        origin: Point3f,
-        up: Vector3f,
-        forward: Vector3f,
-        right: Vector3f,
-
-        x0: Real,
-        y0: Real,
-        dx: Real,
-        dy: Real,
+        frame: CameraFrame,
+        projection: Arc<Projection>,
+        shutter: (Real, Real),
+        motion: Option<Motion>,
     }
 
     impl CameraRayGenerator {
         pub fn new() -> CameraRayGenerator {
             CameraRayGenerator {
-                origin: math::origin(),
-                up: math::zero(),
-                forward: math::zero(),
-                right: math::zero(),
-
-                x0: 0.0,
-                y0: 0.0,
-                dx: 0.0,
-                dy: 0.0,
+                frame: CameraFrame {
+                    origin: math::origin(),
+                    right: math::zero(),
+                    up: math::zero(),
+                    forward: math::zero(),
+                    width: 1,
+                    height: 1,
+                },
+                projection: Arc::new(Perspective::new(0.0)),
+                shutter: (0.0, 0.0),
+                motion: None,
             }
         }
 
         pub fn with_camera<C: RenderCamera + ?Sized>(camera: &C) -> CameraRayGenerator {
-
-            let origin = camera.pos();
-            let up = camera.up_vec().normalize();
-            let forward = camera.forward_vec().normalize();
-            let right = camera.right_vec().normalize();
-
-            let ratio = camera.height() as Real / camera.width() as Real;
-            let fovy = camera.fovy();
-            let y = 2.0 * (0.5 * fovy).tan();
-            let x = y / ratio;
-            let dx = x / (camera.width() as Real);
-            let dy = dx;
-            let x0 = (-0.5) * x + dx * 0.5;
-            let y0 = 0.5 * y - dy * 0.5;
-
             CameraRayGenerator {
-                origin: origin,
-                up: up,
-                forward: forward,
-                right: right,
-
-                x0: x0,
-                y0: y0,
-                dx: dx,
-                dy: dy,
+                frame: CameraFrame::new(camera),
+                projection: camera.projection(),
+                shutter: camera.shutter(),
+                motion: camera.motion(),
             }
         }
 
-        pub fn get_ray(&self, x: u32, y: u32) -> Ray3f {
+        /// Ray through a random point of the pixel, `None` if the pixel is
+        /// not covered by the projection.
+        pub fn get_ray(&self, x: u32, y: u32) -> Option<Ray3f> {
+            let rnd_x = rng::uniform();
+            let rnd_y = rng::uniform();
+            let u1 = rng::uniform();
+            let u2 = rng::uniform();
+            let u3 = rng::uniform();
+
+            let (t0, t1) = self.shutter;
+            let time = t0 + (t1 - t0) * u3;
+
+            self.projection
+                .ray(&self.frame_at(time), x as Real + rnd_x, y as Real + rnd_y, (u1, u2))
+                .map(|mut ray| {
+                    ray.time = time;
+                    ray
+                })
+        }
 
-            let Closed01(rnd_x) = rand::random::<Closed01<Real>>();
-            let Closed01(rnd_y) = rand::random::<Closed01<Real>>();
+        /// Random time in the shutter interval, for paths started from the
+        /// lights.
+        pub fn sample_time(&self) -> Real {
+            let u = rng::uniform();
+            let (t0, t1) = self.shutter;
+            t0 + (t1 - t0) * u
+        }
 
-            let rx = self.x0 + self.dx * (x as Real) + self.dx * (rnd_x - 0.5);
-            let ry = self.y0 - self.dy * (y as Real) + self.dy * (rnd_y - 0.5);
-            let ray_dir = self.forward + self.right * rx + self.up * ry;
+        /// Connects the point `p` to the camera at `time`, the inverse of
+        /// `get_ray`. `None` if `p` is outside of the image.
+        pub fn connect(&self, p: &Point3f, time: Real) -> Option<CameraSample> {
+            let u1 = rng::uniform();
+            let u2 = rng::uniform();
+            self.projection.connect(&self.frame_at(time), p, (u1, u2))
+        }
 
-            Ray3f::new(&self.origin, &ray_dir.normalize())
+        /// Density of the direction of `ray` generated by `get_ray`, see
+        /// `Projection::direction_pdf`.
+        pub fn direction_pdf(&self, ray: &Ray3f) -> Option<Real> {
+            self.projection.direction_pdf(&self.frame_at(ray.time), &ray.dir)
+        }
+
+        fn frame_at(&self, time: Real) -> CameraFrame {
+            match self.motion {
+                Some(ref m) => self.frame.with_transform(&m.at(time)),
+                None => self.frame,
+            }
         }
     }
 }
@@ -164,8 +671,38 @@ where
         out_image: &mut TexView<Color>,
     ) {
         self.pre_render(scene, camera, setup);
-        for p in 0..setup.samples_per_pixel {
-            self.render_pass_threads(scene, camera, setup, p, out_image);
+        let mut p = 0;
+        while p < setup.samples_per_pixel {
+            let passes_num = ::std::cmp::min(setup.passes_in_flight, setup.samples_per_pixel - p);
+            self.render_passes_threads(scene, camera, setup, p, passes_num, out_image);
+            p += passes_num;
+        }
+    }
+
+    /// `render_scene_threads` that also writes AOVs to `aovs`, which is
+    /// usually created with the AOVs selected by `setup.aovs()`.
+    fn render_scene_aovs(
+        &mut self,
+        scene: &S,
+        camera: &C,
+        setup: &RenderSettings,
+        out_image: &mut TexView<Color>,
+        aovs: &mut AovBuffers,
+    ) {
+        self.pre_render(scene, camera, setup);
+        let mut p = 0;
+        while p < setup.samples_per_pixel {
+            let passes_num = min(setup.passes_in_flight, setup.samples_per_pixel - p);
+            self.render_tiles(
+                scene,
+                camera,
+                setup,
+                (p, passes_num),
+                out_image,
+                Some(&mut *aovs),
+                &|_, _| true,
+            );
+            p += passes_num;
         }
     }
 
@@ -178,14 +715,26 @@ where
         out_image: &mut TexView<Color>,
     ) {
         let pnum: f32 = if pass_num == 0 { 1.0 } else { pass_num as f32 };
+        self.start_passes(pass_num);
 
         for j in 0..camera.height() {
             for i in 0..camera.width() {
-                let ray = self.get_ray(camera, i, j);
-                let c = self.trace_path(scene, &ray, setup);
+                let c = match self.get_ray(camera, i, j) {
+                    Some(ray) => self.trace_path(scene, &ray, setup),
+                    None => color::BLACK,
+                };
                 self.add_to_pixel(&c, pnum, i, j, out_image);
             }
         }
+
+        if self.has_splats() {
+            let paths = camera.width() * camera.height();
+            for (x, y, c) in self.splat_job(scene, camera, setup, paths) {
+                let (x, y) = (x as usize, y as usize);
+                let pixel = out_image.pixel(x, y) + c * (1.0 / pnum);
+                out_image.set_pixel(x, y, pixel);
+            }
+        }
     }
 
     fn render_pass_threads(
@@ -196,78 +745,119 @@ where
         pass_num: u32,
         out_image: &mut TexView<Color>,
     ) {
+        self.render_passes_threads(scene, camera, setup, pass_num, 1, out_image);
+    }
 
-        let pnum: f32 = if pass_num == 0 { 1.0 } else { pass_num as f32 };
-
-        let (chunk_w, chunk_h) = setup.render_chunk;
-        let chunks_num = (camera.width() / chunk_w) * (camera.height() / chunk_h);
-        // let mut res_chunks: Vec<Vec<Color>> = Vec::with_capacity(chunks_num as usize);
-
-        // for _ in 0..chunks_num {
-        //     res_chunks.push(Vec::new());
-        // }
-
-        let out_img = Arc::new(Mutex::new(out_image));
-
-        let mut pool = Pool::new(setup.threads_num);
-        pool.scoped(|scope| {
-
-            let mut offset_x = 0;
-            let mut offset_y = 0;
-
-            //for b in &mut res_chunks {
-            for _ in 0..chunks_num {
-
-                let tmp_out_img = out_img.clone();
-
-                scope.execute(move || {
-                    let chunk = self.render_job(
-                        scene,
-                        camera,
-                        setup,
-                        ((offset_x, offset_y), (chunk_w, chunk_h)),
-                    );
-                    //*b = chunk;
-                    let mut img = tmp_out_img.lock().unwrap();
-                    for j in 0..chunk_h {
-                        for i in 0..chunk_w {
-                            let c = chunk[(j * chunk_w + i) as usize];
-                            self.add_to_pixel(
-                                &c,
-                                pnum,
-                                offset_x + i,
-                                offset_y + j,
-                                *img.deref_mut(),
-                            );
-                        }
-                    }
-                });
+    /// Renders passes `first_pass..first_pass + passes_num`. Tiles are taken
+    /// from a shared queue by the workers, each worker renders all the passes
+    /// of a tile before taking the next one.
+    fn render_passes_threads(
+        &self,
+        scene: &S,
+        camera: &C,
+        setup: &RenderSettings,
+        first_pass: u32,
+        passes_num: u32,
+        out_image: &mut TexView<Color>,
+    ) {
+        self.render_tiles(
+            scene,
+            camera,
+            setup,
+            (first_pass, passes_num),
+            out_image,
+            None,
+            &|_, _| true,
+        );
+    }
 
-                offset_x += chunk_w;
-                if offset_x >= camera.width() {
-                    offset_x = 0;
-                    offset_y += chunk_h;
-                }
-            }
+    /// Multithreaded `render_scene` that can be cancelled, limited by a time
+    /// or spp budget and reports its progress through `control`. Fails if a
+    /// checkpoint cannot be saved.
+    fn render_scene_controlled(
+        &mut self,
+        scene: &S,
+        camera: &C,
+        setup: &RenderSettings,
+        out_image: &mut TexView<Color>,
+        control: &RenderControl,
+    ) -> io::Result<RenderStatus> {
+        self.pre_render(scene, camera, setup);
+        self.render_passes_controlled(scene, camera, setup, 0, out_image, control)
+    }
 
-            scope.join_all();
+    /// Restores `checkpoint` into `out_image` and continues the render from
+    /// the pass it was saved at.
+    fn resume_scene_controlled(
+        &mut self,
+        scene: &S,
+        camera: &C,
+        setup: &RenderSettings,
+        checkpoint: &Checkpoint,
+        out_image: &mut TexView<Color>,
+        control: &RenderControl,
+    ) -> io::Result<RenderStatus> {
+        let first_pass = checkpoint.restore(setup, out_image)?;
+        self.pre_render(scene, camera, setup);
+        self.render_passes_controlled(scene, camera, setup, first_pass, out_image, control)
+    }
 
-        });
+    /// Continues a controlled render from `first_pass`, `pre_render` must
+    /// have been called before.
+    fn render_passes_controlled(
+        &self,
+        scene: &S,
+        camera: &C,
+        setup: &RenderSettings,
+        first_pass: u32,
+        out_image: &mut TexView<Color>,
+        control: &RenderControl,
+    ) -> io::Result<RenderStatus> {
+        control.start();
+        let passes_total = control.passes_total(setup.samples_per_pixel);
+
+        let mut p = first_pass;
+        let status = loop {
+            if p >= passes_total {
+                break if control.spp_budget_reached(setup.samples_per_pixel) {
+                    RenderStatus::BudgetExhausted(p)
+                } else {
+                    RenderStatus::Completed(p)
+                };
+            }
+            if control.is_cancelled() {
+                break RenderStatus::Cancelled(p);
+            }
+            if control.is_time_exceeded() {
+                break RenderStatus::BudgetExhausted(p);
+            }
 
-        // let mut chunk_ix = 0;
-        // for offset_y in (0..camera.height()).step_by(chunk_h) {
-        //     for offset_x in (0..camera.width()).step_by(chunk_w) {
-
-        //         for j in 0..chunk_h {
-        //             for i in 0..chunk_w {
-        //                 let res = &res_chunks[chunk_ix];
-        //                 let c = res[(j * chunk_w + i) as usize];
-        //                 self.add_to_pixel(&c, pnum, offset_x + i, offset_y + j, out_image);
-        //             }
-        //         }
-        //         chunk_ix += 1;
-        //     }
-        // }
+            let passes_num = min(setup.passes_in_flight, passes_total - p);
+            let boundary = control.capture_checkpoint(setup, p, out_image);
+            let finished = self.render_tiles(
+                scene,
+                camera,
+                setup,
+                (p, passes_num),
+                out_image,
+                None,
+                &|tiles_done, tiles_total| {
+                    control.report((p, passes_num, passes_total), (tiles_done, tiles_total));
+                    !control.is_cancelled()
+                },
+            );
+            if !finished {
+                // some tiles hold the unfinished passes already
+                if let Some(ckpt) = boundary {
+                    control.write_checkpoint(&ckpt)?;
+                }
+                return Ok(RenderStatus::Cancelled(p));
+            }
+            p += passes_num;
+            control.save_checkpoint(setup, p, out_image, false)?;
+        };
 
+        control.save_checkpoint(setup, status.passes(), out_image, true)?;
+        Ok(status)
     }
 }
@@ -1,28 +1,79 @@
 
 
-use super::inner::{CameraRayGenerator, RendererHelper};
-use {Color, RenderSettings};
+use super::WorkerPool;
+use super::aov::AovSample;
+use super::guiding::Guide;
+use super::inner::{scatters, CameraRayGenerator, RendererHelper};
+use {Color, RenderSettings, SurfacePoint};
 use color;
-use math::{ApproxEq, Dot, Norm, Ray3f, Real};
-use rand;
-use rand::Closed01;
+use math::{ApproxEq, Dot, Norm, Point3f, Ray3f, Real, Vector3f};
+use spectrum::{Spd, Spectrum, Wavelengths};
+use std::f64;
 use traits::{RenderCamera, Renderer, SceneHandler, Surface};
 use utils::consts;
+use utils::rng;
 
 pub struct PathTracer {
     ray_gen: CameraRayGenerator,
+    workers: WorkerPool,
     setup: RenderSettings,
 
     /// (brdf, light sources)
     di_samples_weight: Option<(Real, Real)>,
+    /// (light samples per vertex, heuristic)
+    mis: Option<(u32, MisHeuristic)>,
+    /// (training passes, BSDF sampling fraction)
+    guiding: Option<(u32, Real)>,
+    guide: Option<Guide>,
+    spectral: bool,
+}
+
+/// Multiple importance sampling heuristic combining light and BSDF samples.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MisHeuristic {
+    Balance,
+    /// Power heuristic with exponent 2.
+    Power,
+}
+
+impl MisHeuristic {
+    /// Weight of a sample taken with `pdf` when the other technique would
+    /// have taken it with `other_pdf`, both scaled by their sample counts.
+    pub fn weight(&self, pdf: Real, other_pdf: Real) -> Real {
+        let (a, b) = match *self {
+            MisHeuristic::Balance => (pdf, other_pdf),
+            MisHeuristic::Power => (pdf * pdf, other_pdf * other_pdf),
+        };
+        if a + b > 0.0 {
+            a / (a + b)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Vertex a ray was sampled at, weighs the emission the ray hits.
+struct Scatter {
+    position: Point3f,
+    normal: Vector3f,
+    /// Solid angle pdf of the continuation direction.
+    pdf_w: Real,
+    /// Projected solid angle pdf of the continuation direction, infinite
+    /// for a refraction or mirror reflection.
+    pdf_proj: Real,
 }
 
 impl PathTracer {
     pub fn new(setup: &RenderSettings) -> PathTracer {
         PathTracer {
             ray_gen: CameraRayGenerator::new(),
+            workers: WorkerPool::new(),
             setup: *setup,
             di_samples_weight: None,
+            mis: None,
+            guiding: None,
+            guide: None,
+            spectral: false,
         }
     }
 
@@ -42,119 +93,498 @@ impl PathTracer {
         self
     }
 
-    fn trace_path_rec<S>(&self, scene: &S, ray: &Ray3f, depth: u32) -> Color
+    /// Takes `light_samples` light samples at every vertex and weights the
+    /// emitters hit by the continuation ray with `heuristic`, instead of
+    /// the random choice of `with_direct_illumination`, which it overrides.
+    pub fn with_mis(mut self, light_samples: u32, heuristic: MisHeuristic) -> Self {
+        self.mis = Some((light_samples.max(1), heuristic));
+        self
+    }
+
+    /// Guides the continuation rays by the incident radiance learned over
+    /// the first `training_passes` passes, in iterations of 1, 2, 4, ...
+    /// passes. `bsdf_fraction` of the guided directions sample the BSDF.
+    /// Passes rendered together, see `passes_in_flight`, belong to the
+    /// iteration of the first one.
+    pub fn with_guiding(mut self, training_passes: u32, bsdf_fraction: Real) -> Self {
+        self.guiding = Some((training_passes, bsdf_fraction.max(0.0).min(1.0)));
+        self
+    }
+
+    /// Traces hero wavelengths instead of RGB, which gives dispersion in
+    /// dielectrics. The spectra are converted to XYZ and then to sRGB.
+    /// Direct illumination uses the light samples of `with_mis`, one per
+    /// vertex unless set. AOV renders stay RGB.
+    pub fn with_spectral(mut self) -> Self {
+        self.spectral = true;
+        if self.mis.is_none() {
+            self.mis = Some((1, MisHeuristic::Power));
+        }
+        self
+    }
+
+    /// Radiance along `ray` split into the light emitted by the hit surface
+    /// and the light it reflects.
+    fn trace_path_rec<S>(
+        &self,
+        scene: &S,
+        ray: &Ray3f,
+        depth: u32,
+        prev: Option<&Scatter>,
+    ) -> (Color, Color)
     where
         S: SceneHandler + ?Sized,
     {
 
         if depth == self.setup.path_depth {
-            return color::BLACK;
+            return (color::BLACK, color::BLACK);
+        }
+
+        match scene.intersection(ray) {
+            Some(ref sp) if scatters(sp, ray) => {
+                let le = self.emitted(scene, ray, sp, prev);
+                let (direct_illumination, _) = self.direct_illumination(scene, ray, sp);
+
+                let (new_ray_dir, w, scatter) = self.sample_continuation(ray, sp, None);
+                let new_ray = Ray3f::with_time(&sp.position, &new_ray_dir, ray.time);
+                let (le_i, lr_i) =
+                    self.trace_path_rec::<S>(scene, &new_ray, depth + 1, Some(&scatter));
+                let indirect_illumination = w * (le_i + lr_i);
+
+                let lr = direct_illumination + indirect_illumination;
+                let emission = sp.bsdf.radiance_dir(&sp.normal, &(-ray.dir));
+                self.record(prev, ray, emission.unwrap_or(color::BLACK) + lr);
+                (le, lr)
+            }
+            _ => {
+                self.record(prev, ray, color::BLACK);
+                (color::BLACK, color::BLACK)
+            }
         }
+    }
 
-        let di_enable = self.di_samples_weight.is_some();
+    /// `trace_path_rec` at the wavelengths `wl`, emitted and reflected light
+    /// together.
+    fn trace_spectral_rec<S>(
+        &self,
+        scene: &S,
+        ray: &Ray3f,
+        depth: u32,
+        prev: Option<&Scatter>,
+        wl: &mut Wavelengths,
+    ) -> Spectrum
+    where
+        S: SceneHandler + ?Sized,
+    {
+        if depth == self.setup.path_depth {
+            return Spectrum::new(0.0);
+        }
 
         match scene.intersection(ray) {
-            Some(ref sp) if sp.normal.dot(&(-ray.dir)) > 0.0 => {
-                let mat = sp.bsdf.as_ref();
-
-                let le = if let Some(c) = mat.radiance() {
-                    if depth > 0 && di_enable {
-                        color::BLACK
-                    } else {
-                        c
+            Some(ref sp) if scatters(sp, ray) => {
+                let le = wl.emission(&self.emitted(scene, ray, sp, prev), sp.bsdf.emission_spd());
+                let mut direct = Spectrum::new(0.0);
+                if let Some((samples, heuristic)) = self.mis {
+                    if !sp.bsdf.is_specular() {
+                        self.light_samples(scene, ray, sp, samples, heuristic, |fr, le, spd, w| {
+                            direct += wl.reflectance(&fr) * wl.emission(&le, spd) * w as f32;
+                        });
                     }
-                } else {
-                    color::BLACK
-                };
+                }
 
-                let direct_illumination = if let Some((brdf_w, ls_w)) = self.di_samples_weight {
-                    let mut di = color::BLACK;
-
-                    let Closed01(e) = rand::random::<Closed01<Real>>();
-                    if e > brdf_w {
-                        // light source sampling
-
-                        if let Some((lp, pdf_ls)) = scene
-                            .light_sources()
-                            .sample((&sp.position, &sp.normal), Surface::sample_surface_d_proj)
-                        {
-                            let shadow_ray =
-                                Ray3f::new(&sp.position, &(lp.position - sp.position).normalize());
-                            let cos_theta = sp.normal.dot(&shadow_ray.dir);
-                            let cos_theta_l = lp.normal.dot(&(-shadow_ray.dir));
-
-                            if cos_theta > 0.0 && cos_theta_l > 0.0 {
-                                if let Some(ip) = scene.intersection(&shadow_ray) {
-                                    if ip.position.approx_eq_eps(
-                                        &lp.position,
-                                        &(consts::POSITION_EPSILON * 2.0),
-                                    ) {
-
-                                        let (fr, pdf_brdf) = sp.bsdf
-                                            .eval_proj(&sp.normal, &ray.dir, &shadow_ray.dir);
-                                        let pdf_sum_inv = 1.0 / (pdf_brdf * brdf_w + pdf_ls * ls_w);
-                                        let le = lp.bsdf.radiance().unwrap();
-
-                                        di = (fr * le) * (pdf_sum_inv as f32);
-                                    }
-                                }
-                            }
-                        }
-                    } else {
-                        // brdf sampling
-                        let (brdf_ray_dir, _, _) = sp.bsdf.sample_proj(&sp.normal, &ray.dir);
-                        let shadow_ray = Ray3f::new(&sp.position, &brdf_ray_dir);
-
-                        if let Some(ip) = scene.intersection(&shadow_ray) {
-                            if let Some(le) = ip.bsdf.radiance() {
-
-                                let pdf_ls = scene.light_sources().pdf(
-                                    ip.surface,
-                                    (&ip.position, &ip.normal),
-                                    (&sp.position, &sp.normal),
-                                    Surface::pdf_d_proj,
-                                );
-                                let (fr, pdf_brdf) =
-                                    sp.bsdf.eval_proj(&sp.normal, &ray.dir, &shadow_ray.dir);
-
-                                let pdf_sum_inv = 1.0 / (pdf_brdf * brdf_w + pdf_ls * ls_w);
-                                let res = (fr * le) * (pdf_sum_inv as f32);
-                                di += res;
-                            }
-                        }
+                let lambda = match sp.bsdf.ior() {
+                    Some(ior) if ior.is_dispersive() => {
+                        wl.terminate_secondary();
+                        Some(wl.hero())
                     }
+                    _ => None,
+                };
+                let (new_ray_dir, w, scatter) = self.sample_continuation(ray, sp, lambda);
+                let new_ray = Ray3f::with_time(&sp.position, &new_ray_dir, ray.time);
+                let li =
+                    self.trace_spectral_rec::<S>(scene, &new_ray, depth + 1, Some(&scatter), wl);
+                let lr = direct + wl.reflectance(&w) * li;
 
-                    di
+                let emission = sp.bsdf
+                    .radiance_dir(&sp.normal, &(-ray.dir))
+                    .unwrap_or(color::BLACK);
+                self.record(prev, ray, emission + wl.to_rgb(&lr));
+                le + lr
+            }
+            _ => {
+                self.record(prev, ray, color::BLACK);
+                Spectrum::new(0.0)
+            }
+        }
+    }
+
+    /// Records the radiance arriving at `prev` along `ray` while the guide
+    /// is trained.
+    fn record(&self, prev: Option<&Scatter>, ray: &Ray3f, radiance: Color) {
+        if let (Some(guide), Some(prev)) = (self.guide.as_ref(), prev) {
+            guide.record(&prev.position, &ray.dir, radiance, prev.pdf_w);
+        }
+    }
 
+    /// Light emitted by `sp`, reached from `prev` unless it is the first
+    /// hit.
+    fn emitted<S>(
+        &self,
+        scene: &S,
+        ray: &Ray3f,
+        sp: &SurfacePoint,
+        prev: Option<&Scatter>,
+    ) -> Color
+    where
+        S: SceneHandler + ?Sized,
+    {
+        let le = match sp.bsdf.radiance_dir(&sp.normal, &(-ray.dir)) {
+            Some(c) => c,
+            None => return color::BLACK,
+        };
+        match (prev, self.mis) {
+            // no light sample can reach a light through a specular vertex
+            (Some(prev), _) if prev.pdf_proj.is_infinite() => le,
+            (Some(prev), Some((samples, heuristic))) => {
+                let pdf_ls = scene.light_sources().pdf(
+                    sp.surface,
+                    (&sp.position, &sp.normal),
+                    (&prev.position, &prev.normal),
+                    ray.time,
+                    Surface::pdf_d_proj,
+                );
+                le * heuristic.weight(prev.pdf_proj, samples as Real * pdf_ls) as f32
+            }
+            // already counted by the direct illumination of the previous hit
+            (Some(_), None) if self.di_samples_weight.is_some() => color::BLACK,
+            _ => le,
+        }
+    }
+
+    /// Continuation direction of `ray` at `sp`, with the BSDF times cosine
+    /// over the pdf and the vertex for the next hit. A refraction is
+    /// sampled for light of `lambda` nm if given.
+    fn sample_continuation(
+        &self,
+        ray: &Ray3f,
+        sp: &SurfacePoint,
+        lambda: Option<Real>,
+    ) -> (Vector3f, Color, Scatter) {
+        if sp.bsdf.is_specular() {
+            let (dir, w, _) = match lambda {
+                Some(lambda) => sp.bsdf.sample_wavelength(&sp.normal, &ray.dir, lambda),
+                None => sp.bsdf.sample_proj(&sp.normal, &ray.dir),
+            };
+            let scatter = Scatter {
+                position: sp.position,
+                normal: sp.normal,
+                pdf_w: f64::INFINITY,
+                pdf_proj: f64::INFINITY,
+            };
+            return (dir, w, scatter);
+        }
+
+        let guided = match self.guide {
+            Some(ref guide) => guide.sample(sp, &ray.dir),
+            None => None,
+        };
+        let (dir, w, pdf_w) = match guided {
+            Some(sample) => sample,
+            None => {
+                let (dir, fr, pdf_p) = sp.bsdf.sample_proj(&sp.normal, &ray.dir);
+                // the pdf of `sample_proj` may be scaled along with the BSDF
+                // value
+                let pdf_w = if self.mis.is_some() || self.guide.is_some() {
+                    sp.bsdf.eval(&sp.normal, &ray.dir, &dir).1
                 } else {
-                    color::BLACK
+                    0.0
                 };
+                (dir, fr * (1.0 / pdf_p) as f32, pdf_w)
+            }
+        };
+        let cos = sp.normal.dot(&dir);
+        let pdf_proj = if cos > 0.0 { pdf_w / cos } else { 0.0 };
+        let scatter = Scatter {
+            position: sp.position,
+            normal: sp.normal,
+            pdf_w,
+            pdf_proj,
+        };
+        (dir, w, scatter)
+    }
+
+    /// Projected solid angle pdf of the continuation of `ray` at `sp`
+    /// taking `dir`.
+    fn continuation_pdf_proj(&self, ray: &Ray3f, sp: &SurfacePoint, dir: &Vector3f) -> Real {
+        let guided = match self.guide {
+            Some(ref guide) => guide.pdf(sp, &ray.dir, dir),
+            None => None,
+        };
+        match guided {
+            Some(pdf_w) => {
+                let cos = sp.normal.dot(dir);
+                if cos > 0.0 { pdf_w / cos } else { 0.0 }
+            }
+            None => sp.bsdf.eval_proj(&sp.normal, &ray.dir, dir).1,
+        }
+    }
+
+    /// Takes `samples` light samples at `sp` weighted against BSDF
+    /// sampling, passing the BSDF value, the emitted radiance, the emission
+    /// spectrum and the weight of each to `add`. Returns the direction of
+    /// the last one.
+    fn light_samples<S, F>(
+        &self,
+        scene: &S,
+        ray: &Ray3f,
+        sp: &SurfacePoint,
+        samples: u32,
+        heuristic: MisHeuristic,
+        mut add: F,
+    ) -> Option<Vector3f>
+    where
+        S: SceneHandler + ?Sized,
+        F: FnMut(Color, Color, Option<&Spd>, Real),
+    {
+        let mut last_dir = None;
+        for _ in 0..samples {
+            if let Some((lp, pdf_ls)) = scene
+                .light_sources()
+                .sample((&sp.position, &sp.normal), ray.time, Surface::sample_surface_d_proj)
+            {
+                let shadow_ray = Ray3f::with_time(
+                    &sp.position,
+                    &(lp.position - sp.position).normalize(),
+                    ray.time,
+                );
+                let cos_theta = sp.normal.dot(&shadow_ray.dir);
+                let cos_theta_l = lp.normal.dot(&(-shadow_ray.dir));
+                if cos_theta <= 0.0 || cos_theta_l <= 0.0 || pdf_ls <= 0.0 {
+                    continue;
+                }
+                if let Some(ip) = scene.intersection(&shadow_ray) {
+                    if ip.position
+                        .approx_eq_eps(&lp.position, &(consts::POSITION_EPSILON * 2.0))
+                    {
+                        let fr = sp.bsdf.eval_proj(&sp.normal, &ray.dir, &shadow_ray.dir).0;
+                        let pdf_brdf = self.continuation_pdf_proj(ray, sp, &shadow_ray.dir);
+                        let le = lp.bsdf
+                            .radiance_dir(&lp.normal, &(-shadow_ray.dir))
+                            .unwrap();
+                        let n_pdf_ls = samples as Real * pdf_ls;
+                        let w = heuristic.weight(n_pdf_ls, pdf_brdf) / n_pdf_ls;
+                        add(fr, le, lp.bsdf.emission_spd(), w);
+                        last_dir = Some(shadow_ray.dir);
+                    }
+                }
+            }
+        }
+        last_dir
+    }
+
+    /// Light reaching `sp` directly from the light sources, with the
+    /// direction it was sampled in.
+    fn direct_illumination<S>(
+        &self,
+        scene: &S,
+        ray: &Ray3f,
+        sp: &SurfacePoint,
+    ) -> (Color, Option<Vector3f>)
+    where
+        S: SceneHandler + ?Sized,
+    {
+        if sp.bsdf.is_specular() {
+            return (color::BLACK, None);
+        }
+        if let Some((samples, heuristic)) = self.mis {
+            let mut sum = color::BLACK;
+            let last_dir = self.light_samples(scene, ray, sp, samples, heuristic, |fr, le, _, w| {
+                sum += (fr * le) * w as f32;
+            });
+            return (sum, last_dir);
+        }
+        let (brdf_w, ls_w) = match self.di_samples_weight {
+            Some(w) => w,
+            None => return (color::BLACK, None),
+        };
 
-                let (new_ray_dir, fr, pdf_p) = sp.bsdf.sample_proj(&sp.normal, &ray.dir);
-                let new_ray = Ray3f::new(&sp.position, &new_ray_dir);
-                let li = self.trace_path_rec::<S>(scene, &new_ray, depth + 1);
-                let indirect_illumination = (fr * li) * (1.0 / pdf_p) as f32;
+        let e = rng::uniform();
+        if e > brdf_w {
+            // light source sampling
 
-                le + (direct_illumination + indirect_illumination)
-                // le + direct_illumination;
+            if let Some((lp, pdf_ls)) = scene
+                .light_sources()
+                .sample((&sp.position, &sp.normal), ray.time, Surface::sample_surface_d_proj)
+            {
+                let shadow_ray = Ray3f::with_time(
+                    &sp.position,
+                    &(lp.position - sp.position).normalize(),
+                    ray.time,
+                );
+                let cos_theta = sp.normal.dot(&shadow_ray.dir);
+                let cos_theta_l = lp.normal.dot(&(-shadow_ray.dir));
+
+                if cos_theta > 0.0 && cos_theta_l > 0.0 {
+                    if let Some(ip) = scene.intersection(&shadow_ray) {
+                        if ip.position.approx_eq_eps(
+                            &lp.position,
+                            &(consts::POSITION_EPSILON * 2.0),
+                        ) {
+
+                            let (fr, pdf_brdf) =
+                                sp.bsdf.eval_proj(&sp.normal, &ray.dir, &shadow_ray.dir);
+                            let pdf_sum_inv = 1.0 / (pdf_brdf * brdf_w + pdf_ls * ls_w);
+                            let le = lp.bsdf
+                                .radiance_dir(&lp.normal, &(-shadow_ray.dir))
+                                .unwrap();
+
+                            return ((fr * le) * (pdf_sum_inv as f32), Some(shadow_ray.dir));
+                        }
+                    }
+                }
+            }
+        } else {
+            // brdf sampling
+            let (brdf_ray_dir, _, _) = sp.bsdf.sample_proj(&sp.normal, &ray.dir);
+            let shadow_ray = Ray3f::with_time(&sp.position, &brdf_ray_dir, ray.time);
+
+            if let Some(ip) = scene.intersection(&shadow_ray) {
+                if let Some(le) = ip.bsdf.radiance_dir(&ip.normal, &(-shadow_ray.dir)) {
+
+                    let pdf_ls = scene.light_sources().pdf(
+                        ip.surface,
+                        (&ip.position, &ip.normal),
+                        (&sp.position, &sp.normal),
+                        ray.time,
+                        Surface::pdf_d_proj,
+                    );
+                    let (fr, pdf_brdf) = sp.bsdf.eval_proj(&sp.normal, &ray.dir, &shadow_ray.dir);
+
+                    let pdf_sum_inv = 1.0 / (pdf_brdf * brdf_w + pdf_ls * ls_w);
+                    return ((fr * le) * (pdf_sum_inv as f32), Some(shadow_ray.dir));
+                }
             }
-            _ => color::BLACK,
         }
+
+        (color::BLACK, None)
     }
 }
 
+/// Per channel fraction of the BSDF at `sp` that is diffuse.
+fn diffuse_fraction(sp: &SurfacePoint, in_dir: &Vector3f, out_dir: &Vector3f) -> Color {
+    let (fr, _) = sp.bsdf.eval(&sp.normal, in_dir, out_dir);
+    let fd = sp.bsdf.eval_diffuse(&sp.normal, in_dir, out_dir);
+    let ratio = |d: f32, f: f32| if f > 0.0 { (d / f).max(0.0).min(1.0) } else { 0.0 };
+    Color::new(ratio(fd.r, fr.r), ratio(fd.g, fr.g), ratio(fd.b, fr.b))
+}
+
 impl<S: SceneHandler + ?Sized, C: RenderCamera + ?Sized> RendererHelper<S, C> for PathTracer {
     fn trace_path(&self, scene: &S, initial_ray: &Ray3f, _: &RenderSettings) -> Color {
-        self.trace_path_rec::<S>(scene, initial_ray, 0)
+        if self.spectral {
+            let mut wl = Wavelengths::sample(rng::uniform());
+            let l = self.trace_spectral_rec::<S>(scene, initial_ray, 0, None, &mut wl);
+            return wl.to_rgb(&l);
+        }
+        let (le, lr) = self.trace_path_rec::<S>(scene, initial_ray, 0, None);
+        le + lr
     }
 
-    fn get_ray(&self, _: &C, x: u32, y: u32) -> Ray3f {
+    fn trace_path_aov(
+        &self,
+        scene: &S,
+        ray: &Ray3f,
+        _: &RenderSettings,
+        aov: &mut AovSample,
+    ) -> Color {
+        if self.setup.path_depth == 0 {
+            return color::BLACK;
+        }
+        match scene.intersection(ray) {
+            Some(ref sp) if scatters(sp, ray) => {
+                aov.record_hit(scene, ray, sp);
+
+                let le = self.emitted(scene, ray, sp, None);
+                let (di, di_dir) = self.direct_illumination(scene, ray, sp);
+
+                let (new_ray_dir, w, scatter) = self.sample_continuation(ray, sp, None);
+                let new_ray = Ray3f::with_time(&sp.position, &new_ray_dir, ray.time);
+                let (le_i, lr_i) = self.trace_path_rec::<S>(scene, &new_ray, 1, Some(&scatter));
+
+                let di_diffuse = match di_dir {
+                    Some(dir) => di * diffuse_fraction(sp, &ray.dir, &dir),
+                    None => color::BLACK,
+                };
+                let indirect = w * (le_i + lr_i);
+                let indirect_diffuse = indirect * diffuse_fraction(sp, &ray.dir, &new_ray_dir);
+
+                aov.direct = le + di + w * le_i;
+                aov.indirect = w * lr_i;
+                aov.diffuse = di_diffuse + indirect_diffuse;
+                aov.specular = (di - di_diffuse) + (indirect - indirect_diffuse);
+
+                le + di + indirect
+            }
+            _ => color::BLACK,
+        }
+    }
+
+    fn get_ray(&self, _: &C, x: u32, y: u32) -> Option<Ray3f> {
         self.ray_gen.get_ray(x, y)
     }
+
+    fn workers(&self) -> &WorkerPool {
+        &self.workers
+    }
+
+    fn start_passes(&self, first_pass: u32) {
+        if let Some(ref guide) = self.guide {
+            guide.start_pass(first_pass);
+        }
+    }
 }
 
 impl<S: SceneHandler + ?Sized, C: RenderCamera + ?Sized> Renderer<S, C> for PathTracer {
     fn pre_render(&mut self, _: &S, camera: &C, _: &RenderSettings) {
         self.ray_gen = CameraRayGenerator::with_camera(camera);
+        self.guide = self.guiding
+            .map(|(passes, bsdf_fraction)| Guide::new(passes, bsdf_fraction));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use renderer::testing;
+    use scenehandler::ShapeList;
+    use sphere::Sphere;
+    use texture::Texture;
+
+    type Scene = ShapeList<'static, Sphere>;
+
+    #[test]
+    fn heuristic_weights_sum_to_one() {
+        for h in &[MisHeuristic::Balance, MisHeuristic::Power] {
+            let (a, b) = (0.3, 2.0);
+            assert!((h.weight(a, b) + h.weight(b, a) - 1.0).abs() < 1e-12);
+        }
+        assert_eq!(MisHeuristic::Power.weight(1.0, 2.0), 0.2);
+        assert_eq!(MisHeuristic::Balance.weight(0.0, 0.0), 0.0);
+    }
+
+    /// Mean of the channels over a 16x16 image of `scene` at time 1.
+    fn mean_at_end(renderer: &mut PathTracer, scene: &Scene, setup: &RenderSettings) -> f32 {
+        let camera = testing::diffuse_camera(16, 16).with_shutter(1.0, 1.0);
+        let mut img = Texture::<Color>::new(16, 16);
+        renderer.render_scene_threads(scene, &camera, setup, &mut img);
+        img.pixels().map(|c| (c.r + c.g + c.b) / 3.0).sum::<f32>() / 256.0
+    }
+
+    #[test]
+    fn moving_light_matches_static() {
+        let setup = RenderSettings::new(32, 4);
+        let mut pt = PathTracer::new(&setup).with_mis(1, MisHeuristic::Power);
+        let expected = mean_at_end(&mut pt, &testing::diffuse_scene(), &setup);
+        let moving = mean_at_end(&mut pt, &testing::moving_light_scene(), &setup);
+        assert!((moving - expected).abs() < 0.1 * expected, "{} vs {}", moving, expected);
     }
 }
@@ -0,0 +1,428 @@
+//! Primary sample space Metropolis light transport (Kelemen et al. 2002).
+//!
+//! A path traced by `PathTracer` is a function of the uniform random
+//! numbers it consumes. Markov chains mutate these numbers, either all at
+//! once (large step) or by a small perturbation of each (small step), and
+//! accept the mutation with the ratio of the path luminances, so bright
+//! paths that are hard to find are explored locally once found. Both the
+//! proposed and the current path are splatted, weighted by the acceptance
+//! probability. The normalization, the mean luminance of the image, is
+//! estimated from bootstrap paths that also pick the starting states of
+//! the chains.
+
+use super::WorkerPool;
+use super::inner::{parallel_map, RendererHelper};
+use super::pathtracer::PathTracer;
+use {Color, RenderSettings};
+use color;
+use math::{Ray3f, Real};
+use rand::{Rng, SeedableRng, XorShiftRng};
+use std::f64::consts::PI;
+use std::sync::Mutex;
+use traits::{RenderCamera, Renderer, SceneHandler};
+use utils::rng::{self, SampleSource};
+
+pub struct Pssmlt {
+    path_tracer: PathTracer,
+    workers: WorkerPool,
+    setup: RenderSettings,
+    large_step_prob: Real,
+    mutation_size: Real,
+    bootstrap_samples: u32,
+    chains_num: Option<u32>,
+    /// Mean luminance of the image over the primary sample space.
+    normalization: Real,
+    chains: Vec<Mutex<Chain>>,
+}
+
+impl Pssmlt {
+    /// Mutates the paths of `path_tracer`, its path depth and direct
+    /// illumination settings are used as they are.
+    pub fn new(path_tracer: PathTracer, setup: &RenderSettings) -> Pssmlt {
+        Pssmlt {
+            path_tracer,
+            workers: WorkerPool::new(),
+            setup: *setup,
+            large_step_prob: 0.3,
+            mutation_size: 0.01,
+            bootstrap_samples: 100_000,
+            chains_num: None,
+            normalization: 0.0,
+            chains: Vec::new(),
+        }
+    }
+
+    /// Probability of replacing all the primary samples of a path.
+    pub fn with_large_step_probability(mut self, p: Real) -> Self {
+        self.large_step_prob = p;
+        self
+    }
+
+    /// Standard deviation of a small step perturbation.
+    pub fn with_mutation_size(mut self, sigma: Real) -> Self {
+        self.mutation_size = sigma;
+        self
+    }
+
+    /// Paths traced to estimate the normalization before rendering.
+    pub fn with_bootstrap_samples(mut self, samples: u32) -> Self {
+        self.bootstrap_samples = samples.max(1);
+        self
+    }
+
+    /// Number of Markov chains, one per thread by default.
+    pub fn with_chains(mut self, chains: u32) -> Self {
+        self.chains_num = Some(chains.max(1));
+        self
+    }
+
+    fn primary_samples(&self, ix: u64) -> PrimarySamples {
+        PrimarySamples::new(
+            self.setup.seed() ^ ix.wrapping_mul(0x9e37_79b9_7f4a_7c15),
+            self.mutation_size,
+            self.large_step_prob,
+        )
+    }
+
+    /// Traces the path given by `samples`, the first two choose the pixel.
+    fn evaluate<S, C>(&self, scene: &S, camera: &C, samples: &mut PrimarySamples) -> Sample
+    where
+        S: SceneHandler + ?Sized,
+        C: RenderCamera + ?Sized,
+    {
+        let (width, height) = (camera.width(), camera.height());
+        let (x, y, color) = rng::with_source(samples, || {
+            let x = ((rng::uniform() * width as Real) as u32).min(width - 1);
+            let y = ((rng::uniform() * height as Real) as u32).min(height - 1);
+            let color = match RendererHelper::<S, C>::get_ray(&self.path_tracer, camera, x, y) {
+                Some(ray) => RendererHelper::<S, C>::trace_path(
+                    &self.path_tracer,
+                    scene,
+                    &ray,
+                    &self.setup,
+                ),
+                None => color::BLACK,
+            };
+            (x, y, color)
+        });
+
+        let lum = color::luminance(&color) as Real;
+        if lum.is_finite() && lum > 0.0 {
+            Sample { x, y, color, lum }
+        } else {
+            Sample {
+                x,
+                y,
+                color: color::BLACK,
+                lum: 0.0,
+            }
+        }
+    }
+
+    /// Estimates the normalization and starts the chains at bootstrap
+    /// paths chosen in proportion to their luminance.
+    fn bootstrap<S, C>(&mut self, scene: &S, camera: &C, threads_num: u32)
+    where
+        S: SceneHandler + ?Sized,
+        C: RenderCamera + ?Sized,
+    {
+        let weights = {
+            let this = &*self;
+            parallel_map(
+                &this.workers,
+                threads_num,
+                this.bootstrap_samples as usize,
+                |ix| {
+                    let mut samples = this.primary_samples(ix as u64);
+                    this.evaluate(scene, camera, &mut samples).lum
+                },
+            )
+        };
+
+        let mut cdf = Vec::with_capacity(weights.len());
+        let mut sum = 0.0;
+        for w in &weights {
+            sum += *w;
+            cdf.push(sum);
+        }
+        self.normalization = sum / weights.len() as Real;
+        self.chains = Vec::new();
+        if sum <= 0.0 {
+            return;
+        }
+
+        let chains_num = self.chains_num.unwrap_or_else(|| threads_num.max(1));
+        let mut rng = seeded_rng(self.setup.seed(), CHAIN_STARTS_STREAM);
+        for _ in 0..chains_num {
+            let e = rng.gen::<Real>() * sum;
+            let ix = match cdf.binary_search_by(|probe| probe.partial_cmp(&e).unwrap()) {
+                Ok(ix) | Err(ix) => ix.min(cdf.len() - 1),
+            };
+            // the same seed replays the bootstrap path
+            let mut samples = self.primary_samples(ix as u64);
+            let current = self.evaluate(scene, camera, &mut samples);
+            self.chains.push(Mutex::new(Chain { samples, current }));
+        }
+    }
+
+    /// Runs `mutations` steps of `chain`, adding the splats to `splats`.
+    fn mutate<S, C>(
+        &self,
+        scene: &S,
+        camera: &C,
+        chain: &mut Chain,
+        mutations: u32,
+        splats: &mut Vec<(u32, u32, Color)>,
+    ) where
+        S: SceneHandler + ?Sized,
+        C: RenderCamera + ?Sized,
+    {
+        // one mutation per pixel and pass, see `RendererHelper::splat_job`
+        let b = self.normalization;
+        for _ in 0..mutations {
+            chain.samples.start_iteration();
+            let proposed = self.evaluate(scene, camera, &mut chain.samples);
+            let accept = if chain.current.lum > 0.0 {
+                (proposed.lum / chain.current.lum).min(1.0)
+            } else {
+                1.0
+            };
+
+            if accept > 0.0 && proposed.lum > 0.0 {
+                let w = accept * b / proposed.lum;
+                splats.push((proposed.x, proposed.y, proposed.color * w as f32));
+            }
+            if accept < 1.0 {
+                let w = (1.0 - accept) * b / chain.current.lum;
+                let current = &chain.current;
+                splats.push((current.x, current.y, current.color * w as f32));
+            }
+
+            if chain.samples.random() < accept {
+                chain.samples.accept();
+                chain.current = proposed;
+            } else {
+                chain.samples.reject();
+            }
+        }
+    }
+}
+
+struct Sample {
+    x: u32,
+    y: u32,
+    color: Color,
+    lum: Real,
+}
+
+struct Chain {
+    samples: PrimarySamples,
+    current: Sample,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct PrimarySample {
+    value: Real,
+    modified: u64,
+    backup: Real,
+    modified_backup: u64,
+}
+
+const PRIMARY_SAMPLES_STREAM: u32 = 0x9e37_79b9;
+const CHAIN_STARTS_STREAM: u32 = 0x7f4a_7c15;
+
+/// Generator for `seed`, the streams keep the generators of the same seed
+/// apart.
+fn seeded_rng(seed: u64, stream: u32) -> XorShiftRng {
+    XorShiftRng::from_seed([seed as u32, (seed >> 32) as u32, 0x2545_f491, stream])
+}
+
+/// Primary sample vector of a chain, mutated lazily when a sample is used
+/// (Kelemen's lazy evaluation, as in pbrt-v3).
+struct PrimarySamples {
+    rng: XorShiftRng,
+    samples: Vec<PrimarySample>,
+    next: usize,
+    iteration: u64,
+    last_large_step: u64,
+    large_step: bool,
+    sigma: Real,
+    large_step_prob: Real,
+}
+
+impl PrimarySamples {
+    /// The first path is a large step.
+    fn new(seed: u64, sigma: Real, large_step_prob: Real) -> PrimarySamples {
+        PrimarySamples {
+            rng: seeded_rng(seed, PRIMARY_SAMPLES_STREAM),
+            samples: Vec::new(),
+            next: 0,
+            iteration: 0,
+            last_large_step: 0,
+            large_step: true,
+            sigma,
+            large_step_prob,
+        }
+    }
+
+    fn random(&mut self) -> Real {
+        self.rng.gen::<Real>()
+    }
+
+    fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.random() < self.large_step_prob;
+        self.next = 0;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    /// Restores the samples changed by the last iteration.
+    fn reject(&mut self) {
+        let iteration = self.iteration;
+        for s in self.samples.iter_mut().filter(|s| s.modified == iteration) {
+            s.value = s.backup;
+            s.modified = s.modified_backup;
+        }
+        self.iteration -= 1;
+    }
+
+    fn normal(&mut self) -> Real {
+        // Box-Muller
+        let u1 = 1.0 - self.random();
+        let u2 = self.random();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * PI as Real * u2).cos()
+    }
+}
+
+impl SampleSource for PrimarySamples {
+    fn next_sample(&mut self) -> Real {
+        let ix = self.next;
+        self.next += 1;
+        if ix == self.samples.len() {
+            self.samples.push(PrimarySample {
+                value: 0.0,
+                modified: 0,
+                backup: 0.0,
+                modified_backup: 0,
+            });
+        }
+
+        let mut s = self.samples[ix];
+        // a large step since the last use replaced the sample
+        if s.modified < self.last_large_step {
+            s.value = self.random();
+            s.modified = self.last_large_step;
+        }
+        s.backup = s.value;
+        s.modified_backup = s.modified;
+        if self.large_step {
+            s.value = self.random();
+        } else {
+            // the small steps skipped while the sample was unused
+            let steps = (self.iteration - s.modified) as Real;
+            s.value += self.normal() * self.sigma * steps.sqrt();
+            s.value -= s.value.floor();
+        }
+        s.modified = self.iteration;
+        self.samples[ix] = s;
+        s.value
+    }
+}
+
+impl<S: SceneHandler + ?Sized, C: RenderCamera + ?Sized> RendererHelper<S, C> for Pssmlt {
+    fn trace_path(&self, _: &S, _: &Ray3f, _: &RenderSettings) -> Color {
+        color::BLACK
+    }
+
+    /// All the light is splatted.
+    fn get_ray(&self, _: &C, _: u32, _: u32) -> Option<Ray3f> {
+        None
+    }
+
+    fn has_splats(&self) -> bool {
+        true
+    }
+
+    fn splat_job(
+        &self,
+        scene: &S,
+        camera: &C,
+        _: &RenderSettings,
+        paths: u32,
+    ) -> Vec<(u32, u32, Color)> {
+        let mut splats = Vec::new();
+        if self.chains.is_empty() {
+            return splats;
+        }
+
+        // a free chain if there is one, chains must not be run in parallel
+        let mut chain = match self.chains.iter().filter_map(|c| c.try_lock().ok()).next() {
+            Some(chain) => chain,
+            None => {
+                // drawn from the sample source of the job, so it is seeded
+                let ix = (rng::uniform() * self.chains.len() as Real) as usize;
+                self.chains[ix.min(self.chains.len() - 1)].lock().unwrap()
+            }
+        };
+        self.mutate(scene, camera, &mut chain, paths, &mut splats);
+        splats
+    }
+
+    fn workers(&self) -> &WorkerPool {
+        &self.workers
+    }
+}
+
+impl<S: SceneHandler + ?Sized, C: RenderCamera + ?Sized> Renderer<S, C> for Pssmlt {
+    fn pre_render(&mut self, scene: &S, camera: &C, setup: &RenderSettings) {
+        Renderer::<S, C>::pre_render(&mut self.path_tracer, scene, camera, setup);
+        self.bootstrap(scene, camera, setup.threads_num);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use renderer::testing;
+
+    #[test]
+    fn reject_restores_samples() {
+        let mut samples = PrimarySamples::new(7, 0.01, 0.0);
+        let first: Vec<Real> = (0..4).map(|_| samples.next_sample()).collect();
+
+        samples.start_iteration();
+        let mutated: Vec<Real> = (0..4).map(|_| samples.next_sample()).collect();
+        assert!(first.iter().zip(&mutated).all(|(a, b)| a != b));
+        samples.reject();
+
+        assert!(samples.samples.iter().zip(&first).all(|(s, v)| s.value == *v));
+        assert_eq!(samples.iteration, 0);
+    }
+
+    #[test]
+    fn chains_start_from_seed() {
+        let scene = testing::diffuse_scene();
+        let camera = testing::diffuse_camera(8, 8);
+        let starts = |seed| {
+            let setup = RenderSettings::new(1, 4).with_seed(seed);
+            let mut pssmlt = Pssmlt::new(PathTracer::new(&setup), &setup)
+                .with_bootstrap_samples(1000)
+                .with_chains(4);
+            Renderer::pre_render(&mut pssmlt, &scene, &camera, &setup);
+            pssmlt
+                .chains
+                .iter()
+                .map(|c| {
+                    let chain = c.lock().unwrap();
+                    (chain.current.x, chain.current.y)
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(starts(3), starts(3));
+    }
+}
@@ -0,0 +1,179 @@
+use scoped_threadpool::Pool;
+use std::cmp::{max, min};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Order in which tiles of a pass are handed out to the workers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TileOrder {
+    /// Row by row, from the top left corner.
+    Scanline,
+    /// Rings of tiles growing from the image center.
+    Spiral,
+    /// Most expensive tiles of the previous pass first, spiral order until
+    /// the first pass has been measured.
+    Cost,
+}
+
+impl Default for TileOrder {
+    fn default() -> Self {
+        TileOrder::Spiral
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub ix: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Splits the image into tiles of `chunk` size. Tiles at the right and
+/// bottom edges are clipped to the image.
+pub fn split_tiles((width, height): (u32, u32), (chunk_w, chunk_h): (u32, u32)) -> Vec<Tile> {
+    let chunk_w = max(chunk_w, 1);
+    let chunk_h = max(chunk_h, 1);
+    let mut tiles = Vec::new();
+    let mut y = 0;
+    while y < height {
+        let mut x = 0;
+        while x < width {
+            tiles.push(Tile {
+                ix: tiles.len(),
+                x,
+                y,
+                width: min(chunk_w, width - x),
+                height: min(chunk_h, height - y),
+            });
+            x += chunk_w;
+        }
+        y += chunk_h;
+    }
+    tiles
+}
+
+fn sort_spiral(tiles: &mut [Tile], (width, height): (u32, u32), (chunk_w, chunk_h): (u32, u32)) {
+    let cx = width as f64 * 0.5;
+    let cy = height as f64 * 0.5;
+    let key = |t: &Tile| {
+        let dx = (t.x as f64 + t.width as f64 * 0.5 - cx) / max(chunk_w, 1) as f64;
+        let dy = (t.y as f64 + t.height as f64 * 0.5 - cy) / max(chunk_h, 1) as f64;
+        let ring = dx.abs().max(dy.abs()).round();
+        (ring, dy.atan2(dx))
+    };
+    tiles.sort_by(|t0, t1| key(t0).partial_cmp(&key(t1)).unwrap());
+}
+
+struct TileCosts {
+    layout: ((u32, u32), (u32, u32)),
+    costs: Vec<u64>,
+}
+
+/// Worker threads shared by all passes of a renderer.
+///
+/// The thread pool is created on first use and recreated only when the
+/// number of threads in `RenderSettings` changes.
+pub struct WorkerPool {
+    pool: Mutex<Option<Pool>>,
+    tile_costs: Mutex<TileCosts>,
+}
+
+impl WorkerPool {
+    pub fn new() -> WorkerPool {
+        WorkerPool {
+            pool: Mutex::new(None),
+            tile_costs: Mutex::new(TileCosts {
+                layout: ((0, 0), (0, 0)),
+                costs: Vec::new(),
+            }),
+        }
+    }
+
+    /// Runs `f` with the pool, creating it first if needed.
+    pub fn with_pool<F, R>(&self, threads_num: u32, f: F) -> R
+    where
+        F: FnOnce(&mut Pool) -> R,
+    {
+        let threads_num = max(threads_num, 1);
+        let mut guard = self.pool.lock().unwrap();
+        let recreate = match *guard {
+            Some(ref pool) => pool.thread_count() != threads_num,
+            None => true,
+        };
+        if recreate {
+            *guard = Some(Pool::new(threads_num));
+        }
+        f(guard.as_mut().unwrap())
+    }
+
+    /// Returns the tiles of the image in the order they should be rendered.
+    pub fn schedule(
+        &self,
+        image_size: (u32, u32),
+        chunk: (u32, u32),
+        order: TileOrder,
+    ) -> Vec<Tile> {
+        let mut tiles = split_tiles(image_size, chunk);
+        match order {
+            TileOrder::Scanline => {}
+            TileOrder::Spiral => sort_spiral(&mut tiles, image_size, chunk),
+            TileOrder::Cost => {
+                let costs = self.tile_costs.lock().unwrap();
+                if costs.layout == (image_size, chunk) && costs.costs.len() == tiles.len() {
+                    tiles.sort_by(|t0, t1| costs.costs[t1.ix].cmp(&costs.costs[t0.ix]));
+                } else {
+                    sort_spiral(&mut tiles, image_size, chunk);
+                }
+            }
+        }
+        tiles
+    }
+
+    /// Stores the render time of a tile to be used by `TileOrder::Cost`.
+    pub fn record_cost(
+        &self,
+        image_size: (u32, u32),
+        chunk: (u32, u32),
+        tiles_num: usize,
+        tile: &Tile,
+        time: Duration,
+    ) {
+        let mut costs = self.tile_costs.lock().unwrap();
+        if costs.layout != (image_size, chunk) || costs.costs.len() != tiles_num {
+            costs.layout = (image_size, chunk);
+            costs.costs = vec![0; tiles_num];
+        }
+        costs.costs[tile.ix] = time.as_secs() * 1_000_000_000 + time.subsec_nanos() as u64;
+    }
+}
+
+impl Default for WorkerPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_covers_image() {
+        let tiles = split_tiles((100, 50), (32, 32));
+        assert_eq!(tiles.len(), 4 * 2);
+        let area: u32 = tiles.iter().map(|t| t.width * t.height).sum();
+        assert_eq!(area, 100 * 50);
+        assert_eq!(tiles[3].width, 4);
+        assert_eq!(tiles[4].height, 18);
+    }
+
+    #[test]
+    fn spiral_starts_at_center() {
+        let pool = WorkerPool::new();
+        let tiles = pool.schedule((96, 96), (32, 32), TileOrder::Spiral);
+        assert_eq!((tiles[0].x, tiles[0].y), (32, 32));
+        assert_eq!(tiles.len(), 9);
+    }
+}
@@ -0,0 +1,510 @@
+//! Stochastic progressive photon mapping (Hachisuka and Jensen 2009).
+//!
+//! Every pass traces one camera ray per pixel through the specular
+//! surfaces to a visible point, adds the emitted and directly sampled
+//! light on the way, and gathers the photons of the pass that land within
+//! the radius of the pixel. The radius shrinks
+//! with the photons gathered, so the estimate converges while caustics,
+//! that paths from the camera rarely find, are resolved early.
+//!
+//! The pixel statistics live in the renderer only, so a render cannot be
+//! resumed from a checkpoint nor split between the workers of a
+//! `Coordinator`.
+
+use super::{Checkpoint, RenderControl, RenderStatus, WorkerPool};
+use super::aov::AovBuffers;
+use super::inner::{first_hit_emission, parallel_map, sample_direct_light, sample_light_ray,
+                   scatters, CameraRayGenerator, PointGrid, RendererHelper};
+use {Color, RenderSettings, SurfacePoint};
+use color;
+use math::{Dot, Norm, Ray3f, Real, Vector3f};
+use std::f64::consts::PI;
+use std::io;
+use std::sync::Mutex;
+use traits::{RenderCamera, Renderer, SceneHandler, TexView};
+
+pub struct Sppm {
+    ray_gen: CameraRayGenerator,
+    workers: WorkerPool,
+    setup: RenderSettings,
+    initial_radius: Real,
+    alpha: Real,
+    photons_per_pass: Option<u32>,
+    state: Mutex<SppmState>,
+}
+
+impl Sppm {
+    /// `initial_radius` is the gather radius of the first pass in world
+    /// units, about the size of a few pixels at the typical depth.
+    pub fn new(setup: &RenderSettings, initial_radius: Real) -> Sppm {
+        Sppm {
+            ray_gen: CameraRayGenerator::new(),
+            workers: WorkerPool::new(),
+            setup: *setup,
+            initial_radius,
+            alpha: 2.0 / 3.0,
+            photons_per_pass: None,
+            state: Mutex::new(SppmState::new(0, 0, initial_radius)),
+        }
+    }
+
+    /// Fraction of the photons of a pass kept when shrinking the radius,
+    /// in `(0, 1)`. Smaller values shrink the radius faster.
+    pub fn with_alpha(mut self, alpha: Real) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    /// Photons emitted per pass, the number of pixels by default.
+    pub fn with_photons_per_pass(mut self, photons: u32) -> Self {
+        self.photons_per_pass = Some(photons);
+        self
+    }
+
+    fn photons_num(&self, width: u32, height: u32) -> u32 {
+        self.photons_per_pass.unwrap_or(width * height)
+    }
+
+    /// Checks that the passes before `first_pass` are the ones in the pixel
+    /// statistics, the estimate cannot continue from passes rendered
+    /// elsewhere.
+    fn check_first_pass(&self, first_pass: u32) {
+        let passes = self.state.lock().unwrap().passes;
+        assert_eq!(
+            passes, first_pass,
+            "SPPM rendered {} passes, cannot continue from pass {}",
+            passes, first_pass
+        );
+    }
+
+    /// Runs one SPPM pass and updates the pixel statistics.
+    fn iteration<S, C>(&self, scene: &S, camera: &C, threads_num: u32)
+    where
+        S: SceneHandler + ?Sized,
+        C: RenderCamera + ?Sized,
+    {
+        let (width, height) = (camera.width(), camera.height());
+        let mut state = self.state.lock().unwrap();
+
+        let rows = parallel_map(&self.workers, threads_num, height as usize, |y| {
+            (0..width)
+                .map(|x| self.visible_point(scene, x, y as u32))
+                .collect::<Vec<_>>()
+        });
+        let mut points = Vec::new();
+        for (pixel, (direct, vp)) in rows.into_iter().flat_map(|r| r).enumerate() {
+            state.pixels[pixel].direct += direct;
+            if let Some((sp, in_dir, beta)) = vp {
+                points.push(VisiblePoint {
+                    pixel,
+                    sp,
+                    in_dir,
+                    beta,
+                });
+            }
+        }
+
+        let radii: Vec<Real> = points
+            .iter()
+            .map(|vp| state.pixels[vp.pixel].radius)
+            .collect();
+        let mut grid = PointGrid::new(radii.iter().cloned().fold(0.0, Real::max));
+        for (i, vp) in points.iter().enumerate() {
+            grid.insert(i, &vp.sp.position, radii[i]);
+        }
+
+        let photons = self.photons_num(width, height);
+        let jobs = ::std::cmp::max(threads_num, 1);
+        let gathered = parallel_map(&self.workers, threads_num, jobs as usize, |job| {
+            let count = photons / jobs + if (job as u32) < photons % jobs { 1 } else { 0 };
+            self.trace_photons(scene, count, &points, &radii, &grid)
+        });
+
+        for (i, vp) in points.iter().enumerate() {
+            let (phi, m) = gathered.iter().fold((color::BLACK, 0), |(phi, m), g| {
+                (phi + g[i].0, m + g[i].1)
+            });
+            state.pixels[vp.pixel].gather(self.alpha, phi, m);
+        }
+        state.passes += 1;
+        state.photons_emitted += photons as Real;
+    }
+
+    /// Follows a camera ray through pixel `(x, y)` across the specular
+    /// surfaces to the first other hit. Returns the light emitted and
+    /// directly reaching the path, and the hit with the direction it is
+    /// reached from and the throughput of the path to it.
+    fn visible_point<'a, S>(
+        &self,
+        scene: &'a S,
+        x: u32,
+        y: u32,
+    ) -> (Color, Option<(SurfacePoint<'a>, Vector3f, Color)>)
+    where
+        S: SceneHandler + ?Sized,
+    {
+        let mut ray = match self.ray_gen.get_ray(x, y) {
+            Some(ray) => ray,
+            None => return (color::BLACK, None),
+        };
+        let mut beta = color::WHITE;
+        let mut direct = color::BLACK;
+        for _ in 0..self.setup.path_depth {
+            let sp = match scene.intersection(&ray) {
+                Some(sp) => sp,
+                None => break,
+            };
+            if !scatters(&sp, &ray) {
+                break;
+            }
+            if sp.normal.dot(&(-ray.dir)) > 0.0 {
+                if let Some(le) = sp.bsdf.radiance_dir(&sp.normal, &(-ray.dir)) {
+                    direct += beta * le;
+                }
+            }
+            if !sp.bsdf.is_specular() {
+                direct += beta * sample_direct_light(scene, &ray, &sp);
+                return (direct, Some((sp, ray.dir, beta)));
+            }
+
+            // a light sample cannot reach the path through a specular vertex
+            let (new_ray_dir, fr, pdf_p) = sp.bsdf.sample_proj(&sp.normal, &ray.dir);
+            if pdf_p <= 0.0 {
+                break;
+            }
+            beta = (beta * fr) * (1.0 / pdf_p) as f32;
+            ray = Ray3f::with_time(&sp.position, &new_ray_dir, ray.time);
+        }
+        (direct, None)
+    }
+
+    /// Traces `count` photons, returns the flux and the photons gathered by
+    /// every visible point.
+    fn trace_photons<S>(
+        &self,
+        scene: &S,
+        count: u32,
+        points: &[VisiblePoint],
+        radii: &[Real],
+        grid: &PointGrid,
+    ) -> Vec<(Color, u32)>
+    where
+        S: SceneHandler + ?Sized,
+    {
+        let mut gathered = vec![(color::BLACK, 0); points.len()];
+        for _ in 0..count {
+            let time = self.ray_gen.sample_time();
+            let (mut ray, mut beta) = match sample_light_ray(scene, time) {
+                Some(r) => r,
+                None => return gathered,
+            };
+
+            // a photon deposited after n bounces forms a camera path
+            // hitting the emitter at depth n + 1
+            for depth in 0..self.setup.path_depth.saturating_sub(1) {
+                let sp = match scene.intersection(&ray) {
+                    Some(sp) => sp,
+                    None => break,
+                };
+                if !scatters(&sp, &ray) {
+                    break;
+                }
+
+                // the first hit is direct light, sampled at the visible points,
+                // specular surfaces have none
+                if depth > 0 && !sp.bsdf.is_specular() {
+                    for &i in grid.lookup(&sp.position) {
+                        let vp = &points[i];
+                        let d2 = (vp.sp.position - sp.position).norm_squared();
+                        if d2 > radii[i] * radii[i] || vp.sp.normal.dot(&(-ray.dir)) <= 0.0 {
+                            continue;
+                        }
+                        let (fr, _) = vp.sp.bsdf.eval(&vp.sp.normal, &vp.in_dir, &(-ray.dir));
+                        gathered[i].0 += vp.beta * beta * fr;
+                        gathered[i].1 += 1;
+                    }
+                }
+
+                let (new_ray_dir, fr, pdf_p) = sp.bsdf.sample_proj(&sp.normal, &ray.dir);
+                if pdf_p <= 0.0 {
+                    break;
+                }
+                beta = (beta * fr) * (1.0 / pdf_p) as f32;
+                ray = Ray3f::with_time(&sp.position, &new_ray_dir, time);
+            }
+        }
+        gathered
+    }
+
+    fn write_image(&self, out_image: &mut TexView<Color>) {
+        let state = self.state.lock().unwrap();
+        if state.passes == 0 {
+            return;
+        }
+        let passes = state.passes as f32;
+        for y in 0..state.height {
+            for x in 0..state.width {
+                let stats = &state.pixels[y * state.width + x];
+                let area = PI as Real * stats.radius * stats.radius;
+                let indirect = stats.flux * (1.0 / (state.photons_emitted * area)) as f32;
+                out_image.set_pixel(x, y, stats.direct * (1.0 / passes) + indirect);
+            }
+        }
+    }
+}
+
+struct PixelStats {
+    radius: Real,
+    /// Photons gathered so far, reduced by `alpha` every pass.
+    photons: Real,
+    /// Flux of the gathered photons times the BSDF.
+    flux: Color,
+    /// Sum of the emitted and direct light over the passes.
+    direct: Color,
+}
+
+impl PixelStats {
+    /// Adds the `m` photons of a pass carrying `phi`, keeping `alpha` of them
+    /// and shrinking the radius to the area they would cover.
+    fn gather(&mut self, alpha: Real, phi: Color, m: u32) {
+        if m == 0 {
+            return;
+        }
+        let n = self.photons + alpha * m as Real;
+        let radius = self.radius * (n / (self.photons + m as Real)).sqrt();
+        let shrink = (radius * radius) / (self.radius * self.radius);
+        self.flux = (self.flux + phi) * shrink as f32;
+        self.photons = n;
+        self.radius = radius;
+    }
+}
+
+struct SppmState {
+    width: usize,
+    height: usize,
+    pixels: Vec<PixelStats>,
+    passes: u32,
+    photons_emitted: Real,
+}
+
+impl SppmState {
+    fn new(width: usize, height: usize, radius: Real) -> SppmState {
+        SppmState {
+            width,
+            height,
+            pixels: (0..width * height)
+                .map(|_| {
+                    PixelStats {
+                        radius,
+                        photons: 0.0,
+                        flux: color::BLACK,
+                        direct: color::BLACK,
+                    }
+                })
+                .collect(),
+            passes: 0,
+            photons_emitted: 0.0,
+        }
+    }
+}
+
+struct VisiblePoint<'a> {
+    pixel: usize,
+    sp: SurfacePoint<'a>,
+    in_dir: Vector3f,
+    /// Throughput of the camera path to `sp`.
+    beta: Color,
+}
+
+impl<S: SceneHandler + ?Sized, C: RenderCamera + ?Sized> RendererHelper<S, C> for Sppm {
+    fn trace_path(&self, scene: &S, initial_ray: &Ray3f, _: &RenderSettings) -> Color {
+        first_hit_emission(scene, initial_ray)
+    }
+
+    fn get_ray(&self, _: &C, x: u32, y: u32) -> Option<Ray3f> {
+        self.ray_gen.get_ray(x, y)
+    }
+
+    fn workers(&self) -> &WorkerPool {
+        &self.workers
+    }
+
+    /// The statistics of every pass are shared by the whole image.
+    fn supports_jobs(&self) -> bool {
+        false
+    }
+
+    /// Runs the passes over the whole image, `on_tile` is called after
+    /// every pass. AOVs are not supported. Panics if `first_pass` is not the
+    /// number of passes rendered since `pre_render`.
+    fn render_tiles(
+        &self,
+        scene: &S,
+        camera: &C,
+        setup: &RenderSettings,
+        passes: (u32, u32),
+        out_image: &mut TexView<Color>,
+        _: Option<&mut AovBuffers>,
+        on_tile: &(Fn(usize, usize) -> bool + Sync),
+    ) -> bool {
+        let (first_pass, passes_num) = passes;
+        self.check_first_pass(first_pass);
+        for p in 0..passes_num {
+            self.iteration(scene, camera, setup.threads_num);
+            if !on_tile(p as usize + 1, passes_num as usize) {
+                self.write_image(out_image);
+                return false;
+            }
+        }
+        self.write_image(out_image);
+        true
+    }
+}
+
+impl<S: SceneHandler + ?Sized, C: RenderCamera + ?Sized> Renderer<S, C> for Sppm {
+    fn pre_render(&mut self, _: &S, camera: &C, _: &RenderSettings) {
+        self.ray_gen = CameraRayGenerator::with_camera(camera);
+        *self.state.get_mut().unwrap() = SppmState::new(
+            camera.width() as usize,
+            camera.height() as usize,
+            self.initial_radius,
+        );
+    }
+
+    fn render_pass(
+        &self,
+        scene: &S,
+        camera: &C,
+        _: &RenderSettings,
+        pass_num: u32,
+        out_image: &mut TexView<Color>,
+    ) {
+        self.check_first_pass(pass_num);
+        self.iteration(scene, camera, 1);
+        self.write_image(out_image);
+    }
+
+    /// Fails for checkpoints holding rendered passes, their photon
+    /// statistics are not saved.
+    fn resume_scene_controlled(
+        &mut self,
+        scene: &S,
+        camera: &C,
+        setup: &RenderSettings,
+        checkpoint: &Checkpoint,
+        out_image: &mut TexView<Color>,
+        control: &RenderControl,
+    ) -> io::Result<RenderStatus> {
+        if checkpoint.passes() > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "SPPM cannot resume a render, its photon statistics are not checkpointed",
+            ));
+        }
+        self.render_scene_controlled(scene, camera, setup, out_image, control)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use renderer::PathTracer;
+    use renderer::testing::{self, TestCamera};
+    use scenehandler::ShapeList;
+    use sphere::Sphere;
+    use texture::Texture;
+
+    type Scene = ShapeList<'static, Sphere>;
+
+    fn stats(radius: Real, photons: Real, flux: Color) -> PixelStats {
+        PixelStats {
+            radius,
+            photons,
+            flux,
+            direct: color::BLACK,
+        }
+    }
+
+    #[test]
+    fn gather_shrinks_radius_and_flux() {
+        let mut s = stats(1.0, 10.0, color::WHITE * 2.0);
+        s.gather(0.5, color::WHITE, 10);
+
+        // 15 of the 20 photons are kept, the area shrinks by 15/20
+        assert_eq!(s.photons, 15.0);
+        assert!((s.radius - 0.75f64.sqrt()).abs() < 1e-12);
+        assert!((s.flux.r - 2.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn gather_without_photons_keeps_stats() {
+        let mut s = stats(0.5, 4.0, color::WHITE);
+        s.gather(0.5, color::BLACK, 0);
+
+        assert_eq!((s.radius, s.photons), (0.5, 4.0));
+        assert_eq!(s.flux, color::WHITE);
+    }
+
+    /// Mean of the channels over a 16x16 image of `scene`.
+    fn mean<R>(renderer: &mut R, scene: &Scene, setup: &RenderSettings) -> f32
+    where
+        R: Renderer<Scene, TestCamera>,
+    {
+        let camera = testing::diffuse_camera(16, 16);
+        let mut img = Texture::<Color>::new(16, 16);
+        renderer.render_scene_threads(scene, &camera, setup, &mut img);
+        img.pixels().map(|c| (c.r + c.g + c.b) / 3.0).sum::<f32>() / 256.0
+    }
+
+    #[test]
+    fn glass_matches_path_tracer() {
+        let scene = testing::glass_scene();
+        let setup = RenderSettings::new(32, 6);
+        let expected = mean(&mut PathTracer::new(&setup), &scene, &setup);
+        let mut sppm = Sppm::new(&setup, 0.1).with_photons_per_pass(4096);
+        let sppm = mean(&mut sppm, &scene, &setup);
+        assert!((sppm - expected).abs() < 0.1 * expected, "{} vs {}", sppm, expected);
+    }
+
+    #[test]
+    fn passes_continue_across_calls() {
+        let scene = testing::diffuse_scene();
+        let camera = testing::diffuse_camera(4, 4);
+        let setup = RenderSettings::new(3, 4);
+        let mut img = Texture::<Color>::new(4, 4);
+        let mut sppm = Sppm::new(&setup, 0.5);
+        sppm.render_scene_threads(&scene, &camera, &setup, &mut img);
+
+        let state = sppm.state.lock().unwrap();
+        assert_eq!(state.passes, 3);
+        assert!(state.pixels.iter().any(|s| s.radius < 0.5));
+    }
+
+    #[test]
+    #[should_panic]
+    fn foreign_passes_panic() {
+        let scene = testing::diffuse_scene();
+        let camera = testing::diffuse_camera(4, 4);
+        let setup = RenderSettings::new(4, 4);
+        let mut img = Texture::<Color>::new(4, 4);
+        let mut sppm = Sppm::new(&setup, 0.5);
+        sppm.pre_render(&scene, &camera, &setup);
+        sppm.render_passes_threads(&scene, &camera, &setup, 2, 1, &mut img);
+    }
+
+    #[test]
+    fn resume_is_rejected() {
+        let scene = testing::diffuse_scene();
+        let camera = testing::diffuse_camera(4, 4);
+        let setup = RenderSettings::new(4, 4);
+        let mut img = Texture::<Color>::new(4, 4);
+        let checkpoint = Checkpoint::capture(&setup, 2, &img);
+        let mut sppm = Sppm::new(&setup, 0.5);
+
+        let control = RenderControl::new();
+        let res = sppm.resume_scene_controlled(
+            &scene, &camera, &setup, &checkpoint, &mut img, &control,
+        );
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,155 @@
+//! Camera and scenes shared by the renderer tests.
+
+use bsdf::{Dielectric, Diffuse, Ior};
+use color;
+use math::{self, Cross, Isometry3, Matrix4f, Norm, Point3f, Real, Vector3f};
+use motion::Motion;
+use scenehandler::{ShapeList, ShapeListBuilder};
+use sphere::Sphere;
+use std::sync::Arc;
+use traits::RenderCamera;
+
+/// Pinhole camera with a fixed frame.
+pub struct TestCamera {
+    width: u32,
+    height: u32,
+    fovy: Real,
+    pos: Point3f,
+    forward: Vector3f,
+    up: Vector3f,
+    shutter: (Real, Real),
+}
+
+impl TestCamera {
+    /// Camera at the origin looking along `-z`.
+    pub fn new(width: u32, height: u32) -> TestCamera {
+        TestCamera::looking_at(width, height, math::origin(), Point3f::new(0.0, 0.0, -1.0))
+    }
+
+    pub fn looking_at(width: u32, height: u32, pos: Point3f, target: Point3f) -> TestCamera {
+        let forward = (target - pos).normalize();
+        let right = forward.cross(&Vector3f::new(0.0, 1.0, 0.0)).normalize();
+        TestCamera {
+            width,
+            height,
+            fovy: 1.0,
+            pos,
+            forward,
+            up: right.cross(&forward),
+            shutter: (0.0, 0.0),
+        }
+    }
+
+    pub fn with_shutter(mut self, t0: Real, t1: Real) -> TestCamera {
+        self.shutter = (t0, t1);
+        self
+    }
+}
+
+impl RenderCamera for TestCamera {
+    fn view_matrix(&self) -> Matrix4f {
+        math::one()
+    }
+    fn proj_matrix(&self) -> Matrix4f {
+        math::one()
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+    fn width(&self) -> u32 {
+        self.width
+    }
+    fn aspect(&self) -> Real {
+        self.width as Real / self.height as Real
+    }
+    fn znear(&self) -> Real {
+        0.1
+    }
+    fn zfar(&self) -> Real {
+        1000.0
+    }
+    fn fovx(&self) -> Real {
+        2.0 * (self.aspect() * (0.5 * self.fovy).tan()).atan()
+    }
+    fn fovy(&self) -> Real {
+        self.fovy
+    }
+
+    fn pos(&self) -> Point3f {
+        self.pos
+    }
+    fn up_vec(&self) -> Vector3f {
+        self.up
+    }
+    fn forward_vec(&self) -> Vector3f {
+        self.forward
+    }
+    fn right_vec(&self) -> Vector3f {
+        self.forward.cross(&self.up)
+    }
+
+    fn shutter(&self) -> (Real, Real) {
+        self.shutter
+    }
+}
+
+pub fn empty_scene() -> ShapeList<'static, Sphere> {
+    ShapeListBuilder::new().into_shape_list()
+}
+
+/// Diffuse ground lit by a spherical light above it, see `diffuse_camera`.
+pub fn diffuse_scene() -> ShapeList<'static, Sphere> {
+    diffuse_builder().into_shape_list()
+}
+
+/// `diffuse_scene` with a glass sphere below the light, casting a caustic
+/// on the ground.
+pub fn glass_scene() -> ShapeList<'static, Sphere> {
+    let mut builder = diffuse_builder();
+    builder.add_shape(Sphere::new(
+        Point3f::new(0.0, 1.2, 0.0),
+        0.6,
+        Arc::new(Dielectric::new(Ior::Constant(1.5))),
+    ));
+    builder.into_shape_list()
+}
+
+/// `diffuse_scene` with the light moving in from the side, it reaches its
+/// place in `diffuse_scene` at time 1.
+pub fn moving_light_scene() -> ShapeList<'static, Sphere> {
+    let mut builder = ground_builder();
+    let from = Isometry3::new(Vector3f::new(3.0, 0.0, 0.0), math::zero());
+    builder.add_shape(light().with_motion(Motion::linear(0.0, from, 1.0, math::one())));
+    builder.into_shape_list()
+}
+
+fn diffuse_builder() -> ShapeListBuilder<'static, Sphere> {
+    let mut builder = ground_builder();
+    builder.add_shape(light());
+    builder
+}
+
+fn ground_builder() -> ShapeListBuilder<'static, Sphere> {
+    let mut builder = ShapeListBuilder::new();
+    builder.add_shape(Sphere::new(
+        Point3f::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Diffuse::new(color::WHITE * 0.5, None)),
+    ));
+    builder
+}
+
+fn light() -> Sphere {
+    Sphere::new(
+        Point3f::new(0.0, 3.0, 0.0),
+        1.0,
+        Arc::new(Diffuse::new(color::WHITE, Some(color::WHITE * 5.0))),
+    )
+}
+
+/// Camera looking down at the light of `diffuse_scene` and the ground
+/// around it.
+pub fn diffuse_camera(width: u32, height: u32) -> TestCamera {
+    TestCamera::looking_at(width, height, Point3f::new(0.0, 4.0, 8.0), Point3f::new(0.0, 1.0, 0.0))
+}
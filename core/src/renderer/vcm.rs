@@ -0,0 +1,765 @@
+//! Vertex connection and merging (Georgiev et al. 2012).
+//!
+//! Every pass traces one light subpath per pixel and keeps its vertices.
+//! They are connected to the camera, connected to the vertices of the
+//! camera subpath of their pixel and, like photons, merged with the
+//! vertices of all the camera subpaths that lie within a radius. These
+//! techniques, together with hitting and sampling the emitters from the
+//! camera, are weighted with the balance heuristic from quantities
+//! accumulated along the subpaths (the recursive form of SmallVCM), so
+//! diffuse interiors get the low noise of bidirectional connections and
+//! caustics that of photon merging. The merging radius shrinks every pass.
+//!
+//! Specular vertices, like glass and mirrors, only carry the subpaths on:
+//! they are not connected, merged nor stored as light vertices.
+
+use super::WorkerPool;
+use super::aov::AovBuffers;
+use super::inner::{emission_point_pdf, first_hit_emission, parallel_map, sample_emission_point,
+                   CameraRayGenerator, PointGrid, RendererHelper};
+use {Color, RenderSettings, SurfacePoint};
+use color;
+use math::{self, Dot, Norm, Point3f, Ray3f, Real, Vector3f};
+use std::f64::consts::PI;
+use std::sync::Mutex;
+use traits::{RenderCamera, Renderer, SceneHandler, TexView};
+use utils::consts;
+
+/// Only projections implementing `Projection::connect` and
+/// `Projection::direction_pdf` are supported, others render black.
+pub struct Vcm {
+    ray_gen: CameraRayGenerator,
+    workers: WorkerPool,
+    setup: RenderSettings,
+    initial_radius: Real,
+    alpha: Real,
+    state: Mutex<VcmState>,
+}
+
+impl Vcm {
+    /// `initial_radius` is the merging radius of the first pass, chosen
+    /// like the gather radius of `Sppm::new`.
+    pub fn new(setup: &RenderSettings, initial_radius: Real) -> Vcm {
+        Vcm {
+            ray_gen: CameraRayGenerator::new(),
+            workers: WorkerPool::new(),
+            setup: *setup,
+            initial_radius,
+            alpha: 0.75,
+            state: Mutex::new(VcmState::new(0, 0)),
+        }
+    }
+
+    /// The radius of pass `i` is `initial_radius / (i + 1)^((1 - alpha) / 2)`,
+    /// `alpha` in `(0, 1)`. Smaller values shrink the radius faster.
+    pub fn with_alpha(mut self, alpha: Real) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    fn pass_setup(&self, pass: u32, image_size: (u32, u32)) -> PassSetup {
+        let radius =
+            self.initial_radius / ((pass + 1) as Real).powf(0.5 * (1.0 - self.alpha));
+        let light_paths = (image_size.0 * image_size.1) as Real;
+        let eta = PI as Real * radius * radius * light_paths;
+        PassSetup {
+            image_size,
+            radius,
+            vm_weight: eta,
+            vc_weight: 1.0 / eta,
+            vm_normalization: 1.0 / eta,
+        }
+    }
+
+    /// Runs one pass and adds it to the image sum.
+    fn iteration<S, C>(&self, scene: &S, camera: &C, threads_num: u32)
+    where
+        S: SceneHandler + ?Sized,
+        C: RenderCamera + ?Sized,
+    {
+        let (width, height) = (camera.width(), camera.height());
+        let pixels = (width * height) as usize;
+        let mut state = self.state.lock().unwrap();
+        let pass = self.pass_setup(state.passes, (width, height));
+
+        let rows = parallel_map(&self.workers, threads_num, height as usize, |_| {
+            (0..width)
+                .map(|_| {
+                    let mut vertices = Vec::new();
+                    let mut splats = Vec::new();
+                    self.light_path(scene, &pass, &mut vertices, &mut splats);
+                    (vertices, splats)
+                })
+                .collect::<Vec<_>>()
+        });
+        let mut vertices = Vec::new();
+        let mut paths = Vec::with_capacity(pixels);
+        let mut splats = vec![color::BLACK; pixels];
+        for (path, path_splats) in rows.into_iter().flat_map(|r| r) {
+            let start = vertices.len();
+            vertices.extend(path);
+            paths.push((start, vertices.len()));
+            for (x, y, c) in path_splats {
+                splats[(y * width + x) as usize] += c;
+            }
+        }
+        let mut grid = PointGrid::new(pass.radius);
+        for (i, lv) in vertices.iter().enumerate() {
+            grid.insert(i, &lv.sp.position, 0.0);
+        }
+
+        let rows = parallel_map(&self.workers, threads_num, height as usize, |y| {
+            (0..width)
+                .map(|x| {
+                    // the light subpath traced for the same pixel
+                    let (start, end) = paths[y * width as usize + x as usize];
+                    let light_path = &vertices[start..end];
+                    self.camera_path(scene, &pass, (x, y as u32), light_path, &vertices, &grid)
+                })
+                .collect::<Vec<_>>()
+        });
+        for (ix, c) in rows.into_iter().flat_map(|r| r).enumerate() {
+            state.sum[ix] += c + splats[ix];
+        }
+        state.passes += 1;
+    }
+
+    /// Traces a light subpath, storing its vertices and adding its
+    /// connections to the camera to `splats`.
+    fn light_path<'a, S>(
+        &self,
+        scene: &'a S,
+        pass: &PassSetup,
+        vertices: &mut Vec<LightVertex<'a>>,
+        splats: &mut Vec<(u32, u32, Color)>,
+    ) where
+        S: SceneHandler + ?Sized,
+    {
+        let max_length = self.setup.path_depth;
+        if max_length == 0 {
+            return;
+        }
+        let origin: Point3f = math::origin();
+        let zero: Vector3f = math::zero();
+        let time = self.ray_gen.sample_time();
+        let (lp, pdf_a) = match scene
+            .light_sources()
+            .sample((&origin, &zero), time, sample_emission_point)
+        {
+            Some(s) => s,
+            None => return,
+        };
+        // cosine sampling, the pdf cancels the cosine at the light
+        let dir = math::hs_cosine_sampling(&lp.normal);
+        let le = match lp.bsdf.radiance_dir(&lp.normal, &dir) {
+            Some(le) => le,
+            None => return,
+        };
+        let cos_light = lp.normal.dot(&dir);
+        if cos_light <= 0.0 || pdf_a <= 0.0 {
+            return;
+        }
+        let emission_pdf_w = pdf_a * cos_light / PI as Real;
+        let mut throughput = le * (cos_light / emission_pdf_w) as f32;
+        let vc = cos_light / emission_pdf_w;
+        let mut mis = MisState {
+            vcm: pdf_a / emission_pdf_w,
+            vc,
+            vm: vc * pass.vc_weight,
+        };
+        let start = lp.position + lp.normal * consts::POSITION_EPSILON;
+        let mut ray = Ray3f::with_time(&start, &dir, time);
+
+        let mut length = 1;
+        loop {
+            let sp = match scene.intersection(&ray) {
+                Some(sp) => sp,
+                None => break,
+            };
+            let cos_in = match arrival_cos(&sp, &ray) {
+                Some(cos) => cos,
+                None => break,
+            };
+            mis.hit((sp.position - ray.origin).norm_squared(), cos_in);
+
+            let specular = sp.bsdf.is_specular();
+            if !specular && length < max_length {
+                if let Some(splat) =
+                    self.connect_to_camera(scene, pass, &ray, &sp, throughput, &mis)
+                {
+                    splats.push(splat);
+                }
+            }
+
+            let vertex_throughput = throughput;
+            let vertex_mis = mis;
+            let in_dir = ray.dir;
+            // the next vertex must still be connectable to a camera vertex
+            let next = if length + 2 <= max_length {
+                scatter(pass, &ray, &sp, &mut throughput, &mut mis)
+            } else {
+                None
+            };
+            if !specular {
+                vertices.push(LightVertex {
+                    sp,
+                    in_dir,
+                    throughput: vertex_throughput,
+                    length,
+                    mis: vertex_mis,
+                });
+            }
+
+            match next {
+                Some(r) => ray = r,
+                None => break,
+            }
+            length += 1;
+        }
+    }
+
+    /// Light subpath vertex `sp`, reached by `ray`, seen by the camera.
+    fn connect_to_camera<S>(
+        &self,
+        scene: &S,
+        pass: &PassSetup,
+        ray: &Ray3f,
+        sp: &SurfacePoint,
+        throughput: Color,
+        mis: &MisState,
+    ) -> Option<(u32, u32, Color)>
+    where
+        S: SceneHandler + ?Sized,
+    {
+        let cs = match self.ray_gen.connect(&sp.position, ray.time) {
+            Some(cs) => cs,
+            None => return None,
+        };
+        let dir = (cs.origin - sp.position).normalize();
+        let cos_surface = sp.normal.dot(&dir);
+        if cos_surface <= 0.0 {
+            return None;
+        }
+
+        // the camera subpath arrives along `-dir` and continues to the light
+        let (fr, rev_pdf_w) = sp.bsdf.eval(&sp.normal, &(-dir), &(-ray.dir));
+        let camera_pdf_a = cs.importance * cos_surface;
+        let w_light = camera_pdf_a * (pass.vm_weight + mis.vcm + mis.vc * rev_pdf_w);
+
+        if !visible(scene, &sp.position, &cs.origin, ray.time) {
+            return None;
+        }
+        let x = (cs.x as u32).min(pass.image_size.0 - 1);
+        let y = (cs.y as u32).min(pass.image_size.1 - 1);
+        let w = cos_surface * cs.importance / (w_light + 1.0);
+        Some((x, y, (throughput * fr) * w as f32))
+    }
+
+    /// Traces the camera subpath of `pixel` and gathers all the techniques
+    /// at its vertices.
+    fn camera_path<'a, S>(
+        &self,
+        scene: &'a S,
+        pass: &PassSetup,
+        pixel: (u32, u32),
+        light_path: &[LightVertex<'a>],
+        vertices: &[LightVertex<'a>],
+        grid: &PointGrid,
+    ) -> Color
+    where
+        S: SceneHandler + ?Sized,
+    {
+        let mut ray = match self.ray_gen.get_ray(pixel.0, pixel.1) {
+            Some(ray) => ray,
+            None => return color::BLACK,
+        };
+        let camera_pdf = match self.ray_gen.direction_pdf(&ray) {
+            Some(pdf) => pdf,
+            None => return color::BLACK,
+        };
+        let max_length = self.setup.path_depth;
+        let mut throughput = Color::from(1.0);
+        let mut mis = MisState {
+            vcm: 1.0 / camera_pdf,
+            vc: 0.0,
+            vm: 0.0,
+        };
+        let mut color = color::BLACK;
+
+        let mut length = 1;
+        while length <= max_length {
+            let sp = match scene.intersection(&ray) {
+                Some(sp) => sp,
+                None => break,
+            };
+            let cos_in = match arrival_cos(&sp, &ray) {
+                Some(cos) => cos,
+                None => break,
+            };
+            mis.hit((sp.position - ray.origin).norm_squared(), cos_in);
+
+            if let Some(le) = sp.bsdf.radiance_dir(&sp.normal, &(-ray.dir)) {
+                let w = emission_weight(scene, &ray, &sp, cos_in, length, &mis);
+                color += (throughput * le) * w as f32;
+            }
+            if length == max_length {
+                break;
+            }
+
+            if !sp.bsdf.is_specular() {
+                color += throughput * self.direct_light(scene, pass, &ray, &sp, &mis);
+                for lv in light_path {
+                    if lv.length + length + 1 > max_length {
+                        break;
+                    }
+                    color += throughput * connect_vertices(scene, pass, &ray, &sp, &mis, lv);
+                }
+                let merged = merge(pass, &ray, &sp, &mis, max_length - length, vertices, grid);
+                color += (throughput * merged) * pass.vm_normalization as f32;
+            }
+
+            ray = match scatter(pass, &ray, &sp, &mut throughput, &mut mis) {
+                Some(r) => r,
+                None => break,
+            };
+            length += 1;
+        }
+        color
+    }
+
+    /// Light reaching the camera vertex `sp` from a point sampled on the
+    /// emitters.
+    fn direct_light<S>(
+        &self,
+        scene: &S,
+        pass: &PassSetup,
+        ray: &Ray3f,
+        sp: &SurfacePoint,
+        mis: &MisState,
+    ) -> Color
+    where
+        S: SceneHandler + ?Sized,
+    {
+        let (lp, pdf_a) = match scene
+            .light_sources()
+            .sample((&sp.position, &sp.normal), ray.time, sample_emission_point)
+        {
+            Some(s) => s,
+            None => return color::BLACK,
+        };
+        let to_light = lp.position - sp.position;
+        let dist2 = to_light.norm_squared();
+        let dir = to_light / dist2.sqrt();
+        let le = match lp.bsdf.radiance_dir(&lp.normal, &(-dir)) {
+            Some(le) => le,
+            None => return color::BLACK,
+        };
+        let cos_to_light = sp.normal.dot(&dir);
+        let cos_at_light = lp.normal.dot(&(-dir));
+        if cos_to_light <= 0.0 || cos_at_light <= 0.0 || pdf_a <= 0.0 {
+            return color::BLACK;
+        }
+
+        let (fr, pdf_w) = sp.bsdf.eval(&sp.normal, &ray.dir, &dir);
+        let (_, rev_pdf_w) = sp.bsdf.eval(&sp.normal, &(-dir), &(-ray.dir));
+        let direct_pdf_w = pdf_a * dist2 / cos_at_light;
+        let emission_pdf_w = pdf_a * cos_at_light / PI as Real;
+        let w_light = pdf_w / direct_pdf_w;
+        let w_camera = emission_pdf_w * cos_to_light / (direct_pdf_w * cos_at_light)
+            * (pass.vm_weight + mis.vcm + mis.vc * rev_pdf_w);
+
+        if !visible(scene, &sp.position, &lp.position, ray.time) {
+            return color::BLACK;
+        }
+        let w = cos_to_light / (direct_pdf_w * (w_light + 1.0 + w_camera));
+        (fr * le) * w as f32
+    }
+
+    /// Continues from `first_pass` passes. Passes this renderer did not
+    /// render itself, like the ones of a restored checkpoint, are taken
+    /// from their average in `image`.
+    fn continue_from(&self, first_pass: u32, image: &TexView<Color>) {
+        let mut state = self.state.lock().unwrap();
+        if state.passes == first_pass {
+            return;
+        }
+        let passes = first_pass as f32;
+        for y in 0..state.height {
+            for x in 0..state.width {
+                let ix = y * state.width + x;
+                state.sum[ix] = image.pixel(x, y) * passes;
+            }
+        }
+        state.passes = first_pass;
+    }
+
+    fn write_image(&self, out_image: &mut TexView<Color>) {
+        let state = self.state.lock().unwrap();
+        if state.passes == 0 {
+            return;
+        }
+        let scale = 1.0 / state.passes as f32;
+        for y in 0..state.height {
+            for x in 0..state.width {
+                out_image.set_pixel(x, y, state.sum[y * state.width + x] * scale);
+            }
+        }
+    }
+}
+
+/// Cosine at which `ray` arrives at `sp`, `None` at the back of surfaces
+/// that do not transmit.
+fn arrival_cos(sp: &SurfacePoint, ray: &Ray3f) -> Option<Real> {
+    let cos = sp.normal.dot(&(-ray.dir));
+    if cos > 0.0 || (cos < 0.0 && sp.bsdf.ior().is_some()) {
+        Some(cos.abs())
+    } else {
+        None
+    }
+}
+
+/// Continues the subpath arriving along `ray` at `sp`, updating its
+/// throughput and MIS quantities. `None` if the subpath is absorbed.
+fn scatter(
+    pass: &PassSetup,
+    ray: &Ray3f,
+    sp: &SurfacePoint,
+    throughput: &mut Color,
+    mis: &mut MisState,
+) -> Option<Ray3f> {
+    if sp.bsdf.is_specular() {
+        let (new_dir, w, _) = sp.bsdf.sample_proj(&sp.normal, &ray.dir);
+        mis.specular(sp.normal.dot(&new_dir).abs());
+        *throughput = *throughput * w;
+        return Some(Ray3f::with_time(&sp.position, &new_dir, ray.time));
+    }
+
+    let (new_dir, fr, pdf_p) = sp.bsdf.sample_proj(&sp.normal, &ray.dir);
+    let (_, pdf_w) = sp.bsdf.eval(&sp.normal, &ray.dir, &new_dir);
+    let (_, rev_pdf_w) = sp.bsdf.eval(&sp.normal, &(-new_dir), &(-ray.dir));
+    // refracted continuations leave through the back of the surface
+    let cos_out = sp.normal.dot(&new_dir).abs();
+    if pdf_p <= 0.0 || pdf_w <= 0.0 || cos_out <= 0.0 {
+        return None;
+    }
+    mis.scatter(cos_out, pdf_w, rev_pdf_w, pass);
+    *throughput = (*throughput * fr) * (1.0 / pdf_p) as f32;
+    Some(Ray3f::with_time(&sp.position, &new_dir, ray.time))
+}
+
+/// Weight of the emission `sp`, reached by `ray` at the end of a camera
+/// subpath of `length` segments.
+fn emission_weight<S>(
+    scene: &S,
+    ray: &Ray3f,
+    sp: &SurfacePoint,
+    cos_in: Real,
+    length: u32,
+    mis: &MisState,
+) -> Real
+where
+    S: SceneHandler + ?Sized,
+{
+    // the only technique for emitters seen directly
+    if length == 1 {
+        return 1.0;
+    }
+    let origin: Point3f = math::origin();
+    let zero: Vector3f = math::zero();
+    let direct_pdf_a = scene.light_sources().pdf(
+        sp.surface,
+        (&sp.position, &sp.normal),
+        (&origin, &zero),
+        ray.time,
+        emission_point_pdf,
+    );
+    let emission_pdf_w = direct_pdf_a * cos_in / PI as Real;
+    1.0 / (1.0 + direct_pdf_a * mis.vcm + emission_pdf_w * mis.vc)
+}
+
+/// Connects the camera vertex `sp`, reached by `ray`, to the light
+/// vertex `lv`.
+fn connect_vertices<S>(
+    scene: &S,
+    pass: &PassSetup,
+    ray: &Ray3f,
+    sp: &SurfacePoint,
+    mis: &MisState,
+    lv: &LightVertex,
+) -> Color
+where
+    S: SceneHandler + ?Sized,
+{
+    let to_light = lv.sp.position - sp.position;
+    let dist2 = to_light.norm_squared();
+    let dir = to_light / dist2.sqrt();
+    let cos_camera = sp.normal.dot(&dir);
+    let cos_light = lv.sp.normal.dot(&(-dir));
+    if cos_camera <= 0.0 || cos_light <= 0.0 {
+        return color::BLACK;
+    }
+
+    let (camera_fr, camera_pdf_w) = sp.bsdf.eval(&sp.normal, &ray.dir, &dir);
+    let (_, camera_rev_pdf_w) = sp.bsdf.eval(&sp.normal, &(-dir), &(-ray.dir));
+    // at the light vertex the camera subpath arrives along `dir` and
+    // continues to the light
+    let (light_fr, light_rev_pdf_w) = lv.sp.bsdf.eval(&lv.sp.normal, &dir, &(-lv.in_dir));
+    let (_, light_pdf_w) = lv.sp.bsdf.eval(&lv.sp.normal, &lv.in_dir, &(-dir));
+    let camera_pdf_a = camera_pdf_w * cos_light / dist2;
+    let light_pdf_a = light_pdf_w * cos_camera / dist2;
+    let w_light = camera_pdf_a * (pass.vm_weight + lv.mis.vcm + lv.mis.vc * light_rev_pdf_w);
+    let w_camera = light_pdf_a * (pass.vm_weight + mis.vcm + mis.vc * camera_rev_pdf_w);
+
+    if !visible(scene, &sp.position, &lv.sp.position, ray.time) {
+        return color::BLACK;
+    }
+    let w = cos_camera * cos_light / (dist2 * (w_light + 1.0 + w_camera));
+    (camera_fr * light_fr * lv.throughput) * w as f32
+}
+
+/// Sum of the light vertices within the radius of the camera vertex `sp`
+/// with at most `max_length` segments, weighted but not normalized by the
+/// kernel area.
+fn merge(
+    pass: &PassSetup,
+    ray: &Ray3f,
+    sp: &SurfacePoint,
+    mis: &MisState,
+    max_length: u32,
+    vertices: &[LightVertex],
+    grid: &PointGrid,
+) -> Color {
+    let r2 = pass.radius * pass.radius;
+    let mut sum = color::BLACK;
+    grid.visit(&sp.position, pass.radius, |i| {
+        let lv = &vertices[i];
+        let light_dir = -lv.in_dir;
+        if lv.length > max_length || (lv.sp.position - sp.position).norm_squared() > r2
+            || sp.normal.dot(&light_dir) <= 0.0
+        {
+            return;
+        }
+        let (fr, camera_pdf_w) = sp.bsdf.eval(&sp.normal, &ray.dir, &light_dir);
+        let (_, camera_rev_pdf_w) = sp.bsdf.eval(&sp.normal, &lv.in_dir, &(-ray.dir));
+        let w_light = lv.mis.vcm * pass.vc_weight + lv.mis.vm * camera_pdf_w;
+        let w_camera = mis.vcm * pass.vc_weight + mis.vm * camera_rev_pdf_w;
+        sum += (fr * lv.throughput) * (1.0 / (w_light + 1.0 + w_camera)) as f32;
+    });
+    sum
+}
+
+/// Whether nothing blocks the segment from `from`, offset from its
+/// surface, to `to`.
+fn visible<S>(scene: &S, from: &Point3f, to: &Point3f, time: Real) -> bool
+where
+    S: SceneHandler + ?Sized,
+{
+    let d = *to - *from;
+    let dist = d.norm();
+    let ray = Ray3f::with_time(from, &(d / dist), time);
+    match scene.intersection(&ray) {
+        Some(ip) => (ip.position - *from).norm() >= dist - consts::POSITION_EPSILON * 2.0,
+        None => true,
+    }
+}
+
+/// Constants of a pass.
+struct PassSetup {
+    image_size: (u32, u32),
+    radius: Real,
+    /// Merging relative to connecting, `π r^2` times the light subpaths.
+    vm_weight: Real,
+    vc_weight: Real,
+    vm_normalization: Real,
+}
+
+/// Partial MIS weights carried along a subpath, `dVCM`, `dVC` and `dVM`
+/// in the paper's notation.
+#[derive(Copy, Clone, Debug)]
+struct MisState {
+    vcm: Real,
+    vc: Real,
+    vm: Real,
+}
+
+impl MisState {
+    /// Update for a segment of squared length `dist2` hitting a surface
+    /// at cosine `cos_in`.
+    fn hit(&mut self, dist2: Real, cos_in: Real) {
+        self.vcm *= dist2 / cos_in;
+        self.vc /= cos_in;
+        self.vm /= cos_in;
+    }
+
+    /// Update for continuing in a direction at cosine `cos_out` sampled
+    /// with `pdf_w`, `rev_pdf_w` being the pdf of the opposite direction.
+    fn scatter(&mut self, cos_out: Real, pdf_w: Real, rev_pdf_w: Real, pass: &PassSetup) {
+        let f = cos_out / pdf_w;
+        self.vc = f * (self.vc * rev_pdf_w + self.vcm + pass.vm_weight);
+        self.vm = f * (self.vm * rev_pdf_w + self.vcm * pass.vc_weight + 1.0);
+        self.vcm = 1.0 / pdf_w;
+    }
+
+    /// Update for a specular continuation at cosine `cos_out`, whose pdf
+    /// cancels with the pdf of the opposite direction.
+    fn specular(&mut self, cos_out: Real) {
+        self.vcm = 0.0;
+        self.vc *= cos_out;
+        self.vm *= cos_out;
+    }
+}
+
+struct LightVertex<'a> {
+    sp: SurfacePoint<'a>,
+    /// Direction of the ray arriving at the vertex.
+    in_dir: Vector3f,
+    throughput: Color,
+    /// Segments from the emitter.
+    length: u32,
+    mis: MisState,
+}
+
+struct VcmState {
+    width: usize,
+    height: usize,
+    /// Sum of the passes.
+    sum: Vec<Color>,
+    passes: u32,
+}
+
+impl VcmState {
+    fn new(width: usize, height: usize) -> VcmState {
+        VcmState {
+            width,
+            height,
+            sum: vec![color::BLACK; width * height],
+            passes: 0,
+        }
+    }
+}
+
+impl<S: SceneHandler + ?Sized, C: RenderCamera + ?Sized> RendererHelper<S, C> for Vcm {
+    fn trace_path(&self, scene: &S, initial_ray: &Ray3f, _: &RenderSettings) -> Color {
+        first_hit_emission(scene, initial_ray)
+    }
+
+    fn get_ray(&self, _: &C, x: u32, y: u32) -> Option<Ray3f> {
+        self.ray_gen.get_ray(x, y)
+    }
+
+    fn workers(&self) -> &WorkerPool {
+        &self.workers
+    }
+
+    /// The statistics of every pass are shared by the whole image.
+    fn supports_jobs(&self) -> bool {
+        false
+    }
+
+    /// Runs the passes over the whole image, `on_tile` is called after
+    /// every pass. AOVs are not supported. Passes before `first_pass` not
+    /// rendered since `pre_render` are read from `out_image`.
+    fn render_tiles(
+        &self,
+        scene: &S,
+        camera: &C,
+        setup: &RenderSettings,
+        passes: (u32, u32),
+        out_image: &mut TexView<Color>,
+        _: Option<&mut AovBuffers>,
+        on_tile: &(Fn(usize, usize) -> bool + Sync),
+    ) -> bool {
+        let (first_pass, passes_num) = passes;
+        self.continue_from(first_pass, out_image);
+        for p in 0..passes_num {
+            self.iteration(scene, camera, setup.threads_num);
+            if !on_tile(p as usize + 1, passes_num as usize) {
+                self.write_image(out_image);
+                return false;
+            }
+        }
+        self.write_image(out_image);
+        true
+    }
+}
+
+impl<S: SceneHandler + ?Sized, C: RenderCamera + ?Sized> Renderer<S, C> for Vcm {
+    fn pre_render(&mut self, _: &S, camera: &C, _: &RenderSettings) {
+        self.ray_gen = CameraRayGenerator::with_camera(camera);
+        *self.state.get_mut().unwrap() =
+            VcmState::new(camera.width() as usize, camera.height() as usize);
+    }
+
+    fn render_pass(
+        &self,
+        scene: &S,
+        camera: &C,
+        _: &RenderSettings,
+        pass_num: u32,
+        out_image: &mut TexView<Color>,
+    ) {
+        self.continue_from(pass_num, out_image);
+        self.iteration(scene, camera, 1);
+        self.write_image(out_image);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use renderer::PathTracer;
+    use renderer::testing::{self, TestCamera};
+    use scenehandler::ShapeList;
+    use sphere::Sphere;
+    use texture::Texture;
+
+    type Scene = ShapeList<'static, Sphere>;
+
+    /// Mean of the channels over a 16x16 image of `scene`.
+    fn mean<R>(renderer: &mut R, scene: &Scene, setup: &RenderSettings) -> f32
+    where
+        R: Renderer<Scene, TestCamera>,
+    {
+        let camera = testing::diffuse_camera(16, 16);
+        let mut img = Texture::<Color>::new(16, 16);
+        renderer.render_scene_threads(scene, &camera, setup, &mut img);
+        img.pixels().map(|c| (c.r + c.g + c.b) / 3.0).sum::<f32>() / 256.0
+    }
+
+    fn agrees_with_path_tracer(scene: Scene) {
+        let setup = RenderSettings::new(32, 6);
+        let expected = mean(&mut PathTracer::new(&setup), &scene, &setup);
+        let vcm = mean(&mut Vcm::new(&setup, 0.1), &scene, &setup);
+        assert!((vcm - expected).abs() < 0.1 * expected, "{} vs {}", vcm, expected);
+    }
+
+    #[test]
+    fn diffuse_scene_matches_path_tracer() {
+        agrees_with_path_tracer(testing::diffuse_scene());
+    }
+
+    #[test]
+    fn caustic_matches_path_tracer() {
+        agrees_with_path_tracer(testing::glass_scene());
+    }
+
+    #[test]
+    fn resumes_from_image() {
+        let scene = testing::diffuse_scene();
+        let camera = testing::diffuse_camera(4, 4);
+        let setup = RenderSettings::new(4, 4);
+        let mut img = Texture::<Color>::new(4, 4);
+        for y in 0..4 {
+            for x in 0..4 {
+                img.set_pixel(x, y, color::WHITE);
+            }
+        }
+        let mut vcm = Vcm::new(&setup, 0.1);
+        vcm.pre_render(&scene, &camera, &setup);
+        vcm.render_passes_threads(&scene, &camera, &setup, 3, 1, &mut img);
+
+        let state = vcm.state.lock().unwrap();
+        assert_eq!(state.passes, 4);
+        assert!(state.sum.iter().all(|c| c.r >= 3.0));
+    }
+}
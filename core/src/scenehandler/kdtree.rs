@@ -1,4 +1,4 @@
-use super::{LightSourcesHandler, LuminairesSampler, UniformSampler};
+use super::{LightSourcesHandler, LuminairesSampler, SceneIds, TraversalCost, UniformSampler};
 use SurfacePoint;
 use aabb::{intersection_aabb, Aabb3};
 use math::{Ray3f, Real};
@@ -391,6 +391,7 @@ where
     kdtree: KdTree<'a, T>,
     light_sources: Vec<&'a Surface>,
     sampler: Arc<S>,
+    ids: SceneIds,
 }
 
 impl<'a, T, S> KdTreeS<'a, T, S>
@@ -398,26 +399,42 @@ where
     T: BoundedSurface + ?Sized + 'a,
     S: LuminairesSampler<'a> + for<'s> From<&'s [&'a Surface]> + 'a,
 {
+    /// Every surface of `obj_iter` is an object of its own.
     pub fn new<I, U>(obj_iter: U, setup: KdTreeSetup) -> Self
     where
         I: Iterator<Item = &'a T> + 'a,
         U: IntoIterator<Item = I::Item, IntoIter = I> + 'a,
+    {
+        Self::from_objects(obj_iter.into_iter().map(Some), setup)
+    }
+
+    /// The surfaces of every item of `objects` share an object id, e.g.
+    /// the triangles of a mesh.
+    pub fn from_objects<O, I>(objects: O, setup: KdTreeSetup) -> Self
+    where
+        O: IntoIterator<Item = I>,
+        I: IntoIterator<Item = &'a T>,
     {
         let mut ls = Vec::new();
         let mut objs = Vec::new();
-        for s in obj_iter {
-            if s.is_emitter() {
-                ls.push(s.as_surface());
+        let mut ids = SceneIds::new();
+        for object in objects {
+            let surfaces: Vec<&'a T> = object.into_iter().collect();
+            ids.add_object(surfaces.iter().map(|&s| s.as_surface()));
+            for s in surfaces {
+                if s.is_emitter() {
+                    ls.push(s.as_surface());
+                }
+                objs.push((s.aabb(), s));
             }
-            objs.push((s.aabb(), s));
         }
         let sampler = S::from(ls.as_slice());
         Self {
             kdtree: KdTree::build(objs, setup),
             light_sources: ls,
             sampler: Arc::new(sampler),
+            ids,
         }
-
     }
 
     pub fn depth(&self) -> usize {
@@ -457,6 +474,34 @@ where
         res
     }
 
+    fn traversal_cost(&self, ray: &Ray3f) -> Option<TraversalCost> {
+        let mut cost = TraversalCost::default();
+        let mut t_min = Real::max_value();
+        for (leaf, _, t_far) in self.kdtree.traverse_iter(ray) {
+            cost.leaves += 1;
+            for s in leaf.iter() {
+                cost.tests += 1;
+                if let Some((t, _)) = s.intersection(ray) {
+                    if t > 0.0 && t < t_min {
+                        t_min = t;
+                    }
+                }
+            }
+            if t_min < t_far {
+                break;
+            }
+        }
+        Some(cost)
+    }
+
+
+    fn object_id(&self, surface: &Surface) -> u32 {
+        self.ids.object_id(surface)
+    }
+
+    fn material_id(&self, surface: &Surface) -> u32 {
+        self.ids.material_id(surface)
+    }
 
     fn light_sources_iter<'s>(&'s self) -> Box<Iterator<Item = &'s Surface> + 's> {
         box self.light_sources.iter().cloned()
@@ -6,8 +6,9 @@ pub use self::kdtree::{KdTreeSetup, Sah};
 pub use self::shapelist::{ShapeList, ShapeListBuilder};
 use SurfacePoint;
 use math::{Point3f, Ray3f, Real, Vector3f};
-use rand;
+use std::collections::HashMap;
 use std::sync::Arc;
+use utils::rng;
 
 use traits::Surface;
 
@@ -16,16 +17,83 @@ pub trait SceneHandler: Sync {
 
     fn light_sources_iter<'s>(&'s self) -> Box<Iterator<Item = &'s Surface> + 's>;
     fn light_sources(&self) -> LightSourcesHandler;
+
+    /// Work `intersection(ray)` does, `None` if the scene does not track it.
+    fn traversal_cost(&self, _: &Ray3f) -> Option<TraversalCost> {
+        None
+    }
+
+    /// Id of the object `surface` belongs to, see `SceneIds`.
+    fn object_id(&self, _: &Surface) -> u32 {
+        0
+    }
+
+    /// Id of the material of `surface`, see `SceneIds`.
+    fn material_id(&self, _: &Surface) -> u32 {
+        0
+    }
 }
 
-pub type SurfaceSamplerFn<'a> = fn(&'a Surface, (&Point3f, &Vector3f))
+/// Ids of the objects and materials of a scene, numbered from 1 in the
+/// order they are added to the scene, 0 for surfaces outside of it. The
+/// surfaces with the same `Surface::material_key` share a material id.
+#[derive(Clone, Debug, Default)]
+pub struct SceneIds {
+    surfaces: HashMap<usize, (u32, u32)>,
+    materials: HashMap<usize, u32>,
+    objects_num: u32,
+}
+
+impl SceneIds {
+    pub fn new() -> SceneIds {
+        Self::default()
+    }
+
+    /// Adds `surfaces` as one object, e.g. the triangles of a mesh.
+    pub fn add_object<'s, I>(&mut self, surfaces: I)
+    where
+        I: IntoIterator<Item = &'s Surface>,
+    {
+        self.objects_num += 1;
+        for s in surfaces {
+            let next_id = self.materials.len() as u32 + 1;
+            let material_id = *self.materials.entry(s.material_key()).or_insert(next_id);
+            self.surfaces
+                .insert(surface_key(s), (self.objects_num, material_id));
+        }
+    }
+
+    pub fn object_id(&self, surface: &Surface) -> u32 {
+        self.surfaces.get(&surface_key(surface)).map_or(0, |ids| ids.0)
+    }
+
+    pub fn material_id(&self, surface: &Surface) -> u32 {
+        self.surfaces.get(&surface_key(surface)).map_or(0, |ids| ids.1)
+    }
+}
+
+fn surface_key(surface: &Surface) -> usize {
+    surface as *const _ as *const u8 as usize
+}
+
+/// Work done by `SceneHandler::intersection` for one ray.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraversalCost {
+    /// Visited acceleration structure leaves.
+    pub leaves: u32,
+    /// Surfaces tested for an intersection.
+    pub tests: u32,
+}
+
+pub type SurfaceSamplerFn<'a> = fn(&'a Surface, (&Point3f, &Vector3f), Real)
     -> (SurfacePoint<'a>, Real);
-pub type SurfaceSamplerPdfFn<'a> = fn(&'a Surface, (&Point3f, &Vector3f), (&Point3f, &Vector3f))
-    -> Real;
+pub type SurfaceSamplerPdfFn<'a> =
+    fn(&'a Surface, (&Point3f, &Vector3f), (&Point3f, &Vector3f), Real) -> Real;
 pub trait LuminairesSampler<'a>: Sync + Send {
     fn sample(
         &self,
         view_point: (&Point3f, &Vector3f),
+        time: Real,
         surface_sampler: SurfaceSamplerFn<'a>,
     ) -> Option<(SurfacePoint<'a>, Real)>;
 
@@ -34,6 +102,7 @@ pub trait LuminairesSampler<'a>: Sync + Send {
         surface: &'a Surface,
         point_at_surface: (&Point3f, &Vector3f),
         view_point: (&Point3f, &Vector3f),
+        time: Real,
         surface_pdf: SurfaceSamplerPdfFn<'a>,
     ) -> Real;
 }
@@ -47,14 +116,15 @@ impl<'a> LuminairesSampler<'a> for UniformSampler<'a> {
     fn sample(
         &self,
         view_point: (&Point3f, &Vector3f),
+        time: Real,
         surface_sampler: SurfaceSamplerFn<'a>,
     ) -> Option<(SurfacePoint<'a>, Real)> {
         let s_num = self.surfaces.len();
 
         if s_num > 0 {
-            let i = rand::random::<usize>() % s_num;
+            let i = ((rng::uniform() * s_num as Real) as usize).min(s_num - 1);
             let s = self.surfaces[i];
-            let (sp, pdf) = surface_sampler(s, view_point);
+            let (sp, pdf) = surface_sampler(s, view_point, time);
 
             Some((sp, pdf / s_num as Real))
         } else {
@@ -68,10 +138,11 @@ impl<'a> LuminairesSampler<'a> for UniformSampler<'a> {
         surface: &'a Surface,
         point_at_surface: (&Point3f, &Vector3f),
         view_point: (&Point3f, &Vector3f),
+        time: Real,
         surface_pdf: SurfaceSamplerPdfFn<'a>,
     ) -> Real {
         let s_num = self.surfaces.len();
-        let pdf = surface_pdf(surface, point_at_surface, view_point);
+        let pdf = surface_pdf(surface, point_at_surface, view_point, time);
 
         pdf / s_num as Real
     }
@@ -131,13 +202,13 @@ impl<'a> LuminairesSampler<'a> for LinearSampler<'a> {
     fn sample(
         &self,
         view_point: (&Point3f, &Vector3f),
+        time: Real,
         surface_sampler: SurfaceSamplerFn<'a>,
     ) -> Option<(SurfacePoint<'a>, Real)> {
-        use rand::Closed01;
         let s_num = self.surfaces.len();
 
         if s_num > 0 {
-            let Closed01(mut e) = rand::random::<Closed01<Real>>();
+            let mut e = rng::uniform();
             e *= self.sum;
 
             let ix = match self.partial_sum
@@ -149,7 +220,7 @@ impl<'a> LuminairesSampler<'a> for LinearSampler<'a> {
             let s = self.surfaces[ix];
             let il = color_norm(&s.total_radiance().unwrap());
             let pdf = il / self.sum;
-            let (sp, spdf) = surface_sampler(s, view_point);
+            let (sp, spdf) = surface_sampler(s, view_point, time);
 
             Some((sp, spdf * pdf))
 
@@ -165,9 +236,10 @@ impl<'a> LuminairesSampler<'a> for LinearSampler<'a> {
         surface: &'a Surface,
         point_at_surface: (&Point3f, &Vector3f),
         view_point: (&Point3f, &Vector3f),
+        time: Real,
         surface_pdf: SurfaceSamplerPdfFn<'a>,
     ) -> Real {
-        let pdf = surface_pdf(surface, point_at_surface, view_point);
+        let pdf = surface_pdf(surface, point_at_surface, view_point, time);
         let il = color_norm(&surface.total_radiance().unwrap());
         let spdf = il / self.sum;
 
@@ -215,3 +287,36 @@ fn lt_arc_trait_hack<'a, 'b: 'a>(
 ) -> Arc<LuminairesSampler<'a> + 'b> {
     unsafe { ::std::mem::transmute(t) }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsdf::Diffuse;
+    use color;
+    use sphere::Sphere;
+
+    #[test]
+    fn ids_follow_scene_order() {
+        let white = Arc::new(Diffuse::new(color::WHITE, None));
+        let red = Arc::new(Diffuse::new(color::RED, None));
+        let sphere = |x, bsdf: &Arc<Diffuse>| {
+            Sphere::new(Point3f::new(x, 0.0, 0.0), 1.0, bsdf.clone())
+        };
+
+        let mut builder: ShapeListBuilder<Sphere> = ShapeListBuilder::new();
+        builder.add_shape(sphere(0.0, &red));
+        builder.add_object(vec![sphere(3.0, &white), sphere(6.0, &red)]);
+        builder.add_shape(sphere(9.0, &white));
+        let scene = builder.into_shape_list();
+
+        let ids = |x| {
+            let ray = Ray3f::new(&Point3f::new(x, 5.0, 0.0), &Vector3f::new(0.0, -1.0, 0.0));
+            let sp = scene.intersection(&ray).unwrap();
+            (scene.object_id(sp.surface), scene.material_id(sp.surface))
+        };
+        assert_eq!(ids(0.0), (1, 1));
+        assert_eq!(ids(3.0), (2, 2));
+        assert_eq!(ids(6.0), (2, 1));
+        assert_eq!(ids(9.0), (3, 2));
+    }
+}
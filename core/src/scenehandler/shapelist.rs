@@ -1,4 +1,4 @@
-use super::{LightSourcesHandler, LuminairesSampler, UniformSampler};
+use super::{LightSourcesHandler, LuminairesSampler, SceneIds, TraversalCost, UniformSampler};
 use SurfacePoint;
 use math::{Ray3f, Real};
 use std;
@@ -15,6 +15,8 @@ where
 {
     shapes: Vec<T>,
     light_sources: Vec<&'a Surface>,
+    /// Index of the first shape of every object.
+    objects: Vec<usize>,
     _marker: PhantomData<S>,
 }
 
@@ -27,11 +29,26 @@ where
         Self {
             shapes: Vec::new(),
             light_sources: Vec::new(),
+            objects: Vec::new(),
             _marker: PhantomData,
         }
     }
 
+    /// Adds `surface` as an object of its own.
     pub fn add_shape(&mut self, surface: T) {
+        self.objects.push(self.shapes.len());
+        self.push_shape(surface);
+    }
+
+    /// Adds `surfaces` as one object, which shares the object id.
+    pub fn add_object<I: IntoIterator<Item = T>>(&mut self, surfaces: I) {
+        self.objects.push(self.shapes.len());
+        for s in surfaces {
+            self.push_shape(s);
+        }
+    }
+
+    fn push_shape(&mut self, surface: T) {
         if surface.as_ref().is_emitter() {
             self.shapes.push(surface);
             let s_ref = self.shapes.last().unwrap().as_ref();
@@ -47,8 +64,10 @@ where
     where
         T: Clone,
     {
+        let shapes = self.shapes.clone();
         ShapeList {
-            shapes: self.shapes.clone(),
+            ids: scene_ids(&shapes, &self.objects),
+            shapes,
             light_sources: self.light_sources.clone(),
             sampler: Arc::new(S::from(self.light_sources.as_slice())),
         }
@@ -57,6 +76,7 @@ where
     pub fn into_shape_list(self) -> ShapeList<'a, T, S> {
         let sampler = Arc::new(S::from(self.light_sources.as_slice()));
         ShapeList {
+            ids: scene_ids(&self.shapes, &self.objects),
             shapes: self.shapes,
             light_sources: self.light_sources,
             sampler,
@@ -64,6 +84,16 @@ where
     }
 }
 
+/// Ids of `shapes`, the objects start at the indices in `objects`.
+fn scene_ids<'a, T: AsRef<Surface + 'a>>(shapes: &[T], objects: &[usize]) -> SceneIds {
+    let mut ids = SceneIds::new();
+    for (i, &start) in objects.iter().enumerate() {
+        let end = objects.get(i + 1).cloned().unwrap_or_else(|| shapes.len());
+        ids.add_object(shapes[start..end].iter().map(|s| s.as_ref()));
+    }
+    ids
+}
+
 pub struct ShapeList<'a, T, S = UniformSampler<'a>>
 where
     T: AsRef<Surface + 'a> + Sync + 'a,
@@ -72,6 +102,7 @@ where
     shapes: Vec<T>,
     light_sources: Vec<&'a (Surface + 'a)>,
     sampler: Arc<S>,
+    ids: SceneIds,
 }
 
 impl<'a, T, S> SceneHandler for ShapeList<'a, T, S>
@@ -99,6 +130,21 @@ where
         sp
     }
 
+    fn traversal_cost(&self, _: &Ray3f) -> Option<TraversalCost> {
+        Some(TraversalCost {
+            leaves: 1,
+            tests: self.shapes.len() as u32,
+        })
+    }
+
+    fn object_id(&self, surface: &Surface) -> u32 {
+        self.ids.object_id(surface)
+    }
+
+    fn material_id(&self, surface: &Surface) -> u32 {
+        self.ids.material_id(surface)
+    }
+
     fn light_sources_iter<'s>(&'s self) -> Box<Iterator<Item = &'s Surface> + 's> {
         box self.light_sources.iter().cloned()
     }
@@ -0,0 +1,446 @@
+//! Spectral sampling with hero wavelengths (Wilkie et al. 2014).
+//!
+//! A path carries `WAVELENGTHS` wavelengths evenly spaced over the visible
+//! range from a uniformly sampled hero wavelength. RGB reflectances and
+//! emitters are uplifted to spectra with the basis spectra of Smits 1999,
+//! emitters under the D65 illuminant so that white light stays white. A
+//! refraction that depends on the wavelength keeps only the hero
+//! wavelength. Emitters given by a spectral power distribution, `Spd`, use
+//! it directly and its sRGB color in RGB mode.
+
+use Color;
+use color::{self, Xyz};
+use math::Real;
+use std::fmt;
+use std::ops::{Add, AddAssign, Mul};
+
+pub const LAMBDA_MIN: Real = 380.0;
+pub const LAMBDA_MAX: Real = 780.0;
+/// Wavelengths traced along a path.
+pub const WAVELENGTHS: usize = 4;
+
+/// Integral of the CIE `y` matching function over the sampled range.
+const CIE_Y_INTEGRAL: Real = 106.919_73;
+/// Luminance of the tabulated D65 spectrum.
+const D65_Y: Real = 98.852_05;
+
+/// Values of a spectrum at the wavelengths of a path.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Spectrum {
+    pub values: [f32; WAVELENGTHS],
+}
+
+impl Spectrum {
+    pub fn new(value: f32) -> Spectrum {
+        Spectrum { values: [value; WAVELENGTHS] }
+    }
+
+    fn map<F: Fn(usize) -> f32>(f: F) -> Spectrum {
+        let mut values = [0.0; WAVELENGTHS];
+        for (i, v) in values.iter_mut().enumerate() {
+            *v = f(i);
+        }
+        Spectrum { values }
+    }
+}
+
+impl Add for Spectrum {
+    type Output = Spectrum;
+
+    fn add(self, other: Spectrum) -> Spectrum {
+        Spectrum::map(|i| self.values[i] + other.values[i])
+    }
+}
+
+impl AddAssign for Spectrum {
+    fn add_assign(&mut self, other: Spectrum) {
+        *self = *self + other;
+    }
+}
+
+impl Mul for Spectrum {
+    type Output = Spectrum;
+
+    fn mul(self, other: Spectrum) -> Spectrum {
+        Spectrum::map(|i| self.values[i] * other.values[i])
+    }
+}
+
+impl Mul<f32> for Spectrum {
+    type Output = Spectrum;
+
+    fn mul(self, other: f32) -> Spectrum {
+        Spectrum::map(|i| self.values[i] * other)
+    }
+}
+
+/// Wavelengths in nm traced along a path, the first is the hero.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Wavelengths {
+    lambda: [Real; WAVELENGTHS],
+    /// Number of wavelengths still traced.
+    count: usize,
+}
+
+impl Wavelengths {
+    /// Hero wavelength at `u` in `[0, 1)` of the range, the others rotated
+    /// from it.
+    pub fn sample(u: Real) -> Wavelengths {
+        let mut lambda = [0.0; WAVELENGTHS];
+        for (i, l) in lambda.iter_mut().enumerate() {
+            let t = u + i as Real / WAVELENGTHS as Real;
+            *l = LAMBDA_MIN + (t - t.floor()) * (LAMBDA_MAX - LAMBDA_MIN);
+        }
+        Wavelengths {
+            lambda,
+            count: WAVELENGTHS,
+        }
+    }
+
+    pub fn hero(&self) -> Real {
+        self.lambda[0]
+    }
+
+    /// Drops all but the hero wavelength, after a choice only valid for it.
+    pub fn terminate_secondary(&mut self) {
+        self.count = 1;
+    }
+
+    pub fn reflectance(&self, c: &Color) -> Spectrum {
+        Spectrum::map(|i| reflectance(c, self.lambda[i]))
+    }
+
+    pub fn illuminant(&self, c: &Color) -> Spectrum {
+        Spectrum::map(|i| illuminant(c, self.lambda[i]))
+    }
+
+    /// Emitted radiance `le` of an emitter with the distribution `spd`, or
+    /// uplifted from the color without one.
+    pub fn emission(&self, le: &Color, spd: Option<&Spd>) -> Spectrum {
+        match spd {
+            // `le` is the color of the distribution scaled by its luminance
+            Some(spd) => {
+                let lum = color::luminance(le);
+                Spectrum::map(|i| spd.at(self.lambda[i]) * lum)
+            }
+            None => self.illuminant(le),
+        }
+    }
+
+    /// CIE XYZ estimated from the values of a spectrum at the wavelengths.
+    pub fn to_xyz(&self, s: &Spectrum) -> Xyz {
+        let pdf = 1.0 / (LAMBDA_MAX - LAMBDA_MIN);
+        let mut sum = Xyz::<f64>::from(0.0);
+        for i in 0..self.count {
+            sum = sum + color::cie_1931(self.lambda[i]) * (s.values[i] as Real / pdf);
+        }
+        Xyz::from(sum * (1.0 / (self.count as Real * CIE_Y_INTEGRAL)))
+    }
+
+    pub fn to_rgb(&self, s: &Spectrum) -> Color {
+        self.to_xyz(s).to_linear_srgb()
+    }
+}
+
+/// Samples of a tabulated `Spd`, every 5 nm over the range.
+const SPD_SAMPLES: usize = 81;
+
+#[derive(Copy)]
+enum Shape {
+    /// Temperature in K.
+    Blackbody(Real),
+    Tabulated([f32; SPD_SAMPLES]),
+}
+
+/// Spectral power distribution of an emitter, scaled to unit luminance.
+#[derive(Copy)]
+pub struct Spd {
+    shape: Shape,
+    scale: Real,
+}
+
+// arrays longer than 32 are neither `Clone` nor `Debug`
+impl Clone for Shape {
+    fn clone(&self) -> Shape {
+        *self
+    }
+}
+
+impl Clone for Spd {
+    fn clone(&self) -> Spd {
+        *self
+    }
+}
+
+impl fmt::Debug for Spd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.shape {
+            Shape::Blackbody(t) => write!(f, "Spd::Blackbody({}K)", t),
+            Shape::Tabulated(_) => write!(f, "Spd::Tabulated"),
+        }
+    }
+}
+
+impl Spd {
+    /// Planck's law for a black body at `kelvin`.
+    pub fn blackbody(kelvin: Real) -> Spd {
+        Spd::normalized(Shape::Blackbody(kelvin))
+    }
+
+    /// Distribution interpolated from `(wavelength in nm, power)` pairs
+    /// sorted by wavelength, constant beyond the first and the last.
+    pub fn tabulated(samples: &[(Real, Real)]) -> Spd {
+        assert!(!samples.is_empty(), "empty spectral power distribution");
+        let mut table = [0.0; SPD_SAMPLES];
+        for (i, v) in table.iter_mut().enumerate() {
+            let lambda = LAMBDA_MIN + i as Real * (LAMBDA_MAX - LAMBDA_MIN) /
+                (SPD_SAMPLES - 1) as Real;
+            let ix = samples.iter().position(|s| s.0 > lambda).unwrap_or(samples.len());
+            let p = if ix == 0 {
+                samples[0].1
+            } else if ix == samples.len() {
+                samples[ix - 1].1
+            } else {
+                let (l0, p0) = samples[ix - 1];
+                let (l1, p1) = samples[ix];
+                p0 + (p1 - p0) * (lambda - l0) / (l1 - l0)
+            };
+            *v = p as f32;
+        }
+        Spd::normalized(Shape::Tabulated(table))
+    }
+
+    fn normalized(shape: Shape) -> Spd {
+        let mut spd = Spd { shape, scale: 1.0 };
+        let y = spd.to_xyz().y as Real;
+        spd.scale = if y > 0.0 { 1.0 / y } else { 0.0 };
+        spd
+    }
+
+    fn power(&self, lambda: Real) -> Real {
+        match self.shape {
+            Shape::Blackbody(t) => planck(lambda, t),
+            Shape::Tabulated(ref table) => {
+                lerp_table(table, LAMBDA_MIN, LAMBDA_MAX, lambda) as Real
+            }
+        }
+    }
+
+    /// Power at `lambda` nm.
+    pub fn at(&self, lambda: Real) -> f32 {
+        (self.power(lambda) * self.scale) as f32
+    }
+
+    /// CIE XYZ, `y` is 1.
+    pub fn to_xyz(&self) -> Xyz {
+        const STEPS: usize = 400;
+        let dl = (LAMBDA_MAX - LAMBDA_MIN) / STEPS as Real;
+        let mut sum = Xyz::<f64>::from(0.0);
+        for i in 0..STEPS {
+            let lambda = LAMBDA_MIN + (i as Real + 0.5) * dl;
+            sum = sum + color::cie_1931(lambda) * (self.power(lambda) * self.scale * dl);
+        }
+        Xyz::from(sum * (1.0 / CIE_Y_INTEGRAL))
+    }
+
+    /// Linear sRGB of unit luminance, clamped to the gamut.
+    pub fn to_color(&self) -> Color {
+        let c = self.to_xyz().to_linear_srgb();
+        let c = Color::new(c.r.max(0.0), c.g.max(0.0), c.b.max(0.0));
+        let lum = color::luminance(&c);
+        if lum > 0.0 {
+            c * (1.0 / lum)
+        } else {
+            c
+        }
+    }
+
+    /// RGB radiance of luminance `luminance`.
+    pub fn radiance(&self, luminance: f32) -> Color {
+        self.to_color() * luminance
+    }
+}
+
+/// Spectral radiance of a black body at `kelvin` for `lambda` nm, in
+/// W / (sr m² nm).
+pub fn planck(lambda: Real, kelvin: Real) -> Real {
+    const H: Real = 6.626_070_15e-34;
+    const C: Real = 2.997_924_58e8;
+    const K: Real = 1.380_649e-23;
+    let l = lambda * 1e-9;
+    let b = 2.0 * H * C * C / (l.powi(5) * ((H * C / (l * K * kelvin)).exp() - 1.0));
+    b * 1e-9
+}
+
+/// Basis spectra of Smits 1999 over 380 to 720 nm.
+const SMITS_WHITE: [f32; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000
+];
+const SMITS_CYAN: [f32; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000
+];
+const SMITS_MAGENTA: [f32; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959
+];
+const SMITS_YELLOW: [f32; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840
+];
+const SMITS_RED: [f32; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149
+];
+const SMITS_GREEN: [f32; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025
+];
+const SMITS_BLUE: [f32; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496
+];
+
+/// CIE D65 relative spectral power from 380 to 780 nm in 10 nm steps.
+const D65: [f32; 41] = [
+    49.9755, 54.6482, 82.7549, 91.486, 93.4318, 86.6823, 104.865, 117.008, 117.812, 114.861,
+    115.923, 108.811, 109.354, 107.802, 104.79, 107.689, 104.405, 104.046, 100.0, 96.3342,
+    95.788, 88.6856, 90.0062, 89.5991, 87.6987, 83.2886, 83.6992, 80.0268, 80.2146, 82.2778,
+    78.2842, 69.7213, 71.6091, 74.349, 61.604, 69.8856, 75.087, 63.5927, 46.4182, 66.8054,
+    63.3828,
+];
+
+/// Linear interpolation of `table` spanning `min` to `max` nm, constant
+/// outside.
+fn lerp_table(table: &[f32], min: Real, max: Real, lambda: Real) -> f32 {
+    let t = (lambda - min) / (max - min) * (table.len() - 1) as Real;
+    if t <= 0.0 {
+        return table[0];
+    }
+    let i = t as usize;
+    if i + 1 >= table.len() {
+        return table[table.len() - 1];
+    }
+    let f = (t - i as Real) as f32;
+    table[i] * (1.0 - f) + table[i + 1] * f
+}
+
+/// Smooth reflectance spectrum of `c` at `lambda` nm.
+pub fn reflectance(c: &Color, lambda: Real) -> f32 {
+    let basis = |table: &[f32]| lerp_table(table, 380.0, 720.0, lambda);
+    let (r, g, b) = (c.r, c.g, c.b);
+    if r <= g && r <= b {
+        let v = r * basis(&SMITS_WHITE);
+        if g <= b {
+            v + (g - r) * basis(&SMITS_CYAN) + (b - g) * basis(&SMITS_BLUE)
+        } else {
+            v + (b - r) * basis(&SMITS_CYAN) + (g - b) * basis(&SMITS_GREEN)
+        }
+    } else if g <= r && g <= b {
+        let v = g * basis(&SMITS_WHITE);
+        if r <= b {
+            v + (r - g) * basis(&SMITS_MAGENTA) + (b - r) * basis(&SMITS_BLUE)
+        } else {
+            v + (b - g) * basis(&SMITS_MAGENTA) + (r - b) * basis(&SMITS_RED)
+        }
+    } else {
+        let v = b * basis(&SMITS_WHITE);
+        if r <= g {
+            v + (r - b) * basis(&SMITS_YELLOW) + (g - r) * basis(&SMITS_GREEN)
+        } else {
+            v + (g - b) * basis(&SMITS_YELLOW) + (r - g) * basis(&SMITS_RED)
+        }
+    }
+}
+
+/// Emission spectrum of `c` at `lambda` nm, the reflectance of `c` lit by
+/// D65 of unit luminance.
+pub fn illuminant(c: &Color, lambda: Real) -> f32 {
+    reflectance(c, lambda) * d65(lambda)
+}
+
+/// D65 scaled to unit luminance.
+pub fn d65(lambda: Real) -> f32 {
+    lerp_table(&D65, 380.0, 780.0, lambda) / D65_Y as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// XYZ of `f` integrated with the midpoint rule.
+    fn integrate<F: Fn(Real) -> f32>(f: F) -> Xyz {
+        const STEPS: usize = 4000;
+        let dl = (LAMBDA_MAX - LAMBDA_MIN) / STEPS as Real;
+        let mut sum = Xyz::<f64>::from(0.0);
+        for i in 0..STEPS {
+            let l = LAMBDA_MIN + (i as Real + 0.5) * dl;
+            sum = sum + color::cie_1931(l) * (f(l) as Real * dl);
+        }
+        Xyz::from(sum * (1.0 / CIE_Y_INTEGRAL))
+    }
+
+    #[test]
+    fn white_light_is_white() {
+        let xyz = integrate(|l| illuminant(&Color::from(1.0), l));
+        let c = xyz.to_linear_srgb();
+        assert!((xyz.y - 1.0).abs() < 1e-3);
+        for v in &[c.r, c.g, c.b] {
+            assert!((v - 1.0).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn uplift_round_trip() {
+        for c in &[
+            Color::new(0.8, 0.5, 0.2),
+            Color::new(0.2, 0.4, 0.9),
+            Color::new(0.1, 0.7, 0.3),
+        ] {
+            let rgb = integrate(|l| reflectance(c, l) * d65(l)).to_linear_srgb();
+            assert!((rgb.r - c.r).abs() < 0.06);
+            assert!((rgb.g - c.g).abs() < 0.06);
+            assert!((rgb.b - c.b).abs() < 0.06);
+        }
+    }
+
+    #[test]
+    fn hero_wavelengths_are_spread() {
+        let wl = Wavelengths::sample(0.9);
+        assert!((wl.hero() - 740.0).abs() < 1e-9);
+        let mut sorted = wl.lambda;
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for w in sorted.windows(2) {
+            assert!((w[1] - w[0] - 100.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn blackbody_colors() {
+        let warm = Spd::blackbody(3200.0).to_color();
+        assert!((color::luminance(&warm) - 1.0).abs() < 1e-4);
+        assert!(warm.r > warm.g && warm.g > warm.b);
+
+        let c = Spd::blackbody(6504.0).to_color();
+        for v in &[c.r, c.g, c.b] {
+            assert!((v - 1.0).abs() < 0.06);
+        }
+        let y = integrate(|l| Spd::blackbody(2700.0).at(l)).y;
+        assert!((y - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn tabulated_d65_is_white() {
+        let samples: Vec<(Real, Real)> = D65.iter()
+            .enumerate()
+            .map(|(i, p)| (380.0 + 10.0 * i as Real, *p as Real))
+            .collect();
+        let c = Spd::tabulated(&samples).to_color();
+        for v in &[c.r, c.g, c.b] {
+            assert!((v - 1.0).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn srgb_xyz_round_trip() {
+        let c = Color::new(0.3, 0.6, 0.9);
+        let back = Xyz::from_linear_srgb(&c).to_linear_srgb();
+        assert!((back.r - c.r).abs() < 1e-4);
+        assert!((back.g - c.g).abs() < 1e-4);
+        assert!((back.b - c.b).abs() < 1e-4);
+    }
+}
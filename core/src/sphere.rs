@@ -2,7 +2,8 @@ use {Bsdf, Surface, SurfacePoint};
 use aabb::{Aabb3, HasBounds};
 use bsdf::BsdfRef;
 use color::Color;
-use math::{self, Norm, Point3f, Ray3f, Real, Vector3f};
+use math::{self, Norm, Point2f, Point3f, Ray3f, Real, Vector3f};
+use motion::Motion;
 use std::borrow::{Borrow, BorrowMut};
 use std::f64::consts::PI;
 use std::sync::Arc;
@@ -12,6 +13,8 @@ pub struct Sphere {
     pub position: Point3f,
     pub radius: Real,
     pub bsdf: Arc<Bsdf>,
+    /// Moves `position` during the shutter interval.
+    pub motion: Option<Motion>,
 }
 
 impl Sphere {
@@ -20,6 +23,19 @@ impl Sphere {
             position: position,
             radius: radius,
             bsdf: mat,
+            motion: None,
+        }
+    }
+
+    pub fn with_motion(mut self, motion: Motion) -> Self {
+        self.motion = Some(motion);
+        self
+    }
+
+    pub fn center_at(&self, time: Real) -> Point3f {
+        match self.motion {
+            Some(ref m) => m.at(time) * self.position,
+            None => self.position,
         }
     }
 
@@ -27,8 +43,8 @@ impl Sphere {
         BsdfRef::Ref(self.bsdf.as_ref())
     }
 
-    fn normal_to(&self, point: &Point3f) -> Vector3f {
-        (*point - self.position).normalize()
+    fn normal_to(&self, point: &Point3f, time: Real) -> Vector3f {
+        (*point - self.center_at(time)).normalize()
     }
 }
 
@@ -39,9 +55,10 @@ impl Surface for Sphere {
     }
 
     fn intersection(&self, ray: &Ray3f) -> Option<(Real, SurfacePoint)> {
-        if let Some(t) = math::intersection_sphere(&self.position, self.radius, ray) {
+        let center = self.center_at(ray.time);
+        if let Some(t) = math::intersection_sphere(&center, self.radius, ray) {
             let pos = ray.origin + ray.dir * t;
-            let norm = self.normal_to(&pos);
+            let norm = (pos - center).normalize();
             Some((
                 t,
                 SurfacePoint {
@@ -71,16 +88,45 @@ impl Surface for Sphere {
     }
 
     #[inline]
-    fn normal_at(&self, pos: &Point3f) -> Vector3f {
-        self.normal_to(pos)
+    fn normal_at(&self, pos: &Point3f, time: Real) -> Vector3f {
+        self.normal_to(pos, time)
+    }
+
+    fn uv(&self, pos: &Point3f, time: Real) -> Option<Point2f> {
+        let n = self.normal_to(pos, time);
+        let u = 0.5 + n.z.atan2(n.x) / (2.0 * PI as Real);
+        let v = 0.5 - n.y.max(-1.0).min(1.0).asin() / (PI as Real);
+        Some(Point2f::new(u, v))
+    }
+
+    fn material_key(&self) -> usize {
+        self.bsdf.as_ref() as *const _ as *const u8 as usize
     }
 
-    fn sample_surface_p(&self, view_point: (&Point3f, &Vector3f)) -> (SurfacePoint, Real) {
-        let view_dir = (*view_point.0 - self.position).normalize();
+    fn sample_surface_uniform(&self, time: Real) -> (SurfacePoint, Real) {
+        let normal = math::sph_uniform_sampling();
+        (
+            SurfacePoint {
+                position: self.center_at(time) + normal * self.radius,
+                normal: normal,
+                bsdf: self.bsdf(),
+                surface: self,
+            },
+            1.0 / self.area(),
+        )
+    }
+
+    fn sample_surface_p(
+        &self,
+        view_point: (&Point3f, &Vector3f),
+        time: Real,
+    ) -> (SurfacePoint, Real) {
+        let center = self.center_at(time);
+        let view_dir = (*view_point.0 - center).normalize();
         let normal = math::hs_uniform_sampling(&view_dir);
         // let normal = math::sph_uniform_sampling();
         let pdf = 2.0 / self.area();
-        let pos = self.position + (normal * self.radius);
+        let pos = center + (normal * self.radius);
 
         (
             SurfacePoint {
@@ -94,7 +140,7 @@ impl Surface for Sphere {
     }
 
     #[inline]
-    fn pdf_p(&self, _: (&Point3f, &Vector3f), _: (&Point3f, &Vector3f)) -> Real {
+    fn pdf_p(&self, _: (&Point3f, &Vector3f), _: (&Point3f, &Vector3f), _: Real) -> Real {
         2.0 / self.area()
     }
 
@@ -127,7 +173,11 @@ impl Surface for Sphere {
 impl HasBounds for Sphere {
     fn aabb(&self) -> Aabb3 {
         let dpos = Vector3f::new(self.radius, self.radius, self.radius);
-        Aabb3::new(self.position - dpos, self.position + dpos)
+        let aabb = Aabb3::new(self.position - dpos, self.position + dpos);
+        match self.motion {
+            Some(ref m) => m.bounds(&aabb),
+            None => aabb,
+        }
     }
 }
 
@@ -1,12 +1,16 @@
 use super::{Color, SurfacePoint};
+pub use camera::Lens;
+use camera::{Perspective, Projection};
+use motion::Motion;
 pub use aabb::HasBounds;
 pub use bsdf::Bsdf;
-use math::{Dot, Matrix4f, Norm, Point3f, Ray3f, Real, Vector3f};
+use math::{self, Dot, Matrix4f, Norm, Point2f, Point3f, Ray3f, Real, Vector3f};
 pub use polygon::{Material, Vertex};
 
 pub use renderer::Renderer;
 pub use scenehandler::SceneHandler;
 pub use texture::TexView;
+use std::sync::Arc;
 
 pub trait RenderCamera: Sync {
     fn view_matrix(&self) -> Matrix4f;
@@ -24,6 +28,27 @@ pub trait RenderCamera: Sync {
     fn up_vec(&self) -> Vector3f;
     fn forward_vec(&self) -> Vector3f;
     fn right_vec(&self) -> Vector3f;
+
+    /// Thin lens for depth of field, `None` for a pinhole camera.
+    fn lens(&self) -> Option<Lens> {
+        None
+    }
+
+    /// Interval the shutter is open, primary rays get a random time from it.
+    fn shutter(&self) -> (Real, Real) {
+        (0.0, 0.0)
+    }
+
+    /// Camera-to-world transform over the shutter interval, `None` for a
+    /// camera at rest.
+    fn motion(&self) -> Option<Motion> {
+        None
+    }
+
+    /// Projection used to generate the primary rays.
+    fn projection(&self) -> Arc<Projection> {
+        Arc::new(Perspective::new(self.fovy()).with_lens(self.lens()))
+    }
 }
 
 pub trait Surface: Sync {
@@ -36,10 +61,43 @@ pub trait Surface: Sync {
     fn total_radiance(&self) -> Option<Color>;
 
     fn area(&self) -> Real;
-    fn normal_at(&self, pos: &Point3f) -> Vector3f;
+    /// Normal at `pos` with the surface placed as at `time`.
+    fn normal_at(&self, pos: &Point3f, time: Real) -> Vector3f;
+
+    /// Texture coordinates at `pos` at `time`, `None` if the surface has
+    /// none.
+    fn uv(&self, _: &Point3f, _: Real) -> Option<Point2f> {
+        None
+    }
+
+    /// Barycentric coordinates of `pos` on a triangle at `time`, `None` for
+    /// other surfaces.
+    fn barycentric(&self, _: &Point3f, _: Real) -> Option<(Real, Real, Real)> {
+        None
+    }
+
+    /// Key shared by the surfaces with the same material, the address of
+    /// the material. `SceneIds` numbers the materials by it.
+    fn material_key(&self) -> usize {
+        0
+    }
 
-    fn sample_surface_p(&self, view_point: (&Point3f, &Vector3f)) -> (SurfacePoint, Real) {
-        let (sp, pdf_d) = self.sample_surface_d(view_point);
+    /// Point distributed uniformly over the surface and its area pdf, used
+    /// to start paths at emitters. The default assumes `sample_surface_p`
+    /// does not depend on the view point.
+    fn sample_surface_uniform(&self, time: Real) -> (SurfacePoint, Real) {
+        let (sp, _) = self.sample_surface_p((&math::origin(), &math::zero()), time);
+        (sp, 1.0 / self.area())
+    }
+
+    /// Point on the surface as placed at `time`, the samplers and pdfs below
+    /// take the time of the ray they are used with.
+    fn sample_surface_p(
+        &self,
+        view_point: (&Point3f, &Vector3f),
+        time: Real,
+    ) -> (SurfacePoint, Real) {
+        let (sp, pdf_d) = self.sample_surface_d(view_point, time);
         let view_dir = *view_point.0 - sp.position;
         let r2 = view_dir.norm_squared();
         let cos_theta_l = sp.normal.dot(&view_dir.normalize());
@@ -47,8 +105,12 @@ pub trait Surface: Sync {
         (sp, pdf_p)
     }
 
-    fn sample_surface_d(&self, view_point: (&Point3f, &Vector3f)) -> (SurfacePoint, Real) {
-        let (sp, pdf_p) = self.sample_surface_p(view_point);
+    fn sample_surface_d(
+        &self,
+        view_point: (&Point3f, &Vector3f),
+        time: Real,
+    ) -> (SurfacePoint, Real) {
+        let (sp, pdf_p) = self.sample_surface_p(view_point, time);
         let view_dir = *view_point.0 - sp.position;
         let r2 = view_dir.norm_squared();
         let cos_theta_l = sp.normal.dot(&view_dir.normalize());
@@ -56,8 +118,12 @@ pub trait Surface: Sync {
         (sp, pdf_d)
     }
 
-    fn sample_surface_d_proj(&self, view_point: (&Point3f, &Vector3f)) -> (SurfacePoint, Real) {
-        let (sp, pdf_d) = self.sample_surface_d(view_point);
+    fn sample_surface_d_proj(
+        &self,
+        view_point: (&Point3f, &Vector3f),
+        time: Real,
+    ) -> (SurfacePoint, Real) {
+        let (sp, pdf_d) = self.sample_surface_d(view_point, time);
         let view_dir_inv = (sp.position - *view_point.0).normalize();
         let cos_theta = view_point.1.dot(&view_dir_inv);
         let pdf_d_proj = pdf_d / cos_theta;
@@ -68,8 +134,9 @@ pub trait Surface: Sync {
         &self,
         point_at_surface: (&Point3f, &Vector3f),
         view_point: (&Point3f, &Vector3f),
+        time: Real,
     ) -> Real {
-        let pdf_d = self.pdf_d(point_at_surface, view_point);
+        let pdf_d = self.pdf_d(point_at_surface, view_point, time);
         let view_dir = *view_point.0 - *point_at_surface.0;
         let r2 = view_dir.norm_squared();
         let cos_theta_l = point_at_surface.1.dot(&view_dir.normalize());
@@ -80,8 +147,9 @@ pub trait Surface: Sync {
         &self,
         point_at_surface: (&Point3f, &Vector3f),
         view_point: (&Point3f, &Vector3f),
+        time: Real,
     ) -> Real {
-        let pdf_p = self.pdf_p(point_at_surface, view_point);
+        let pdf_p = self.pdf_p(point_at_surface, view_point, time);
         let view_dir = *view_point.0 - *point_at_surface.0;
         let r2 = view_dir.norm_squared();
         let cos_theta_l = point_at_surface.1.dot(&view_dir.normalize());
@@ -92,8 +160,9 @@ pub trait Surface: Sync {
         &self,
         point_at_surface: (&Point3f, &Vector3f),
         view_point: (&Point3f, &Vector3f),
+        time: Real,
     ) -> Real {
-        let pdf_d = self.pdf_d(point_at_surface, view_point);
+        let pdf_d = self.pdf_d(point_at_surface, view_point, time);
         let view_dir_inv = (*point_at_surface.0 - *view_point.0).normalize();
         let cos_theta = view_point.1.dot(&view_dir_inv);
         pdf_d / cos_theta // pdf_d_proj
@@ -1,7 +1,9 @@
 pub mod consts;
+pub mod rng;
 
 use color::Rgb;
 use math::Real;
+use std::hash::Hasher;
 
 pub fn clamp<T: Copy + PartialOrd>(val: T, left_bound: T, right_bound: T) -> T {
     if val < left_bound {
@@ -21,3 +23,27 @@ pub fn normal_dx_to_ogl(n: &Rgb<Real>) -> Rgb<Real> {
     assert!(res.g >= 0.0 && res.g <= 1.0);
     res
 }
+
+/// 64-bit FNV-1a, unlike `DefaultHasher` its output is stable between
+/// compiler versions so it can be stored in files.
+#[derive(Copy, Clone, Debug)]
+pub struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        FnvHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Uniform random numbers used for sampling.
+//!
+//! Every thread draws from `rand::thread_rng` unless a `SampleSource` is
+//! installed with `with_source`, which lets a renderer replace all the
+//! random decisions of a path, e.g. by a primary sample vector mutated by a
+//! Markov chain or by a `SeededSource` for a reproducible render.
+
+use math::Real;
+use rand::{self, Rng, SeedableRng, XorShiftRng};
+use std::cell::Cell;
+
+pub trait SampleSource {
+    /// Next sample in `[0, 1)`.
+    fn next_sample(&mut self) -> Real;
+}
+
+thread_local!(static SOURCE: Cell<Option<*mut SampleSource>> = Cell::new(None));
+
+/// Uniform sample in `[0, 1)` from the source installed on this thread.
+pub fn uniform() -> Real {
+    match SOURCE.with(|s| s.get()) {
+        // the pointer is valid while `with_source` runs, which is the only
+        // place setting it
+        Some(source) => unsafe { (*source).next_sample() },
+        None => rand::random::<Real>(),
+    }
+}
+
+/// Runs `f` with every `uniform` call on this thread taken from `source`.
+pub fn with_source<R, F>(source: &mut (SampleSource + 'static), f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    struct Restore(Option<*mut SampleSource>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            SOURCE.with(|s| s.set(self.0));
+        }
+    }
+
+    let _restore = Restore(SOURCE.with(|s| s.replace(Some(source as *mut SampleSource))));
+    f()
+}
+
+/// Reproducible samples of the stream `stream` of `seed`.
+pub struct SeededSource(XorShiftRng);
+
+impl SeededSource {
+    pub fn new(seed: u64, stream: u64) -> SeededSource {
+        let s = seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        SeededSource(XorShiftRng::from_seed([s as u32, (s >> 32) as u32, 0x2545_f491, 0x9e37_79b9]))
+    }
+}
+
+impl SampleSource for SeededSource {
+    fn next_sample(&mut self) -> Real {
+        self.0.gen::<Real>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Counter(Real);
+
+    impl SampleSource for Counter {
+        fn next_sample(&mut self) -> Real {
+            self.0 += 0.25;
+            self.0
+        }
+    }
+
+    #[test]
+    fn source_is_restored() {
+        let mut counter = Counter(0.0);
+        let (a, b) = with_source(&mut counter, || (uniform(), uniform()));
+        assert_eq!((a, b), (0.25, 0.5));
+        assert_eq!(counter.0, 0.5);
+
+        let mut inner = Counter(0.5);
+        let c = with_source(&mut counter, || {
+            with_source(&mut inner, uniform);
+            uniform()
+        });
+        assert_eq!(c, 0.75);
+        assert_eq!(inner.0, 0.75);
+    }
+
+    #[test]
+    fn seeded_streams_repeat() {
+        let draw = |seed, stream| {
+            let mut source = SeededSource::new(seed, stream);
+            with_source(&mut source, || (uniform(), uniform()))
+        };
+        assert_eq!(draw(7, 3), draw(7, 3));
+        assert!(draw(7, 3) != draw(7, 4));
+        assert!(draw(7, 3) != draw(8, 3));
+    }
+}
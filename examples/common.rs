@@ -133,6 +133,20 @@ impl ExampleApp {
                                         ex_app.save_img(&res_img, None);
                                     }
 
+                                    VirtualKeyCode::V if pressed => {
+                                        let mode = ex_app.dbg_rdr.mode().next();
+                                        println!("debug view: {:?}", mode);
+                                        ex_app.dbg_rdr.set_mode(mode);
+                                    }
+
+                                    VirtualKeyCode::G if pressed => {
+                                        let wireframe = match ex_app.dbg_rdr.wireframe() {
+                                            Some(_) => None,
+                                            None => Some((color::YELLOW, 0.02)),
+                                        };
+                                        ex_app.dbg_rdr.set_wireframe(wireframe);
+                                    }
+
                                     _ => (),
                                 }
                             }
@@ -217,6 +231,8 @@ impl ExampleApp {
         println!(" - SPACE - move up");
         println!(" - SHIFT - move down");
         println!(" - R     - toggle renderer");
+        println!(" - V     - next debug view");
+        println!(" - G     - toggle debug wireframe");
         println!(" - I     - save image");
         println!(" - ESC   - quit\n");
     }
@@ -152,14 +152,16 @@ impl AppState for Envmap {
     }
 
     fn create_scene<'s>(&'s self) -> Box<SceneHandler + 's> {
-        let pol_iter = self.envbox_polygons.iter().map(|r| r as &BoundedSurface);
-        let model_iter = self.model_polygons.iter().map(|r| r as &BoundedSurface);
-        let it = pol_iter.chain(model_iter);
+        let envbox: Vec<_> = self.envbox_polygons.iter().map(|r| r as &BoundedSurface).collect();
+        let model: Vec<_> = self.model_polygons.iter().map(|r| r as &BoundedSurface).collect();
         let kdtree_setup = KdTreeSetup::new(32, 16, Sah::new(1.0, 1.0));
 
         print!("building kd-tree ...");
         let _ = std::io::stdout().flush();
-        let kdtree = box KdTreeS::<BoundedSurface, LinearSampler>::new(it, kdtree_setup);
+        let kdtree = box KdTreeS::<BoundedSurface, LinearSampler>::from_objects(
+            vec![envbox, model],
+            kdtree_setup,
+        );
         println!("done! (depth: {})", kdtree.depth());
         kdtree
     }
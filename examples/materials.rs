@@ -153,9 +153,7 @@ impl AppState for Materials {
 
         let room_pols = room_mesh.into_polygons();
 
-        for p in room_pols {
-            scene.add_shape((box p) as Box<Surface>);
-        }
+        scene.add_object(room_pols.into_iter().map(|p| (box p) as Box<Surface>));
 
         {
             let scene_ref = &mut scene;
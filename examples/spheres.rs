@@ -120,9 +120,7 @@ impl AppState for Spheres {
         use rtcore::scenehandler::{LinearSampler};
 
         let mut scene = ShapeListBuilder::<_, LinearSampler>::new();
-        for s in &self.room_polygons {
-            scene.add_shape(s as &Surface);
-        }
+        scene.add_object(self.room_polygons.iter().map(|s| s as &Surface));
         for s in &self.spheres {
             scene.add_shape(s as &Surface);
         }
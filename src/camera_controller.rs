@@ -18,7 +18,7 @@ pub trait CameraController {
     );
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct FPSCameraController {
     cam: FPSCamera,
     mouse_sens: Real,
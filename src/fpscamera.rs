@@ -1,11 +1,14 @@
 use rtcore::RenderCamera;
+use rtcore::camera::{Lens, Perspective, Projection};
 use rtcore::math;
 use rtcore::math::{Inverse, Rotate, Rotation, ToHomogeneous};
 use rtcore::math::{Isometry3, Matrix4, PerspectiveMatrix3, Rotation3, Vector3};
 use rtcore::math::{Point3f, Real, Vector3f};
+use rtcore::motion::Motion;
 use rtcore::utils::consts;
+use std::sync::Arc;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct FPSCamera {
     width: u32,
     height: u32,
@@ -13,6 +16,10 @@ pub struct FPSCamera {
     znear: Real,
     zfar: Real,
     trfm: Isometry3<Real>,
+    lens: Option<Lens>,
+    projection: Option<Arc<Projection>>,
+    shutter: (Real, Real),
+    motion: Option<Motion>,
 }
 
 impl FPSCamera {
@@ -24,6 +31,10 @@ impl FPSCamera {
             znear: znear,
             zfar: zfar,
             trfm: math::one(),
+            lens: None,
+            projection: None,
+            shutter: (0.0, 0.0),
+            motion: None,
         }
     }
 
@@ -32,6 +43,30 @@ impl FPSCamera {
             Rotation3::look_at_lh(&(*target - *self.pos()), &Vector3f::from(&consts::UP_VEC));
     }
 
+    /// Enables depth of field, `None` switches back to a pinhole camera.
+    pub fn set_lens(&mut self, lens: Option<Lens>) -> &mut FPSCamera {
+        self.lens = lens;
+        self
+    }
+
+    /// Replaces the perspective projection, `None` restores it.
+    pub fn set_projection(&mut self, projection: Option<Arc<Projection>>) -> &mut FPSCamera {
+        self.projection = projection;
+        self
+    }
+
+    pub fn set_shutter(&mut self, open: Real, close: Real) -> &mut FPSCamera {
+        self.shutter = (open, close);
+        self
+    }
+
+    /// Moves the camera during the shutter interval, replacing its current
+    /// transform while rendering.
+    pub fn set_motion(&mut self, motion: Option<Motion>) -> &mut FPSCamera {
+        self.motion = motion;
+        self
+    }
+
     pub fn transform(&self) -> &Isometry3<Real> {
         &self.trfm
     }
@@ -131,4 +166,19 @@ impl RenderCamera for FPSCamera {
     fn right_vec(&self) -> Vector3f {
         self.right()
     }
+    fn lens(&self) -> Option<Lens> {
+        self.lens.clone()
+    }
+    fn shutter(&self) -> (Real, Real) {
+        self.shutter
+    }
+    fn motion(&self) -> Option<Motion> {
+        self.motion.clone()
+    }
+    fn projection(&self) -> Arc<Projection> {
+        match self.projection {
+            Some(ref p) => p.clone(),
+            None => Arc::new(Perspective::new(self.fovy).with_lens(self.lens())),
+        }
+    }
 }